@@ -0,0 +1,475 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Rectangle packing for atlas pages.
+//!
+//! Two packers are provided:
+//!
+//! * `BinTreeAllocator`, the simple binary tree packer described here:
+//!
+//!   <http://www.blackpawn.com/texts/lightmaps/default.html>
+//!
+//! * `ShelfAllocator`, which divides the page into horizontal shelves and packs objects of similar
+//!   height side by side. Space in the middle of a shelf is only reclaimed once everything on it
+//!   has been freed, but that happens often for glyphs, which tend to come and go together.
+
+use euclid::{Point2D, Rect, Size2D};
+use std::cmp;
+
+/// Shelf heights are rounded up to a multiple of this so that objects of slightly different
+/// heights can share shelves.
+const SHELF_HEIGHT_GRANULARITY: u32 = 8;
+
+/// Packs rectangles into a fixed-size area.
+pub trait Allocator {
+    /// Finds space for an object of the given size and returns its origin, or `None` if there is
+    /// no room.
+    fn allocate(&mut self, size: &Size2D<u32>) -> Option<Point2D<u32>>;
+
+    /// Frees the object previously allocated at `origin`.
+    fn free(&mut self, origin: &Point2D<u32>);
+
+    fn statistics(&self) -> AllocatorStatistics;
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AllocatorKind {
+    BinTree,
+    Shelf,
+}
+
+pub fn new_allocator(kind: AllocatorKind, size: &Size2D<u32>) -> Box<Allocator> {
+    match kind {
+        AllocatorKind::BinTree => Box::new(BinTreeAllocator::new(size)) as Box<Allocator>,
+        AllocatorKind::Shelf => Box::new(ShelfAllocator::new(size)) as Box<Allocator>,
+    }
+}
+
+/// How well an allocator is using its space.
+#[derive(Copy, Clone, Debug)]
+pub struct AllocatorStatistics {
+    pub allocation_count: usize,
+    /// The total area of all live objects, in pixels.
+    pub allocated_area: u64,
+    /// The total area managed by the allocator, in pixels.
+    pub total_area: u64,
+}
+
+impl AllocatorStatistics {
+    pub fn new() -> AllocatorStatistics {
+        AllocatorStatistics {
+            allocation_count: 0,
+            allocated_area: 0,
+            total_area: 0,
+        }
+    }
+
+    /// Returns the fraction of the area that is occupied by live objects.
+    pub fn occupancy(&self) -> f32 {
+        if self.total_area == 0 {
+            return 0.0
+        }
+        (self.allocated_area as f64 / self.total_area as f64) as f32
+    }
+
+    /// Combines the statistics of two allocators, e.g. for different pages of the same atlas.
+    pub fn add(&self, other: &AllocatorStatistics) -> AllocatorStatistics {
+        AllocatorStatistics {
+            allocation_count: self.allocation_count + other.allocation_count,
+            allocated_area: self.allocated_area + other.allocated_area,
+            total_area: self.total_area + other.total_area,
+        }
+    }
+}
+
+pub struct BinTreeAllocator {
+    root_bin: Bin,
+}
+
+impl BinTreeAllocator {
+    pub fn new(size: &Size2D<u32>) -> BinTreeAllocator {
+        BinTreeAllocator {
+            root_bin: Bin::new(&Rect::new(Point2D::new(0, 0), *size)),
+        }
+    }
+}
+
+impl Allocator for BinTreeAllocator {
+    fn allocate(&mut self, size: &Size2D<u32>) -> Option<Point2D<u32>> {
+        // Empty objects still take up a pixel, so that they get a bin of their own that `free()`
+        // can find by its origin.
+        self.root_bin.insert(&Size2D::new(cmp::max(size.width, 1), cmp::max(size.height, 1)))
+    }
+
+    fn free(&mut self, origin: &Point2D<u32>) {
+        self.root_bin.free(origin);
+    }
+
+    fn statistics(&self) -> AllocatorStatistics {
+        let mut statistics = AllocatorStatistics::new();
+        statistics.total_area = area(&self.root_bin.rect.size);
+        self.root_bin.accumulate_statistics(&mut statistics);
+        statistics
+    }
+}
+
+struct Bin {
+    children: Option<[Box<Bin>; 2]>,
+    rect: Rect<u32>,
+    full: bool,
+}
+
+impl Bin {
+    fn new(rect: &Rect<u32>) -> Bin {
+        Bin {
+            children: None,
+            rect: *rect,
+            full: false,
+        }
+    }
+
+    fn insert(&mut self, size: &Size2D<u32>) -> Option<Point2D<u32>> {
+        if let Some(ref mut children) = self.children {
+            let (left, right) = children.split_at_mut(1);
+            let (left, right) = (&mut left[0], &mut right[0]);
+            return left.insert(size).or_else(|| right.insert(size))
+        }
+
+        if self.full {
+            return None
+        }
+
+        match (self.rect.size.width.cmp(&size.width), self.rect.size.height.cmp(&size.height)) {
+            (cmp::Ordering::Less, _) | (_, cmp::Ordering::Less) => return None,
+            (cmp::Ordering::Equal, cmp::Ordering::Equal) => {
+                self.full = true;
+                return Some(self.rect.origin)
+            }
+            _ => {}
+        }
+
+        let left_child = Box::new(Bin::new(&Rect::new(self.rect.origin, *size)));
+
+        let extra_width = self.rect.size.width - size.width;
+        let extra_height = self.rect.size.height - size.height;
+        let right_child = if extra_width > extra_height {
+            Box::new(Bin::new(&Rect::new(Point2D::new(self.rect.origin.x + size.width,
+                                                      self.rect.origin.y),
+                                         Size2D::new(self.rect.size.width - size.width,
+                                                     self.rect.size.height))))
+        } else {
+            Box::new(Bin::new(&Rect::new(Point2D::new(self.rect.origin.x,
+                                                      self.rect.origin.y + size.height),
+                                         Size2D::new(self.rect.size.width,
+                                                     self.rect.size.height - size.height))))
+        };
+
+        self.children = Some([ left_child, right_child ]);
+        self.children.as_mut().unwrap()[0].insert(size)
+    }
+
+    /// Frees the object previously inserted at `origin`, merging empty bins back together.
+    /// Returns true if the object was found.
+    fn free(&mut self, origin: &Point2D<u32>) -> bool {
+        if origin.x < self.rect.origin.x || origin.y < self.rect.origin.y ||
+                origin.x >= self.rect.max_x() || origin.y >= self.rect.max_y() {
+            return false
+        }
+
+        let found = match self.children {
+            None => {
+                if self.full && self.rect.origin == *origin {
+                    self.full = false;
+                    return true
+                }
+                return false
+            }
+            Some(ref mut children) => children.iter_mut().any(|child| child.free(origin)),
+        };
+
+        if found && self.children.as_ref().unwrap().iter().all(|child| child.is_empty()) {
+            self.children = None
+        }
+        found
+    }
+
+    fn is_empty(&self) -> bool {
+        self.children.is_none() && !self.full
+    }
+
+    /// Full leaves are exactly the size of the object they hold, so their areas add up to the
+    /// allocated area.
+    fn accumulate_statistics(&self, statistics: &mut AllocatorStatistics) {
+        match self.children {
+            None if self.full => {
+                statistics.allocation_count += 1;
+                statistics.allocated_area += area(&self.rect.size);
+            }
+            None => {}
+            Some(ref children) => {
+                for child in children.iter() {
+                    child.accumulate_statistics(statistics)
+                }
+            }
+        }
+    }
+}
+
+pub struct ShelfAllocator {
+    size: Size2D<u32>,
+    /// Shelves from top to bottom. Together they always cover the whole height of the area;
+    /// empty shelves are free space that can be split to make new shelves.
+    shelves: Vec<Shelf>,
+}
+
+struct Shelf {
+    y: u32,
+    height: u32,
+    /// The x position where the next object on this shelf will go.
+    next_x: u32,
+    /// The x positions and sizes of the live objects on this shelf.
+    objects: Vec<(u32, Size2D<u32>)>,
+}
+
+impl Shelf {
+    fn new(y: u32, height: u32) -> Shelf {
+        Shelf {
+            y: y,
+            height: height,
+            next_x: 0,
+            objects: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+
+impl ShelfAllocator {
+    pub fn new(size: &Size2D<u32>) -> ShelfAllocator {
+        ShelfAllocator {
+            size: *size,
+            shelves: vec![Shelf::new(0, size.height)],
+        }
+    }
+
+    /// Returns the index of the shelf that an object of the given size should go on, splitting a
+    /// new shelf off free space if necessary.
+    fn find_shelf(&mut self, size: &Size2D<u32>) -> Option<usize> {
+        let shelf_height = cmp::min(round_up(size.height, SHELF_HEIGHT_GRANULARITY),
+                                    self.size.height);
+
+        // Best of all is a shelf made for objects of this height.
+        for (index, shelf) in self.shelves.iter().enumerate() {
+            if !shelf.is_empty() && shelf.height == shelf_height &&
+                    shelf.next_x + size.width <= self.size.width {
+                return Some(index)
+            }
+        }
+
+        // Next best is to open a new shelf in the smallest free space that fits.
+        let mut best_free_shelf: Option<usize> = None;
+        for (index, shelf) in self.shelves.iter().enumerate() {
+            if !shelf.is_empty() || shelf.height < shelf_height {
+                continue
+            }
+            match best_free_shelf {
+                Some(best_index) if self.shelves[best_index].height <= shelf.height => {}
+                _ => best_free_shelf = Some(index),
+            }
+        }
+        if let Some(index) = best_free_shelf {
+            if self.shelves[index].height > shelf_height {
+                let remainder = Shelf::new(self.shelves[index].y + shelf_height,
+                                           self.shelves[index].height - shelf_height);
+                self.shelves[index].height = shelf_height;
+                self.shelves.insert(index + 1, remainder);
+            }
+            return Some(index)
+        }
+
+        // Failing that, waste some space on the shortest taller shelf with room left.
+        let mut best_shelf: Option<usize> = None;
+        for (index, shelf) in self.shelves.iter().enumerate() {
+            if shelf.is_empty() || shelf.height < size.height ||
+                    shelf.next_x + size.width > self.size.width {
+                continue
+            }
+            match best_shelf {
+                Some(best_index) if self.shelves[best_index].height <= shelf.height => {}
+                _ => best_shelf = Some(index),
+            }
+        }
+        best_shelf
+    }
+
+    /// Merges the empty shelf at `index` with any empty neighbors.
+    fn coalesce_free_shelves(&mut self, mut index: usize) {
+        if index + 1 < self.shelves.len() && self.shelves[index + 1].is_empty() {
+            let next = self.shelves.remove(index + 1);
+            self.shelves[index].height += next.height;
+        }
+        if index > 0 && self.shelves[index - 1].is_empty() {
+            let shelf = self.shelves.remove(index);
+            index -= 1;
+            self.shelves[index].height += shelf.height;
+        }
+    }
+}
+
+impl Allocator for ShelfAllocator {
+    fn allocate(&mut self, size: &Size2D<u32>) -> Option<Point2D<u32>> {
+        // Empty objects still take up a pixel, so that they get a shelf of their own height and
+        // an origin that no other object shares, which `free()` relies on to find them.
+        let size = &Size2D::new(cmp::max(size.width, 1), cmp::max(size.height, 1));
+        if size.width > self.size.width || size.height > self.size.height {
+            return None
+        }
+
+        let index = match self.find_shelf(size) {
+            None => return None,
+            Some(index) => index,
+        };
+        let shelf = &mut self.shelves[index];
+        let origin = Point2D::new(shelf.next_x, shelf.y);
+        shelf.next_x += size.width;
+        shelf.objects.push((origin.x, *size));
+        Some(origin)
+    }
+
+    fn free(&mut self, origin: &Point2D<u32>) {
+        let index = match self.shelves.iter().position(|shelf| {
+            origin.y >= shelf.y && origin.y < shelf.y + shelf.height
+        }) {
+            None => return,
+            Some(index) => index,
+        };
+
+        {
+            let shelf = &mut self.shelves[index];
+            match shelf.objects.iter().position(|&(x, _)| x == origin.x) {
+                None => return,
+                Some(object_index) => {
+                    shelf.objects.swap_remove(object_index);
+                }
+            }
+
+            // Space freed at the end of the shelf can be reused right away.
+            shelf.next_x = 0;
+            for &(x, ref size) in shelf.objects.iter() {
+                shelf.next_x = cmp::max(shelf.next_x, x + size.width)
+            }
+            if !shelf.is_empty() {
+                return
+            }
+        }
+        self.coalesce_free_shelves(index)
+    }
+
+    fn statistics(&self) -> AllocatorStatistics {
+        let mut statistics = AllocatorStatistics::new();
+        statistics.total_area = area(&self.size);
+        for shelf in self.shelves.iter() {
+            for &(_, ref size) in shelf.objects.iter() {
+                statistics.allocation_count += 1;
+                statistics.allocated_area += area(size);
+            }
+        }
+        statistics
+    }
+}
+
+fn area(size: &Size2D<u32>) -> u64 {
+    (size.width as u64) * (size.height as u64)
+}
+
+fn round_up(value: u32, multiple: u32) -> u32 {
+    (value + multiple - 1) / multiple * multiple
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::Size2D;
+
+    use super::{Allocator, AllocatorKind, AllocatorStatistics, new_allocator};
+
+    const PAGE_SIZE: u32 = 1024;
+
+    /// A deterministic stream of glyph-like sizes: mostly small and roughly square, from a linear
+    /// congruential generator so that every run packs the same objects.
+    fn glyph_sizes(count: usize) -> Vec<Size2D<u32>> {
+        let mut state: u32 = 12345;
+        let mut next = |range: u32| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) % range
+        };
+        (0..count).map(|_| {
+            let height = 10 + next(30);
+            Size2D::new(height / 2 + next(height), height)
+        }).collect()
+    }
+
+    /// Allocates glyphs until the page is full, returning the statistics at that point.
+    fn fill(kind: AllocatorKind) -> AllocatorStatistics {
+        let mut allocator = new_allocator(kind, &Size2D::new(PAGE_SIZE, PAGE_SIZE));
+        let mut expected_area = 0;
+        let mut count = 0;
+        for size in glyph_sizes(100000).iter() {
+            if allocator.allocate(size).is_none() {
+                break
+            }
+            count += 1;
+            expected_area += (size.width as u64) * (size.height as u64);
+        }
+        let statistics = allocator.statistics();
+        assert_eq!(statistics.allocation_count, count);
+        assert_eq!(statistics.allocated_area, expected_area);
+        assert_eq!(statistics.total_area, (PAGE_SIZE as u64) * (PAGE_SIZE as u64));
+        statistics
+    }
+
+    #[test]
+    fn shelf_packs_glyphs_at_least_as_densely_as_bin_tree() {
+        let bin_tree_statistics = fill(AllocatorKind::BinTree);
+        let shelf_statistics = fill(AllocatorKind::Shelf);
+        assert!(shelf_statistics.occupancy() > 0.8);
+        assert!(shelf_statistics.occupancy() >= bin_tree_statistics.occupancy());
+    }
+
+    #[test]
+    fn freeing_everything_empties_the_page() {
+        for &kind in &[AllocatorKind::BinTree, AllocatorKind::Shelf] {
+            let mut allocator = new_allocator(kind, &Size2D::new(PAGE_SIZE, PAGE_SIZE));
+            let origins: Vec<_> = glyph_sizes(100000).iter()
+                                                     .map(|size| allocator.allocate(size))
+                                                     .take_while(|origin| origin.is_some())
+                                                     .map(|origin| origin.unwrap())
+                                                     .collect();
+            assert_eq!(allocator.statistics().allocation_count, origins.len());
+            for origin in origins.iter() {
+                allocator.free(origin)
+            }
+            let statistics = allocator.statistics();
+            assert_eq!(statistics.allocation_count, 0);
+            assert_eq!(statistics.allocated_area, 0);
+            assert!(allocator.allocate(&Size2D::new(PAGE_SIZE, PAGE_SIZE)).is_some());
+        }
+    }
+
+    #[test]
+    fn empty_objects_can_be_freed() {
+        for &kind in &[AllocatorKind::BinTree, AllocatorKind::Shelf] {
+            let mut allocator = new_allocator(kind, &Size2D::new(PAGE_SIZE, PAGE_SIZE));
+            let origins: Vec<_> = [ Size2D::new(0, 0), Size2D::new(16, 0), Size2D::new(0, 16) ]
+                .iter()
+                .map(|size| allocator.allocate(size).unwrap())
+                .collect();
+            for origin in origins.iter() {
+                allocator.free(origin)
+            }
+            assert_eq!(allocator.statistics().allocation_count, 0);
+        }
+    }
+}
@@ -2,19 +2,29 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use atlas::{Atlas, AtlasHandle};
+use atlas::{Atlas, AtlasHandle, PixelFormat, Priority};
 use blur;
-use display_list::{DisplayItem, DisplayList};
+use debug::DebugDump;
+use display_list::{DisplayItem, DisplayList, ImageKey};
 use distance_field::{self, GLYPH_DISTANCE_SCALING_FACTOR};
 use job_server::JobServer;
-
-use euclid::Size2D;
-use freetype::{Face, Library};
-use freetype::face::RENDER;
-use std::cell::RefCell;
+use outline::{Outline, OutlineSegment};
+
+use euclid::{Point2D, Size2D};
+use freetype::{Face, Library, Vector};
+use freetype::face::{NO_BITMAP, RENDER};
+use freetype::outline::Curve;
+use image;
+use std::cell::{Cell, RefCell};
+use std::cmp;
 use std::collections::HashMap;
+use std::f32;
+use std::hash::{Hash, Hasher};
+use std::iter;
 use std::mem;
-use std::rc::Rc;
+use std::path::Path;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
 use std::sync::mpsc::Receiver;
 
 pub const ARC_RADIUS: u32 = 64;
@@ -26,6 +36,9 @@ const DISTANCE_FIELD_RATIO: f32 =
     (DISTANCE_FIELD_SIZE as f32) / (FONT_SIZE_FOR_RASTERIZATION as f32);
 const GLYPH_BUFFER_SIZE_RATIO: f32 = 0.5;
 
+/// The minimum number of entries in the asset map before dead entries are purged from it.
+const MIN_ASSET_MAP_PURGE_THRESHOLD: usize = 64;
+
 pub struct AssetContext {
     freetype_library: Library,
     loaded_fonts: HashMap<String,Face<'static>>,
@@ -58,11 +71,29 @@ impl Asset {
 
     pub fn is_in_atlas(&self) -> bool {
         match self.rasterization_status {
-            AssetRasterizationStatus::InAtlas(..) => true,
+            AssetRasterizationStatus::InAtlas(_, ref handle) => !handle.borrow().evicted,
             _ => false,
         }
     }
 
+    /// If this asset's space in the atlas has been evicted, marks it as being only in memory, so
+    /// that it will be reuploaded the next time it's needed.
+    pub fn return_to_memory_if_evicted(&mut self) {
+        if let AssetRasterizationStatus::InAtlas(_, ref handle) = self.rasterization_status {
+            if !handle.borrow().evicted {
+                return
+            }
+        } else {
+            return
+        }
+
+        let status = mem::replace(&mut self.rasterization_status,
+                                  AssetRasterizationStatus::Pending);
+        if let AssetRasterizationStatus::InAtlas(rasterized_asset, _) = status {
+            self.rasterization_status = AssetRasterizationStatus::InMemory(rasterized_asset)
+        }
+    }
+
     pub fn get_rasterization(&mut self) -> &mut AssetRasterization {
         let rasterized_asset = match self.rasterization_status {
             AssetRasterizationStatus::Pending => {
@@ -100,6 +131,33 @@ impl Asset {
         self.rasterization_status = AssetRasterizationStatus::InAtlas(rasterized_asset, handle)
     }
 
+    pub fn description(&self) -> &AssetDescription {
+        &self.description
+    }
+
+    /// Returns a name for this asset suitable for debug output.
+    pub fn debug_name(&self) -> String {
+        match self.derived_from {
+            None => self.description.debug_name(),
+            Some(ref derived_from) => {
+                format!("{}-of-{}",
+                        self.description.debug_name(),
+                        derived_from.borrow().debug_name())
+            }
+        }
+    }
+
+    /// Returns the key that identifies this asset: its description, and the descriptions of the
+    /// assets it's derived from.
+    pub fn key(&self) -> AssetKey {
+        AssetKey {
+            description: self.description.clone(),
+            derived_from: self.derived_from.as_ref().map(|derived_from| {
+                Box::new(derived_from.borrow().key())
+            }),
+        }
+    }
+
     pub fn get_atlas_handle(&self) -> Rc<RefCell<AtlasHandle>> {
         if let AssetRasterizationStatus::InAtlas(_, ref asset_handle) = self.rasterization_status {
             return (*asset_handle).clone()
@@ -108,11 +166,12 @@ impl Asset {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub enum AssetDescription {
     Glyph(Glyph),
     BlurredGlyph(BlurredGlyph),
     Arc(ArcAsset),
+    Image(ImageAsset),
 }
 
 impl AssetDescription {
@@ -125,6 +184,49 @@ impl AssetDescription {
                                         dependency.expect("Blurred glyphs need a glyph to blur!"))
             }
             AssetDescription::Arc(ref arc) => arc.rasterize(context),
+            AssetDescription::Image(ref image) => image.rasterize(context),
+        }
+    }
+
+    pub fn debug_name(&self) -> String {
+        match *self {
+            AssetDescription::Glyph(ref glyph) => {
+                let font_name = match Path::new(&glyph.font_path).file_stem() {
+                    Some(file_stem) => file_stem.to_string_lossy().into_owned(),
+                    None => glyph.font_path.clone(),
+                };
+                let suffix = match glyph.mode {
+                    DistanceFieldMode::SingleChannel => "",
+                    DistanceFieldMode::Multichannel => "-multichannel",
+                };
+                format!("glyph-{}-U+{:04X}{}", font_name, glyph.character as u32, suffix)
+            }
+            AssetDescription::BlurredGlyph(ref blurred_glyph) => {
+                format!("blurred-glyph-sigma{}", blurred_glyph.sigma)
+            }
+            AssetDescription::Arc(ref arc) => {
+                match arc.mode {
+                    ArcMode::FilledArc => "arc".to_owned(),
+                    ArcMode::InvertedFilledArc => "inverted-arc".to_owned(),
+                }
+            }
+            AssetDescription::Image(ref image) => {
+                match Path::new(&image.name).file_stem() {
+                    Some(file_stem) => format!("image-{}", file_stem.to_string_lossy()),
+                    None => format!("image-{}", image.key.0),
+                }
+            }
+        }
+    }
+
+    /// Returns true if this asset is a multichannel distance field, which must be decoded by
+    /// taking the median of its color channels.
+    pub fn is_multichannel(&self) -> bool {
+        match *self {
+            AssetDescription::Glyph(ref glyph) => glyph.mode == DistanceFieldMode::Multichannel,
+            AssetDescription::BlurredGlyph(_) |
+            AssetDescription::Arc(_) |
+            AssetDescription::Image(_) => false,
         }
     }
 }
@@ -132,14 +234,29 @@ impl AssetDescription {
 #[derive(Clone)]
 pub struct Glyph {
     pub font_path: String,
+    /// The font as it was named where the glyph came from, such as a path relative to a scene
+    /// file, for writing scenes. By default this is `font_path`.
+    pub font_name: String,
     pub character: char,
+    pub mode: DistanceFieldMode,
 }
 
 impl Glyph {
     pub fn new(font_path: String, character: char) -> Glyph {
         Glyph {
+            font_name: font_path.clone(),
             font_path: font_path,
             character: character,
+            mode: DistanceFieldMode::SingleChannel,
+        }
+    }
+
+    pub fn new_multichannel(font_path: String, character: char) -> Glyph {
+        Glyph {
+            font_name: font_path.clone(),
+            font_path: font_path,
+            character: character,
+            mode: DistanceFieldMode::Multichannel,
         }
     }
 
@@ -150,6 +267,14 @@ impl Glyph {
                           .entry(self.font_path.clone())
                           .or_insert_with(|| freetype_library.new_face(font_path, 0).unwrap());
         face.set_char_size(FONT_SIZE_FOR_RASTERIZATION as isize * 64, 0, 50, 0).unwrap();
+
+        // Build the field straight from the outline if we can. Otherwise (for example, for bitmap
+        // fonts), render the glyph at a large size and build the field from that.
+        face.load_char(self.character as usize, NO_BITMAP).unwrap();
+        if let Some(outline) = load_outline(face) {
+            return rasterize_outline(&outline, self.mode)
+        }
+
         face.load_char(self.character as usize, RENDER).unwrap();
 
         let glyph = face.glyph();
@@ -161,24 +286,155 @@ impl Glyph {
         let glyph_size_in_field =
             Size2D::new(((glyph_width as f32) * DISTANCE_FIELD_RATIO) as u32,
                         ((glyph_height as f32) * DISTANCE_FIELD_RATIO) as u32);
-        let extra_buffer_size =
-            Size2D::new((glyph_size_in_field.width as f32 * GLYPH_BUFFER_SIZE_RATIO) as u32,
-                        (glyph_size_in_field.height as f32 * GLYPH_BUFFER_SIZE_RATIO) as u32);
-        let distance_field_size =
-            Size2D::new(glyph_size_in_field.width + extra_buffer_size.width,
-                        glyph_size_in_field.height + extra_buffer_size.height);
-        let distance_field = distance_field::build_distance_field_for_glyph(buffer,
-                                                                            &glyph_size,
-                                                                            &glyph_size_in_field,
-                                                                            &distance_field_size);
+        let distance_field_size = distance_field_size_for_glyph(&glyph_size_in_field);
+        let distance_field =
+            distance_field::build_distance_field_for_glyph(buffer,
+                                                           &glyph_size,
+                                                           &glyph_size_in_field,
+                                                           &distance_field_size);
+
+        // Without an outline there are no edges to color, so give every channel the same
+        // distance. The median of the channels then decodes to the ordinary field.
+        if self.mode == DistanceFieldMode::Multichannel {
+            let mut multichannel_distance_field = Vec::with_capacity(distance_field.len() * 4);
+            for &distance in distance_field.iter() {
+                multichannel_distance_field.extend(iter::repeat(distance).take(4))
+            }
+            return AssetRasterization {
+                data: multichannel_distance_field,
+                size: distance_field_size,
+                format: PixelFormat::Rgba,
+            }
+        }
 
         AssetRasterization {
             data: distance_field,
             size: distance_field_size,
+            format: PixelFormat::Alpha,
         }
     }
 }
 
+// Glyphs are compared without their font names, which are only for display, so that a font
+// reached by two names is rasterized once.
+impl PartialEq for Glyph {
+    fn eq(&self, other: &Glyph) -> bool {
+        self.font_path == other.font_path && self.character == other.character &&
+            self.mode == other.mode
+    }
+}
+
+impl Eq for Glyph {}
+
+impl Hash for Glyph {
+    fn hash<H>(&self, state: &mut H) where H: Hasher {
+        self.font_path.hash(state);
+        self.character.hash(state);
+        self.mode.hash(state)
+    }
+}
+
+/// The kind of distance field generated for a glyph.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum DistanceFieldMode {
+    /// A single distance stored in the alpha channel.
+    SingleChannel,
+    /// Edge-colored distances stored in the color channels, with the single-channel field in
+    /// alpha. These keep corners sharp when magnified.
+    Multichannel,
+}
+
+/// Returns the outline of the glyph currently loaded into `face`, in pixels of the glyph
+/// rasterized at full size with the Y axis pointing down, or `None` if it has no outline.
+fn load_outline(face: &Face) -> Option<Outline> {
+    let glyph = face.glyph();
+    let freetype_outline = match glyph.outline() {
+        Some(freetype_outline) => freetype_outline,
+        None => return None,
+    };
+
+    let to_point = |vector: &Vector| {
+        Point2D::new((vector.x as f32) / 64.0, -(vector.y as f32) / 64.0)
+    };
+
+    let mut outline = Outline::new();
+    for curves in freetype_outline.contours_iter() {
+        let mut previous = to_point(curves.start());
+        let mut contour = Vec::new();
+        for curve in curves {
+            let segment = match curve {
+                Curve::Line(point) => OutlineSegment::Line(previous, to_point(&point)),
+                Curve::Bezier2(control_point, point) => {
+                    OutlineSegment::Quadratic(previous, to_point(&control_point), to_point(&point))
+                }
+                Curve::Bezier3(control_point_0, control_point_1, point) => {
+                    OutlineSegment::Cubic(previous,
+                                          to_point(&control_point_0),
+                                          to_point(&control_point_1),
+                                          to_point(&point))
+                }
+            };
+            previous = segment.end();
+            contour.push(segment)
+        }
+        outline.contours.push(contour)
+    }
+
+    if outline.is_empty() {
+        None
+    } else {
+        Some(outline)
+    }
+}
+
+fn rasterize_outline(outline: &Outline, mode: DistanceFieldMode) -> AssetRasterization {
+    let control_box = outline.control_box();
+    let glyph_size_in_field =
+        Size2D::new(f32::ceil(control_box.size.width * DISTANCE_FIELD_RATIO) as u32,
+                    f32::ceil(control_box.size.height * DISTANCE_FIELD_RATIO) as u32);
+    let distance_field_size = distance_field_size_for_glyph(&glyph_size_in_field);
+    let offset_from_field_to_glyph =
+        Point2D::new(((distance_field_size.width - glyph_size_in_field.width) / 2) as f32,
+                     ((distance_field_size.height - glyph_size_in_field.height) / 2) as f32);
+    let outline = outline.transform(|point| {
+        Point2D::new(offset_from_field_to_glyph.x +
+                        (point.x - control_box.origin.x) * DISTANCE_FIELD_RATIO,
+                     offset_from_field_to_glyph.y +
+                        (point.y - control_box.origin.y) * DISTANCE_FIELD_RATIO)
+    });
+    let (distance_field, format) = match mode {
+        DistanceFieldMode::SingleChannel => {
+            (distance_field::build_distance_field_for_outline(&outline,
+                                                              &distance_field_size,
+                                                              1.0 / DISTANCE_FIELD_RATIO),
+             PixelFormat::Alpha)
+        }
+        DistanceFieldMode::Multichannel => {
+            (distance_field::build_multichannel_distance_field_for_outline(
+                    &outline,
+                    &distance_field_size,
+                    1.0 / DISTANCE_FIELD_RATIO),
+             PixelFormat::Rgba)
+        }
+    };
+
+    AssetRasterization {
+        data: distance_field,
+        size: distance_field_size,
+        format: format,
+    }
+}
+
+/// Returns the size of the distance field for a glyph, leaving room around it for the field to
+/// fall off.
+fn distance_field_size_for_glyph(glyph_size_in_field: &Size2D<u32>) -> Size2D<u32> {
+    let extra_buffer_size =
+        Size2D::new((glyph_size_in_field.width as f32 * GLYPH_BUFFER_SIZE_RATIO) as u32,
+                    (glyph_size_in_field.height as f32 * GLYPH_BUFFER_SIZE_RATIO) as u32);
+    Size2D::new(glyph_size_in_field.width + extra_buffer_size.width,
+                glyph_size_in_field.height + extra_buffer_size.height)
+}
+
 #[derive(Clone)]
 pub struct BlurredGlyph {
     pub sigma: f32,
@@ -195,17 +451,34 @@ impl BlurredGlyph {
                      -> AssetRasterization {
         let data =
             blur::approximate_gaussian_blur_with_distance_field(&dependency.data[..],
+                                                                dependency.format,
                                                                 GLYPH_DISTANCE_SCALING_FACTOR,
                                                                 &dependency.size,
                                                                 self.sigma);
         AssetRasterization {
             data: data,
             size: dependency.size,
+            format: PixelFormat::Alpha,
         }
     }
 }
 
-#[derive(Clone)]
+// Blurs are compared by the bits of their sigma so that they can be used as map keys.
+impl PartialEq for BlurredGlyph {
+    fn eq(&self, other: &BlurredGlyph) -> bool {
+        self.sigma.to_bits() == other.sigma.to_bits()
+    }
+}
+
+impl Eq for BlurredGlyph {}
+
+impl Hash for BlurredGlyph {
+    fn hash<H>(&self, state: &mut H) where H: Hasher {
+        self.sigma.to_bits().hash(state)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct ArcAsset {
     pub mode: ArcMode,
 }
@@ -216,20 +489,80 @@ impl ArcAsset {
         AssetRasterization {
             data: data,
             size: Size2D::new(ARC_SIZE, ARC_SIZE),
+            format: PixelFormat::Alpha,
         }
     }
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub enum ArcMode {
     FilledArc,
     InvertedFilledArc,
 }
 
+/// An image, decoded from PNG, JPEG, or GIF data into premultiplied RGBA.
+#[derive(Clone)]
+pub struct ImageAsset {
+    pub key: ImageKey,
+    /// Where the image came from, such as its path as a scene file gave it, for debugging and for
+    /// writing scenes.
+    pub name: String,
+    /// The encoded image. It's shared, since the description is cloned to send it to the job
+    /// server.
+    pub data: Arc<Vec<u8>>,
+}
+
+impl ImageAsset {
+    fn rasterize(&self, _: &mut AssetContext) -> AssetRasterization {
+        let image = match image::load_from_memory(&self.data[..]) {
+            Ok(image) => image.to_rgba(),
+            Err(error) => {
+                // Draw nothing rather than bringing down the job server over a bad image.
+                warn!("couldn't decode the image \"{}\": {}", self.name, error);
+                return AssetRasterization {
+                    data: vec![0; 4],
+                    size: Size2D::new(1, 1),
+                    format: PixelFormat::Rgba,
+                }
+            }
+        };
+        let (width, height) = image.dimensions();
+        let mut data = image.into_raw();
+        for pixel in data.chunks_mut(4) {
+            let alpha = pixel[3] as u32;
+            for channel in 0..3 {
+                pixel[channel] = ((pixel[channel] as u32 * alpha + 127) / 255) as u8
+            }
+        }
+        AssetRasterization {
+            data: data,
+            size: Size2D::new(width, height),
+            format: PixelFormat::Rgba,
+        }
+    }
+}
+
+// Images are compared by key alone, since images with the same key have the same data.
+impl PartialEq for ImageAsset {
+    fn eq(&self, other: &ImageAsset) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for ImageAsset {}
+
+impl Hash for ImageAsset {
+    fn hash<H>(&self, state: &mut H) where H: Hasher {
+        self.key.hash(state)
+    }
+}
+
 #[derive(Clone)]
 pub struct AssetRasterization {
     pub data: Vec<u8>,
     pub size: Size2D<u32>,
+    /// The layout of `data`, which determines the kind of atlas page the asset goes on.
+    pub format: PixelFormat,
 }
 
 pub enum AssetRasterizationStatus {
@@ -240,9 +573,21 @@ pub enum AssetRasterizationStatus {
     InAtlas(AssetRasterization, Rc<RefCell<AtlasHandle>>),
 }
 
+/// Identifies an asset for the purposes of sharing it among display items.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct AssetKey {
+    description: AssetDescription,
+    derived_from: Option<Box<AssetKey>>,
+}
+
 pub struct AssetManager {
     job_server: Rc<RefCell<JobServer>>,
     pub atlas: Rc<RefCell<Atlas>>,
+    /// All assets that are still alive, so that identical ones are only rasterized once. The
+    /// references are weak so that assets no display item uses any longer are freed.
+    assets: RefCell<HashMap<AssetKey,Weak<RefCell<Asset>>>>,
+    /// The size the asset map must grow to before dead entries are purged from it.
+    purge_threshold: Cell<usize>,
 }
 
 impl AssetManager {
@@ -250,19 +595,78 @@ impl AssetManager {
         AssetManager {
             job_server: job_server,
             atlas: atlas,
+            assets: RefCell::new(HashMap::new()),
+            purge_threshold: Cell::new(MIN_ASSET_MAP_PURGE_THRESHOLD),
         }
     }
 
+    /// Returns the asset with the given description, derived from the given asset. If such an
+    /// asset already exists, it is shared instead of a new one being created.
     pub fn create_asset(&self,
                         description: AssetDescription,
                         derived_from: Option<Rc<RefCell<Asset>>>)
                         -> Rc<RefCell<Asset>> {
-        // TODO(pcwalton): Maintain a map of assets so we don't rasterize things multiple times.
-        Rc::new(RefCell::new(Asset {
+        let key = AssetKey {
+            description: description.clone(),
+            derived_from: derived_from.as_ref().map(|derived_from| {
+                Box::new(derived_from.borrow().key())
+            }),
+        };
+        if let Some(asset) = self.assets.borrow().get(&key).and_then(|asset| asset.upgrade()) {
+            return asset
+        }
+
+        let asset = Rc::new(RefCell::new(Asset {
             description: description,
             rasterization_status: AssetRasterizationStatus::Pending,
             derived_from: derived_from,
-        }))
+        }));
+        self.assets.borrow_mut().insert(key, Rc::downgrade(&asset));
+
+        if self.assets.borrow().len() >= self.purge_threshold.get() {
+            self.purge_unused_assets();
+            self.purge_threshold.set(cmp::max(self.assets.borrow().len() * 2,
+                                              MIN_ASSET_MAP_PURGE_THRESHOLD));
+        }
+        asset
+    }
+
+    /// Forgets about assets that are no longer used by anything.
+    pub fn purge_unused_assets(&self) {
+        let mut assets = self.assets.borrow_mut();
+        let dead_keys: Vec<AssetKey> = assets.iter()
+                                             .filter(|&(_, asset)| asset.upgrade().is_none())
+                                             .map(|(key, _)| key.clone())
+                                             .collect();
+        for key in dead_keys.iter() {
+            assets.remove(key);
+        }
+    }
+
+    /// Repacks the atlas so that the space freed by evicted and dead assets can be reused. Batches
+    /// built before this refer to the old locations of the assets, so they must be patched or
+    /// rebuilt afterward.
+    pub fn compact_atlas(&self) {
+        let assets: Vec<Rc<RefCell<Asset>>> = self.assets
+                                                  .borrow()
+                                                  .values()
+                                                  .filter_map(|asset| asset.upgrade())
+                                                  .collect();
+        self.atlas.borrow_mut().compact(&assets[..]);
+    }
+
+    /// Compacts the atlas if the live assets are spread thinly over its pages. Returns true if it
+    /// was compacted.
+    pub fn compact_atlas_if_fragmented(&self) -> bool {
+        let fragmented = {
+            let mut atlas = self.atlas.borrow_mut();
+            atlas.reclaim_dead_entries();
+            atlas.is_fragmented()
+        };
+        if fragmented {
+            self.compact_atlas()
+        }
+        fragmented
     }
 
     pub fn start_rasterizing_asset_if_necessary(&self, asset: &mut Asset) {
@@ -303,7 +707,9 @@ impl AssetManager {
                                                                  display_list: &mut DisplayList) {
         for item in display_list.items.iter_mut() {
             match *item {
-                DisplayItem::SolidColor(_) => {}
+                DisplayItem::SolidColor(_) |
+                DisplayItem::LinearGradient(_) |
+                DisplayItem::RadialGradient(_) => {}
                 DisplayItem::Text(ref mut text_display_item) => {
                     self.start_rasterizing_asset_if_necessary(
                         &mut *text_display_item.glyph_asset.borrow_mut());
@@ -318,6 +724,78 @@ impl AssetManager {
                     self.start_rasterizing_asset_if_necessary(
                         &mut *border_display_item.inverted_arc_asset.borrow_mut());
                 }
+                DisplayItem::Image(ref mut image_display_item) => {
+                    self.start_rasterizing_asset_if_necessary(
+                        &mut *image_display_item.image_asset.borrow_mut())
+                }
+            }
+        }
+    }
+
+    /// Waits for every asset in the display list to be rasterized, starting any that haven't been
+    /// yet. Assets derived from others are started once the assets they derive from are done.
+    pub fn finish_rasterizing_assets_in_display_list(&self, display_list: &mut DisplayList) {
+        for item in display_list.items.iter_mut() {
+            match *item {
+                DisplayItem::SolidColor(_) |
+                DisplayItem::LinearGradient(_) |
+                DisplayItem::RadialGradient(_) => {}
+                DisplayItem::Text(ref mut text_display_item) => {
+                    self.finish_rasterizing_asset(&mut *text_display_item.glyph_asset.borrow_mut());
+                    if let Some(ref blurred_glyph_asset) = text_display_item.blurred_glyph_asset {
+                        self.finish_rasterizing_asset(&mut *blurred_glyph_asset.borrow_mut())
+                    }
+                }
+                DisplayItem::Border(ref mut border_display_item) => {
+                    self.finish_rasterizing_asset(
+                        &mut *border_display_item.arc_asset.borrow_mut());
+                    self.finish_rasterizing_asset(
+                        &mut *border_display_item.inverted_arc_asset.borrow_mut());
+                }
+                DisplayItem::Image(ref mut image_display_item) => {
+                    self.finish_rasterizing_asset(&mut *image_display_item.image_asset.borrow_mut())
+                }
+            }
+        }
+    }
+
+    fn finish_rasterizing_asset(&self, asset: &mut Asset) {
+        self.start_rasterizing_asset_if_necessary(asset);
+        asset.get_rasterization();
+    }
+
+    /// Makes sure that every asset the display list draws with is in the atlas. The assets must
+    /// have been rasterized.
+    pub fn upload_assets_in_display_list(&self,
+                                         display_list: &DisplayList,
+                                         debug_dump: &DebugDump) {
+        let mut atlas = self.atlas.borrow_mut();
+        for item in display_list.items.iter() {
+            match *item {
+                DisplayItem::SolidColor(_) |
+                DisplayItem::LinearGradient(_) |
+                DisplayItem::RadialGradient(_) => {}
+                DisplayItem::Text(ref text_display_item) => {
+                    // Blurred text is drawn with the blurred glyph alone.
+                    let asset = match text_display_item.blurred_glyph_asset {
+                        Some(ref blurred_glyph_asset) => blurred_glyph_asset,
+                        None => &text_display_item.glyph_asset,
+                    };
+                    atlas.require_asset(&mut *asset.borrow_mut(), Priority::Retained, debug_dump)
+                }
+                DisplayItem::Border(ref border_display_item) => {
+                    atlas.require_asset(&mut *border_display_item.arc_asset.borrow_mut(),
+                                        Priority::Retained,
+                                        debug_dump);
+                    atlas.require_asset(&mut *border_display_item.inverted_arc_asset.borrow_mut(),
+                                        Priority::Retained,
+                                        debug_dump);
+                }
+                DisplayItem::Image(ref image_display_item) => {
+                    atlas.require_asset(&mut *image_display_item.image_asset.borrow_mut(),
+                                        Priority::Retained,
+                                        debug_dump)
+                }
             }
         }
     }
@@ -4,199 +4,650 @@
 
 //! Texture atlas management.
 //!
-//! Currently, the bin packing algorithm is the simple one described here:
+//! Space on each page is handed out by one of the packers in the `allocator` module; the shelf
+//! packer is the default, since it copes best with lots of similarly sized glyphs.
 //!
-//!     http://www.blackpawn.com/texts/lightmaps/default.html
+//! The atlas consists of one or more pages, each a separate texture. When the atlas fills up,
+//! space belonging to assets that are no longer alive is reclaimed first. After that, assets that
+//! haven't been used in the current generation (frame) are evicted in least-recently-used order;
+//! their assets remain in memory and are reuploaded on demand. Only if that fails to make room
+//! does the atlas grow by another page. `compact` repacks the survivors to close up the holes
+//! that eviction leaves behind, once they are spread thinly enough over several pages that doing
+//! so would free one.
+//!
+//! Each page has a pixel format, and assets only go on pages with the same format as their
+//! rasterization. Distance fields need only one channel, so they go on alpha pages, which take a
+//! quarter of the memory and upload bandwidth of the RGBA pages that color content needs.
+//!
+//! Pages are separate 2D textures rather than layers of a texture array, because texture arrays
+//! aren't available in OpenGL ES 2.0. The batcher starts a new batch whenever an item needs a
+//! different page, so this costs a draw call per page switch.
+//!
+//! Objects too big to share a page, like large images, each get a standalone page of their own,
+//! exactly their size. Standalone pages don't count toward the page limit and are never evicted;
+//! their textures are released as soon as their objects die. If the shared pages are at their
+//! limit and everything on them is in use, new objects get standalone pages too, so that the
+//! atlas never runs out of space, at the cost of a draw call each.
 
+use allocator::{self, Allocator, AllocatorKind, AllocatorStatistics};
 use assets::Asset;
+use debug::DebugDump;
 
-use euclid::{Point2D, Rect, Size2D};
-use gleam::gl::{self, GLint, GLuint};
+use euclid::{Rect, Size2D};
+use gleam::gl::{self, GLenum, GLint, GLuint};
 use std::cell::RefCell;
-use std::cmp;
-use std::fs::File;
-use std::io::Write;
-use std::rc::Rc;
-use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use std::mem;
+use std::rc::{Rc, Weak};
 
 pub const WIDTH: GLuint = 1024;
 pub const HEIGHT: GLuint = 1024;
 
+/// The maximum number of shared pages the atlas may grow to.
+const MAX_PAGES: usize = 8;
+
+/// The atlas is worth compacting when the live objects of some format would fill less than this
+/// fraction of one page fewer than they are spread over.
+const COMPACTION_OCCUPANCY_THRESHOLD: f32 = 0.5;
+
+/// Objects wider or taller than this go on standalone pages, so that a few large images don't
+/// crowd everything else out of the shared ones.
+const MAX_SHARED_OBJECT_SIZE: u32 = 512;
+
 pub struct Atlas {
-    pub texture: GLuint,
-    root_bin: Bin,
+    pages: Vec<AtlasPage>,
+    /// The kind of packer to use for each page.
+    allocator_kind: AllocatorKind,
+    /// False if pages are only kept in memory, for software rendering without a GL context.
+    uses_textures: bool,
+    /// Everything that has been allocated in the atlas and not yet freed.
+    entries: Vec<AtlasEntry>,
+    /// The current generation. Handles used since this was last advanced may not be evicted.
+    generation: u64,
 }
 
-impl Atlas {
-    pub fn new() -> Atlas {
-        let texture = gl::gen_textures(1)[0];
-        gl::bind_texture(gl::TEXTURE_2D, texture);
+/// One texture of the atlas.
+struct AtlasPage {
+    /// The texture backing this page, or `None` if the atlas doesn't use textures.
+    texture: Option<GLuint>,
+    format: PixelFormat,
+    size: Size2D<u32>,
+    /// A copy of the texture's contents, since OpenGL ES can't read textures back. This also
+    /// serves the software rasterizer. Empty once a standalone page has been released.
+    pixels: Vec<u8>,
+    allocator: Box<Allocator>,
+    /// True if this page holds a single object too big for the shared pages.
+    standalone: bool,
+}
+
+impl AtlasPage {
+    fn new(allocator_kind: AllocatorKind, format: PixelFormat, uses_textures: bool) -> AtlasPage {
+        AtlasPage::with_size(allocator_kind, format, &Size2D::new(WIDTH, HEIGHT), uses_textures)
+    }
+
+    /// Returns a standalone page just big enough for an object of the given size.
+    fn new_standalone(allocator_kind: AllocatorKind,
+                      format: PixelFormat,
+                      size: &Size2D<u32>,
+                      uses_textures: bool)
+                      -> AtlasPage {
+        let mut page = AtlasPage::with_size(allocator_kind, format, size, uses_textures);
+        page.standalone = true;
+        page
+    }
 
-        let mut buffer = Vec::new();
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                buffer.extend([ 0, 0, 255, 255 ].iter());
+    fn with_size(allocator_kind: AllocatorKind,
+                 format: PixelFormat,
+                 size: &Size2D<u32>,
+                 uses_textures: bool)
+                 -> AtlasPage {
+        let mut pixels = Vec::new();
+        for y in 0..size.height {
+            for x in 0..size.width {
+                match format {
+                    PixelFormat::Alpha => pixels.push(0),
+                    PixelFormat::Rgba => pixels.extend([ 0, 0, 255, 255 ].iter()),
+                }
             }
         }
+
+        let texture = if uses_textures {
+            Some(AtlasPage::create_texture(format, size, &pixels[..]))
+        } else {
+            None
+        };
+        AtlasPage {
+            texture: texture,
+            format: format,
+            size: *size,
+            pixels: pixels,
+            allocator: allocator::new_allocator(allocator_kind, size),
+            standalone: false,
+        }
+    }
+
+    fn create_texture(format: PixelFormat, size: &Size2D<u32>, pixels: &[u8]) -> GLuint {
+        let texture = gl::gen_textures(1)[0];
+        gl::bind_texture(gl::TEXTURE_2D, texture);
         gl::tex_image_2d(gl::TEXTURE_2D,
                          0,
-                         gl::RGBA as GLint,
-                         WIDTH as GLint,
-                         HEIGHT as GLint,
+                         format.gl_format() as GLint,
+                         size.width as GLint,
+                         size.height as GLint,
                          0,
-                         gl::RGBA,
+                         format.gl_format(),
                          gl::UNSIGNED_BYTE,
-                         Some(&buffer[..]));
+                         Some(pixels));
 
         gl::tex_parameter_i(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
         gl::tex_parameter_i(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
-        gl::tex_parameter_i(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
-        gl::tex_parameter_i(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
+        // Standalone pages needn't be a power of two in size, and OpenGL ES 2.0 can't repeat
+        // textures that aren't. Texture coordinates stay inside objects anyway.
+        gl::tex_parameter_i(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+        gl::tex_parameter_i(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+        texture
+    }
+
+    /// Releases the texture and pixels of a standalone page whose object has been freed. The page
+    /// itself stays, so that the indices of later pages don't change, until it's reused.
+    fn release(&mut self) {
+        if let Some(texture) = self.texture.take() {
+            gl::delete_textures(&[texture]);
+        }
+        self.pixels = Vec::new()
+    }
+
+    /// Returns true if this is a standalone page whose object has been freed.
+    fn is_released(&self) -> bool {
+        self.standalone && self.allocator.statistics().allocation_count == 0
+    }
+}
+
+struct AtlasEntry {
+    location: AtlasLocation,
+    handle: Weak<RefCell<AtlasHandle>>,
+}
+
+impl Atlas {
+    pub fn new() -> Atlas {
+        Atlas::with_allocator(AllocatorKind::Shelf)
+    }
+
+    pub fn with_allocator(allocator_kind: AllocatorKind) -> Atlas {
+        Atlas::create(allocator_kind, true)
+    }
+
+    /// Returns an atlas whose pages are only kept in memory. It needs no GL context, and is meant
+    /// for the software rasterizer.
+    pub fn new_in_memory() -> Atlas {
+        Atlas::create(AllocatorKind::Shelf, false)
+    }
+
+    fn create(allocator_kind: AllocatorKind, uses_textures: bool) -> Atlas {
         Atlas {
-            texture: texture,
-            root_bin: Bin::new(&Rect::new(Point2D::new(0, 0), Size2D::new(WIDTH, HEIGHT))),
+            pages: vec![AtlasPage::new(allocator_kind, PixelFormat::Alpha, uses_textures)],
+            allocator_kind: allocator_kind,
+            uses_textures: uses_textures,
+            entries: Vec::new(),
+            generation: 0,
+        }
+    }
+
+    /// Returns the texture backing the given page.
+    pub fn texture_for_page(&self, page: usize) -> GLuint {
+        self.pages[page].texture.expect("This atlas is only in memory!")
+    }
+
+    pub fn page_format(&self, page: usize) -> PixelFormat {
+        self.pages[page].format
+    }
+
+    /// Returns the size of the given page in pixels. Standalone pages are the size of their
+    /// objects.
+    pub fn page_size(&self, page: usize) -> Size2D<u32> {
+        self.pages[page].size
+    }
+
+    /// Returns the contents of the given page.
+    pub fn page_pixels(&self, page: usize) -> &[u8] {
+        &self.pages[page].pixels[..]
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Returns packing statistics for all shared pages together.
+    pub fn statistics(&self) -> AllocatorStatistics {
+        let mut statistics = AllocatorStatistics::new();
+        for page in self.pages.iter().filter(|page| !page.standalone) {
+            statistics = statistics.add(&page.allocator.statistics())
         }
+        statistics
     }
 
-    pub fn require_asset(&mut self, asset: &mut Asset, priority: Priority) {
+    /// Starts a new generation. Call this once per frame, before building batches; objects that
+    /// aren't required again after this become candidates for eviction.
+    pub fn advance_generation(&mut self) {
+        self.generation += 1
+    }
+
+    /// Writes each page of the atlas out through the debug dump.
+    pub fn snapshot(&self, debug_dump: &DebugDump) {
+        if !debug_dump.is_enabled() {
+            return
+        }
+        let name = debug_dump.next_snapshot_name();
+        for (page_index, page) in self.pages.iter().enumerate() {
+            if page.is_released() {
+                continue
+            }
+            debug_dump.dump(&format!("{}-page{}", name, page_index),
+                            &page.pixels[..],
+                            &page.size,
+                            page.format)
+        }
+    }
+
+    pub fn require_asset(&mut self, asset: &mut Asset, priority: Priority, debug_dump: &DebugDump) {
+        asset.return_to_memory_if_evicted();
         if asset.is_in_atlas() {
+            asset.get_atlas_handle().borrow_mut().last_used_generation = self.generation;
             return
         }
 
         let handle = {
             let rasterization = asset.get_rasterization();
-            let location = self.allocate(priority, &rasterization.size);
-            self.upload(&location, &rasterization.data[..])
+            self.place(priority, &rasterization.size, rasterization.format, &rasterization.data[..])
         };
-
+        if debug_dump.is_enabled() {
+            let rasterization = asset.get_rasterization();
+            debug_dump.dump(&asset.debug_name(),
+                            &rasterization.data[..],
+                            &handle.borrow().location.rect.size,
+                            rasterization.format)
+        }
         asset.set_atlas_handle(handle);
     }
 
-    fn allocate(&mut self, _: Priority, size: &Size2D<u32>) -> AtlasLocation {
-        // TODO(pcwalton): Evict old objects.
-        let point = self.root_bin.insert(size).expect("Atlas out of space!");
-        println!("placing object at {:?}", point);
+    /// Uploads an object and returns its handle. If the shared pages are at their limit and full
+    /// of objects used in the current generation, the object goes on a standalone page instead.
+    fn place(&mut self, priority: Priority, size: &Size2D<u32>, format: PixelFormat, data: &[u8])
+             -> Rc<RefCell<AtlasHandle>> {
+        let location = match self.allocate(priority, size, format) {
+            Some(location) => location,
+            None => {
+                warn!("the atlas is out of space; placing a {}x{} object on a page of its own",
+                      size.width,
+                      size.height);
+                self.allocate_standalone(size, format)
+            }
+        };
+        self.upload(&location, data);
+
+        let handle = Rc::new(RefCell::new(AtlasHandle {
+            location: location,
+            last_used_generation: self.generation,
+            evicted: false,
+        }));
+        self.entries.push(AtlasEntry {
+            location: location,
+            handle: Rc::downgrade(&handle),
+        });
+        handle
+    }
+
+    /// Evicts everything and releases all pages, leaving the atlas as it was when it was created.
+    pub fn clear(&mut self) {
+        for entry in self.entries.drain(..) {
+            if let Some(handle) = entry.handle.upgrade() {
+                handle.borrow_mut().evicted = true
+            }
+        }
+        for page in self.pages.drain(..) {
+            if let Some(texture) = page.texture {
+                gl::delete_textures(&[texture]);
+            }
+        }
+        self.pages.push(AtlasPage::new(self.allocator_kind, PixelFormat::Alpha, self.uses_textures))
+    }
+
+    /// Returns true if the live objects of some format are spread over more pages than they need,
+    /// so that compacting the atlas would likely free a page.
+    pub fn is_fragmented(&self) -> bool {
+        let page_area = (WIDTH as u64) * (HEIGHT as u64);
+        [ PixelFormat::Alpha, PixelFormat::Rgba ].iter().any(|&format| {
+            let mut statistics = AllocatorStatistics::new();
+            let mut occupied_page_count = 0;
+            for page in self.pages.iter() {
+                let page_statistics = page.allocator.statistics();
+                if page.standalone || page.format != format ||
+                        page_statistics.allocation_count == 0 {
+                    continue
+                }
+                occupied_page_count += 1;
+                statistics = statistics.add(&page_statistics)
+            }
+            occupied_page_count > 1 &&
+                (statistics.allocated_area as f32) <
+                ((occupied_page_count - 1) as f32) * (page_area as f32) *
+                COMPACTION_OCCUPANCY_THRESHOLD
+        })
+    }
+
+    /// Repacks every live object in the atlas, closing up the holes left behind by eviction.
+    ///
+    /// Objects are reuploaded from the rasterizations that `assets` retain, and their handles are
+    /// updated in place, so display items that refer to them stay valid. Vertices already
+    /// generated for those items still point at the old locations; retained batches notice that
+    /// the locations changed and patch just those items. Objects belonging to assets not in
+    /// `assets`, and any that no longer fit once the atlas is at its page limit, are evicted and
+    /// reuploaded on demand. Pages that end up empty are released. Objects on standalone pages
+    /// have nothing to be packed with, so they stay where they are.
+    pub fn compact(&mut self, assets: &[Rc<RefCell<Asset>>]) {
+        // Place the tallest objects first, which suits both packers.
+        let mut live_assets = Vec::new();
+        for asset in assets.iter() {
+            let mut asset_ref = asset.borrow_mut();
+            if asset_ref.is_in_atlas() &&
+                    !self.pages[asset_ref.get_atlas_handle().borrow().location.page].standalone {
+                live_assets.push((asset_ref.get_rasterization().size.height, asset.clone()))
+            }
+        }
+        live_assets.sort_by(|&(a, _), &(b, _)| b.cmp(&a));
+
+        let entries = mem::replace(&mut self.entries, Vec::new());
+        for entry in entries.into_iter() {
+            if self.pages[entry.location.page].standalone {
+                self.entries.push(entry);
+                continue
+            }
+            if let Some(handle) = entry.handle.upgrade() {
+                handle.borrow_mut().evicted = true
+            }
+        }
+        for page in self.pages.iter_mut().filter(|page| !page.standalone) {
+            page.allocator = allocator::new_allocator(self.allocator_kind, &page.size);
+        }
+
+        for &(_, ref asset) in live_assets.iter() {
+            let mut asset = asset.borrow_mut();
+            let handle = asset.get_atlas_handle();
+            let rasterization = asset.get_rasterization();
+            let location;
+            loop {
+                if let Some(inserted_location) = self.insert(&rasterization.size,
+                                                             rasterization.format) {
+                    location = Some(inserted_location);
+                    break
+                }
+                if self.pages.iter().filter(|page| !page.standalone).count() == MAX_PAGES {
+                    location = None;
+                    break
+                }
+                self.pages.push(AtlasPage::new(self.allocator_kind,
+                                               rasterization.format,
+                                               self.uses_textures))
+            }
+            let location = match location {
+                None => continue,
+                Some(location) => location,
+            };
+            self.upload(&location, &rasterization.data[..]);
+
+            let mut handle_ref = handle.borrow_mut();
+            handle_ref.location = location;
+            handle_ref.evicted = false;
+            self.entries.push(AtlasEntry {
+                location: location,
+                handle: Rc::downgrade(&handle),
+            });
+        }
+
+        while self.pages.len() > 1 &&
+                self.pages.last().unwrap().allocator.statistics().allocation_count == 0 {
+            if let Some(texture) = self.pages.pop().unwrap().texture {
+                gl::delete_textures(&[texture]);
+            }
+        }
+    }
+
+    /// Finds space for an object, evicting objects not used in the current generation and adding
+    /// pages as necessary. Returns `None` if the shared pages are at their limit and there's still
+    /// no room.
+    fn allocate(&mut self, _: Priority, size: &Size2D<u32>, format: PixelFormat)
+                -> Option<AtlasLocation> {
+        if size.width > MAX_SHARED_OBJECT_SIZE || size.height > MAX_SHARED_OBJECT_SIZE {
+            return Some(self.allocate_standalone(size, format))
+        }
+
+        let mut reclaimed_dead_entries = false;
+        let location;
+        loop {
+            if let Some(inserted_location) = self.insert(size, format) {
+                location = inserted_location;
+                break
+            }
+            if !reclaimed_dead_entries {
+                self.reclaim_dead_entries();
+                reclaimed_dead_entries = true;
+                continue
+            }
+            if self.evict_least_recently_used_entry(format) {
+                continue
+            }
+            if self.pages.iter().filter(|page| !page.standalone).count() == MAX_PAGES {
+                return None
+            }
+            self.pages.push(AtlasPage::new(self.allocator_kind, format, self.uses_textures))
+        }
+        debug!("placing object at {:?} on page {}", location.rect.origin, location.page);
+        Some(location)
+    }
+
+    /// Places an object on a standalone page of its own, reusing a released one if possible.
+    fn allocate_standalone(&mut self, size: &Size2D<u32>, format: PixelFormat) -> AtlasLocation {
+        self.reclaim_dead_entries();
+        let mut standalone_page = AtlasPage::new_standalone(self.allocator_kind,
+                                                            format,
+                                                            size,
+                                                            self.uses_textures);
+        let origin = standalone_page.allocator.allocate(size).expect("Standalone page too small!");
+        let page_index = match self.pages.iter().position(|page| page.is_released()) {
+            Some(page_index) => {
+                self.pages[page_index] = standalone_page;
+                page_index
+            }
+            None => {
+                self.pages.push(standalone_page);
+                self.pages.len() - 1
+            }
+        };
         AtlasLocation {
-            rect: Rect::new(point, *size),
+            page: page_index,
+            rect: Rect::new(origin, *size),
+        }
+    }
+
+    fn insert(&mut self, size: &Size2D<u32>, format: PixelFormat) -> Option<AtlasLocation> {
+        for (page_index, page) in self.pages.iter_mut().enumerate() {
+            if page.format != format || page.standalone {
+                continue
+            }
+            if let Some(point) = page.allocator.allocate(size) {
+                return Some(AtlasLocation {
+                    page: page_index,
+                    rect: Rect::new(point, *size),
+                })
+            }
+        }
+        None
+    }
+
+    fn free(&mut self, location: &AtlasLocation) {
+        let page = &mut self.pages[location.page];
+        page.allocator.free(&location.rect.origin);
+        if page.standalone {
+            page.release()
+        }
+    }
+
+    /// Frees the space belonging to objects whose handles have been dropped.
+    pub fn reclaim_dead_entries(&mut self) {
+        let mut index = 0;
+        while index < self.entries.len() {
+            if self.entries[index].handle.upgrade().is_some() {
+                index += 1;
+                continue
+            }
+            let entry = self.entries.swap_remove(index);
+            self.free(&entry.location);
         }
     }
 
-    fn upload(&mut self, location: &AtlasLocation, buffer: &[u8]) -> Rc<RefCell<AtlasHandle>> {
-        assert!(buffer.len() >=
-                (location.rect.size.width * location.rect.size.height * 4) as usize);
-        gl::bind_texture(gl::TEXTURE_2D, self.texture);
-        gl::tex_sub_image_2d(gl::TEXTURE_2D,
-                             0,
-                             location.rect.origin.x as GLint,
-                             location.rect.origin.y as GLint,
-                             location.rect.size.width as GLint,
-                             location.rect.size.height as GLint,
-                             gl::RGBA,
-                             gl::UNSIGNED_BYTE,
-                             buffer);
+    /// Evicts the least recently used object on a shared page of the given format that hasn't
+    /// been used in the current generation. Returns false if there is no such object.
+    fn evict_least_recently_used_entry(&mut self, format: PixelFormat) -> bool {
+        let mut victim: Option<(usize, u64)> = None;
+        for (index, entry) in self.entries.iter().enumerate() {
+            let page = &self.pages[entry.location.page];
+            if page.format != format || page.standalone {
+                continue
+            }
+            let handle = match entry.handle.upgrade() {
+                Some(handle) => handle,
+                None => continue,
+            };
+            let last_used_generation = handle.borrow().last_used_generation;
+            if last_used_generation >= self.generation {
+                continue
+            }
+            match victim {
+                Some((_, victim_generation)) if victim_generation <= last_used_generation => {}
+                _ => victim = Some((index, last_used_generation)),
+            }
+        }
+
+        let index = match victim {
+            None => return false,
+            Some((index, _)) => index,
+        };
+        let entry = self.entries.swap_remove(index);
+        if let Some(handle) = entry.handle.upgrade() {
+            handle.borrow_mut().evicted = true
+        }
+        self.free(&entry.location);
+        true
+    }
 
-        {
-            static ATLAS_INDEX: AtomicUsize = ATOMIC_USIZE_INIT;
+    fn upload(&mut self, location: &AtlasLocation, buffer: &[u8]) {
+        let page = &mut self.pages[location.page];
+        let bytes_per_pixel = page.format.bytes_per_pixel();
+        assert!(buffer.len() >= (location.rect.size.width * location.rect.size.height *
+                                 bytes_per_pixel) as usize);
 
-            let value = ATLAS_INDEX.fetch_add(1, Ordering::SeqCst);
-            write_tga(&format!("atlas{}.tga", value), buffer, &location.rect.size);
+        if let Some(texture) = page.texture {
+            // Rows of alpha data aren't necessarily a multiple of 4 bytes long.
+            gl::pixel_store_i(gl::UNPACK_ALIGNMENT, 1);
+            gl::bind_texture(gl::TEXTURE_2D, texture);
+            gl::tex_sub_image_2d(gl::TEXTURE_2D,
+                                 0,
+                                 location.rect.origin.x as GLint,
+                                 location.rect.origin.y as GLint,
+                                 location.rect.size.width as GLint,
+                                 location.rect.size.height as GLint,
+                                 page.format.gl_format(),
+                                 gl::UNSIGNED_BYTE,
+                                 buffer);
         }
 
-        Rc::new(RefCell::new(AtlasHandle {
-            location: *location,
-        }))
+        let row_length = (location.rect.size.width * bytes_per_pixel) as usize;
+        for y in 0..location.rect.size.height {
+            let source_start = (y as usize) * row_length;
+            let destination_start = (((location.rect.origin.y + y) * page.size.width +
+                                      location.rect.origin.x) * bytes_per_pixel) as usize;
+            for x in 0..row_length {
+                page.pixels[destination_start + x] = buffer[source_start + x]
+            }
+        }
     }
 }
 
-/// A reference to an object in the atlas.
+/// A reference to an object in the atlas. The object's space is reclaimed once all references to
+/// its handle are gone.
 pub struct AtlasHandle {
     pub location: AtlasLocation,
+    /// The atlas generation in which this object was last required.
+    pub last_used_generation: u64,
+    /// True if the object has been evicted to make room for others. Its location is no longer
+    /// valid.
+    pub evicted: bool,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub struct AtlasLocation {
+    /// The index of the page the object is on.
+    pub page: usize,
     pub rect: Rect<u32>,
 }
 
-/// Priority of assets in the atlas, from lowest to highest.
-#[derive(Copy, Clone, PartialEq)]
-pub enum Priority {
-    /// An item in the retained display list needs this asset.
-    Retained = 0,
-}
-
-struct Bin {
-    children: Option<[Box<Bin>; 2]>,
-    rect: Rect<u32>,
-    full: bool,
+/// The layout of the pixels of an atlas page or an asset's rasterization.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PixelFormat {
+    /// One byte per pixel, holding a distance or coverage value.
+    Alpha,
+    /// Four bytes per pixel: red, green, blue, and alpha.
+    Rgba,
 }
 
-impl Bin {
-    fn new(rect: &Rect<u32>) -> Bin {
-        Bin {
-            children: None,
-            rect: *rect,
-            full: false,
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> u32 {
+        match *self {
+            PixelFormat::Alpha => 1,
+            PixelFormat::Rgba => 4,
         }
     }
 
-    fn insert(&mut self, size: &Size2D<u32>) -> Option<Point2D<u32>> {
-        if let Some(ref mut children) = self.children {
-            let (left, right) = children.split_at_mut(1);
-            let (left, right) = (&mut left[0], &mut right[0]);
-            return left.insert(size).or_else(|| right.insert(size))
+    fn gl_format(&self) -> GLenum {
+        match *self {
+            PixelFormat::Alpha => gl::ALPHA,
+            PixelFormat::Rgba => gl::RGBA,
         }
+    }
+}
 
-        if self.full {
-            return None
-        }
+/// Priority of assets in the atlas, from lowest to highest.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Priority {
+    /// An item in the retained display list needs this asset.
+    Retained = 0,
+}
 
-        match (self.rect.size.width.cmp(&size.width), self.rect.size.height.cmp(&size.height)) {
-            (cmp::Ordering::Less, _) | (_, cmp::Ordering::Less) => return None,
-            (cmp::Ordering::Equal, cmp::Ordering::Equal) => {
-                self.full = true;
-                return Some(self.rect.origin)
-            }
-            _ => {}
-        }
+#[cfg(test)]
+mod tests {
+    use euclid::Size2D;
 
-        let left_child = Box::new(Bin::new(&Rect::new(self.rect.origin, *size)));
+    use super::{Atlas, HEIGHT, MAX_PAGES, PixelFormat, Priority, WIDTH};
 
-        let extra_width = self.rect.size.width - size.width;
-        let extra_height = self.rect.size.height - size.height;
-        let right_child = if extra_width > extra_height {
-            Box::new(Bin::new(&Rect::new(Point2D::new(self.rect.origin.x + size.width,
-                                                      self.rect.origin.y),
-                                         Size2D::new(self.rect.size.width - size.width,
-                                                     self.rect.size.height))))
-        } else {
-            Box::new(Bin::new(&Rect::new(Point2D::new(self.rect.origin.x,
-                                                      self.rect.origin.y + size.height),
-                                         Size2D::new(self.rect.size.width,
-                                                     self.rect.size.height - size.height))))
-        };
+    #[test]
+    fn objects_in_use_overflow_onto_standalone_pages() {
+        let mut atlas = Atlas::new_in_memory();
+        atlas.advance_generation();
 
-        self.children = Some([ left_child, right_child ]);
-        self.children.as_mut().unwrap()[0].insert(size)
-    }
-}
+        // Fill every shared page, and then some, with objects used in this generation.
+        let size = Size2D::new(64, 64);
+        let data = vec![0; 64 * 64];
+        let per_page = ((WIDTH / size.width) * (HEIGHT / size.height)) as usize;
+        let handles: Vec<_> = (0..(MAX_PAGES * per_page + 16)).map(|_| {
+            atlas.place(Priority::Retained, &size, PixelFormat::Alpha, &data[..])
+        }).collect();
 
-pub fn write_tga(name: &str, buffer: &[u8], size: &Size2D<u32>) {
-    let mut file = File::create(name).unwrap();
-    let mut header = [ 0; 18 ];
-    header[2] = 2;
-    header[12] = size.width as u8;
-    header[13] = (size.width >> 8) as u8;
-    header[14] = size.height as u8;
-    header[15] = (size.height >> 8) as u8;
-    header[16] = 24;
-    file.write(&header).unwrap();
-    for y in 0..(size.height as usize) {
-        let y = (size.height as usize) - y - 1;
-        for x in 0..(size.width as usize) {
-            let a = buffer[4 * (y * (size.width as usize) + x) + 3];
-            file.write(&[ a, a, a ]).unwrap();
+        assert!(handles.iter().all(|handle| !handle.borrow().evicted));
+        assert_eq!(atlas.pages.iter().filter(|page| !page.standalone).count(), MAX_PAGES);
+        assert!(atlas.pages.iter().any(|page| page.standalone));
+        for handle in handles.iter() {
+            let location = handle.borrow().location;
+            assert_eq!(location.rect.size, size);
+            assert!(atlas.page_size(location.page).width >= size.width);
         }
     }
 }
-
@@ -3,15 +3,23 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use assets::{ARC_RADIUS, Asset};
-use atlas::{self, Priority};
+use atlas::{AtlasLocation, Priority};
 use context::Context;
-use display_list::{Au, BLACK, ClippingRegion, Color, DisplayItem, TRANSPARENT_BLACK};
-use display_list::{TRANSPARENT_GREEN, TextDisplayItem, WHITE};
+use display_list::{Au, BLACK, ClippingRegion, Color, ComplexClippingRegion, DisplayItem};
+use display_list::{DisplayList, ExtendMode, GradientStop, ItemId, TRANSPARENT_GREEN, WHITE};
 use distance_field;
+use invalidation::{self, DisplayListDiff, DuplicateItemId};
 
 use euclid::{Point2D, Point3D, Rect, Size2D};
 use std::cell::RefCell;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+use std::f32;
 use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::rc::Rc;
 
 const NEAR_DEPTH_VALUE: f32 = -0.5;
 const FAR_DEPTH_VALUE: f32 = 0.5;
@@ -19,12 +27,59 @@ const FAR_DEPTH_VALUE: f32 = 0.5;
 const BUFFER: f32 = (distance_field::BUFFER as f32) / 255.0;
 const GAMMA: f32 = 0.005;
 
+/// The furthest, in device pixels, that the chords of radial gradient rings may stray from the
+/// ellipses they approximate.
+const MAX_RING_CHORD_ERROR: f32 = 0.25;
+const MIN_RING_SEGMENTS: usize = 16;
+const MAX_RING_SEGMENTS: usize = 256;
+/// The narrowest period, in device pixels, that repeating radial gradients are drawn ring by ring
+/// at. Finer ones are drawn in their average color.
+const MIN_REPEAT_PERIOD: f32 = 1.0;
+
+/// Retained batches are rebuilt instead of patched once more than this fraction of their vertices
+/// belong to removed items, so that churning items doesn't make them grow without bound.
+const MAX_DEAD_VERTEX_FRACTION: f32 = 0.5;
+
+/// How the fragment shader interprets the texel sampled for each vertex. Untextured items use
+/// `TEXTURE_MODE_NONE` and are drawn in their vertex color.
+pub const TEXTURE_MODE_NONE: f32 = 0.0;
+/// The alpha channel holds a single-channel distance field.
+pub const TEXTURE_MODE_DISTANCE_FIELD: f32 = 1.0;
+/// The color channels hold a multichannel distance field.
+pub const TEXTURE_MODE_MULTICHANNEL: f32 = 2.0;
+/// The alpha channel holds coverage, as for blurred glyphs.
+pub const TEXTURE_MODE_COVERAGE: f32 = 3.0;
+/// The texel is a premultiplied color, as for images.
+pub const TEXTURE_MODE_IMAGE: f32 = 4.0;
+
+/// How items are clipped to the rectangular part of their clipping regions.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ClipMode {
+    /// Quads are trimmed to the clip, adjusting their texture coordinates to match.
+    Geometry,
+    /// The clip is drawn into the stencil buffer, and quads are drawn whole, only where the
+    /// stencil is set. Clip quads are emitted only when the clip changes from one item to the
+    /// next.
+    Stencil,
+}
+
 pub struct Batch {
     pub vertices: Vec<Point3D<f32>>,
     pub colors: Vec<Color>,
     pub buffer_gamma: Vec<Point2D<f32>>,
     pub texture_coords: Vec<Point2D<f32>>,
+    pub texture_modes: Vec<f32>,
+    /// Vertex positions in device pixels from the top left, for clipping to rounded rectangles.
+    pub clip_coords: Vec<Point2D<f32>>,
+    /// The rounded rectangle that each vertex is clipped to, as the left, top, right, and bottom
+    /// edges in device pixels.
+    pub clip_rects: Vec<[f32; 4]>,
+    /// The corner radii of the rounded rectangle, in device pixels, in the order top left, top
+    /// right, bottom right, bottom left. All zero if the vertex isn't clipped to one.
+    pub clip_radii: Vec<[f32; 4]>,
     pub elements: Vec<u32>,
+    /// The atlas page that this batch's textured vertices refer to, if any.
+    pub page: Option<usize>,
 }
 
 impl Batch {
@@ -34,20 +89,64 @@ impl Batch {
             colors: Vec::new(),
             buffer_gamma: Vec::new(),
             texture_coords: Vec::new(),
+            texture_modes: Vec::new(),
+            clip_coords: Vec::new(),
+            clip_rects: Vec::new(),
+            clip_radii: Vec::new(),
             elements: Vec::new(),
+            page: None,
         }
     }
 
-    fn add_vertices_for_rect(&mut self, context: &Context, rect: &Rect<Au>, z_value: f32) {
+    /// Adds the vertices of a quad, along with the rounded rectangle they're clipped to, if any.
+    fn add_vertices_for_rect(&mut self,
+                             context: &Context,
+                             rect: &Rect<Au>,
+                             z_value: f32,
+                             rounded_clip: Option<&ComplexClippingRegion>) {
         let rect = rect.to_normalized_device_position(context);
         let one_pixel = Point2D::new(1.0 / (context.render_target_size.width as f32),
                                      1.0 / (context.render_target_size.height as f32));
-        self.vertices.extend([
-            Point3D::new(rect.origin.x, -rect.origin.y, z_value),
-            Point3D::new(rect.max_x() - one_pixel.x, -rect.origin.y, z_value),
-            Point3D::new(rect.origin.x, -(rect.max_y() - one_pixel.y), z_value),
-            Point3D::new(rect.max_x() - one_pixel.x, -(rect.max_y() - one_pixel.y), z_value),
-        ].iter());
+        self.add_vertices(context, &[
+            rect.origin,
+            Point2D::new(rect.max_x() - one_pixel.x, rect.origin.y),
+            Point2D::new(rect.origin.x, rect.max_y() - one_pixel.y),
+            Point2D::new(rect.max_x() - one_pixel.x, rect.max_y() - one_pixel.y),
+        ], z_value, rounded_clip)
+    }
+
+    /// Adds vertices at the given positions, in normalized device coordinates with the Y axis
+    /// pointing down, along with the rounded rectangle they're clipped to, if any.
+    fn add_vertices(&mut self,
+                    context: &Context,
+                    positions: &[Point2D<f32>],
+                    z_value: f32,
+                    rounded_clip: Option<&ComplexClippingRegion>) {
+        let size = &context.render_target_size;
+        for position in positions.iter() {
+            self.vertices.push(Point3D::new(position.x, -position.y, z_value));
+            self.clip_coords.push(Point2D::new((position.x + 1.0) * 0.5 * (size.width as f32),
+                                               (position.y + 1.0) * 0.5 * (size.height as f32)))
+        }
+        let (clip_rect, clip_radii) = match rounded_clip {
+            None => ([0.0; 4], [0.0; 4]),
+            Some(rounded_clip) => {
+                let (rect, radii) = (&rounded_clip.rect, &rounded_clip.radii);
+                ([
+                    rect.origin.x.to_device_px(context),
+                    rect.origin.y.to_device_px(context),
+                    (rect.origin.x + rect.size.width).to_device_px(context),
+                    (rect.origin.y + rect.size.height).to_device_px(context),
+                ], [
+                    radii.top_left.to_device_px(context),
+                    radii.top_right.to_device_px(context),
+                    radii.bottom_right.to_device_px(context),
+                    radii.bottom_left.to_device_px(context),
+                ])
+            }
+        };
+        self.clip_rects.extend(iter::repeat(clip_rect).take(positions.len()));
+        self.clip_radii.extend(iter::repeat(clip_radii).take(positions.len()));
     }
 
     fn add_solid_colors(&mut self, count: usize, color: &Color) {
@@ -62,20 +161,32 @@ impl Batch {
         self.add_buffer_gamma(count, 0.0, 0.0)
     }
 
-    fn add_texture_coords_for_rect(&mut self, texture_rect: &Rect<u32>) {
-        let (atlas_width, atlas_height) = (atlas::WIDTH as f32, atlas::HEIGHT as f32);
+    /// Adds texture coordinates for the part of a quad that survived clipping, given as a
+    /// fraction of the whole quad as returned by `clip_rect()`. `texture_rect` is on an atlas page
+    /// of the given size.
+    fn add_texture_coords_for_rect(&mut self,
+                                   page_size: &Size2D<u32>,
+                                   texture_rect: &Rect<u32>,
+                                   fraction: &Rect<f32>) {
+        let (atlas_width, atlas_height) = (page_size.width as f32, page_size.height as f32);
         let one_pixel = Point2D::new(1.0 / atlas_width, 1.0 / atlas_height);
         let texture_rect =
             Rect::new(Point2D::new((texture_rect.origin.x as f32 + 0.5) / atlas_width,
                                    (texture_rect.origin.y as f32 + 0.5) / atlas_height),
-                      Size2D::new((texture_rect.size.width as f32) / atlas_width,
-                                  (texture_rect.size.height as f32) / atlas_height));
-            //Rect::new(Point2D::new(0.0, 0.0), Size2D::new(1.0, 1.0));
+                      Size2D::new((texture_rect.size.width as f32) / atlas_width - one_pixel.x,
+                                  (texture_rect.size.height as f32) / atlas_height - one_pixel.y));
+        let texture_rect =
+            Rect::new(Point2D::new(texture_rect.origin.x +
+                                   texture_rect.size.width * fraction.origin.x,
+                                   texture_rect.origin.y +
+                                   texture_rect.size.height * fraction.origin.y),
+                      Size2D::new(texture_rect.size.width * fraction.size.width,
+                                  texture_rect.size.height * fraction.size.height));
         self.texture_coords.extend([
             texture_rect.origin,
-            Point2D::new(texture_rect.max_x() - one_pixel.x, texture_rect.origin.y),
-            Point2D::new(texture_rect.origin.x, texture_rect.max_y() - one_pixel.y),
-            Point2D::new(texture_rect.max_x() - one_pixel.x, texture_rect.max_y() - one_pixel.y),
+            Point2D::new(texture_rect.max_x(), texture_rect.origin.y),
+            Point2D::new(texture_rect.origin.x, texture_rect.max_y()),
+            Point2D::new(texture_rect.max_x(), texture_rect.max_y()),
         ].iter());
     }
 
@@ -83,6 +194,10 @@ impl Batch {
         self.texture_coords.extend(iter::repeat(Point2D::new(0.0, 0.0)).take(count))
     }
 
+    fn add_texture_modes(&mut self, count: usize, texture_mode: f32) {
+        self.texture_modes.extend(iter::repeat(texture_mode).take(count))
+    }
+
     fn add_elements_for_clockwise_wound_rect(&mut self) {
         let bottom_right = self.vertices.len() as u32 - 1;
         let bottom_left = bottom_right - 1;
@@ -113,222 +228,1457 @@ impl Batch {
         ].iter());
     }
 
-    // TODO(pcwalton): Only clear clips if we need to.
-    // TODO(pcwalton): Clip by adjusting vertices and texture coordinates for simple clips.
+    /// Adds elements drawing the convex polygon made of the last `count` vertices, which wind
+    /// counterclockwise, as a fan.
+    fn add_elements_for_counterclockwise_wound_polygon(&mut self, count: usize) {
+        let first = (self.vertices.len() - count) as u32;
+        for index in 1..(count as u32 - 1) {
+            self.elements.extend([ first, first + index, first + index + 1 ].iter());
+        }
+    }
+
+    /// Adds a back-facing quad that fails the depth test over the whole render target, zeroing
+    /// the stencil everywhere.
     fn clear_clip(&mut self, context: &Context) {
-        let rect = Rect::new(Point2D::new(Au::from_px(0), Au::from_px(0)),
-                             context.render_target_size.to_au());
-        self.add_vertices_for_rect(context, &rect, FAR_DEPTH_VALUE);
+        // Reach a pixel past every edge, since quads stop short of their bottom and right edges.
+        let size = context.render_target_size;
+        let to_au = |device_px: i32| {
+            Au::from_f64_px((device_px as f64) / (context.device_pixel_ratio as f64))
+        };
+        let rect = Rect::new(Point2D::new(to_au(-1), to_au(-1)),
+                             Size2D::new(to_au(size.width + 2), to_au(size.height + 2)));
+        self.add_vertices_for_rect(context, &rect, FAR_DEPTH_VALUE, None);
         self.add_solid_colors(4, &WHITE);
         self.add_dummy_buffer_gamma(4);
         self.add_dummy_texture_coords(4);
+        self.add_texture_modes(4, TEXTURE_MODE_NONE);
         self.add_elements_for_clockwise_wound_rect();
     }
 
-    // TODO(pcwalton): Only add clips if we need to.
-    // TODO(pcwalton): Clip by adjusting vertices and texture coordinates for simple clips.
-    fn add_clip(&mut self, context: &Context, clipping_region: &ClippingRegion) {
-        self.add_vertices_for_rect(context, &clipping_region.main, NEAR_DEPTH_VALUE);
+    /// Adds a back-facing quad that passes the depth test inside the clip, setting the stencil
+    /// there. Its color is transparent, so it draws nothing.
+    fn add_clip(&mut self, context: &Context, clip: &Rect<Au>) {
+        self.add_vertices_for_rect(context, clip, NEAR_DEPTH_VALUE, None);
         self.add_solid_colors(4, &TRANSPARENT_GREEN);
         self.add_dummy_buffer_gamma(4);
         self.add_dummy_texture_coords(4);
+        self.add_texture_modes(4, TEXTURE_MODE_NONE);
         self.add_elements_for_clockwise_wound_rect();
     }
 
-    fn add_solid_color_rect(&mut self, context: &Context, rect: &Rect<Au>, color: &Color) {
-        self.add_vertices_for_rect(context, rect, NEAR_DEPTH_VALUE);
+    fn add_solid_color_rect(&mut self,
+                            context: &Context,
+                            rect: &Rect<Au>,
+                            clip: &QuadClip,
+                            color: &Color) {
+        let clipped_rect = match clip_rect(rect, &clip.rect) {
+            None => return,
+            Some(clipped_rect) => clipped_rect,
+        };
+        self.add_vertices_for_rect(context,
+                                   &clipped_rect.rect,
+                                   NEAR_DEPTH_VALUE,
+                                   clip.rounded_rect.as_ref());
         self.add_solid_colors(4, color);
         self.add_dummy_buffer_gamma(4);
         self.add_dummy_texture_coords(4);
+        self.add_texture_modes(4, TEXTURE_MODE_NONE);
         self.add_elements_for_counterclockwise_wound_rect();
     }
 
     fn add_text(&mut self,
-                context: &mut Context,
+                context: &Context,
                 bounds: &Rect<Au>,
-                glyph_asset: &RefCell<Asset>,
-                blurred_glyph_asset: Option<&mut Asset>) {
-        context.asset_manager.atlas.borrow_mut().require_asset(&mut *glyph_asset.borrow_mut(),
-                                                               Priority::Retained);
-        match blurred_glyph_asset {
-            None => {
-                let atlas_handle = glyph_asset.borrow().get_atlas_handle();
-
-                self.add_vertices_for_rect(context, bounds, NEAR_DEPTH_VALUE);
-                self.add_solid_colors(4, &TRANSPARENT_BLACK);
-                self.add_buffer_gamma(4, BUFFER, GAMMA);
-                self.add_texture_coords_for_rect(&atlas_handle.borrow().location.rect);
-                self.add_elements_for_counterclockwise_wound_rect();
+                clip: &QuadClip,
+                color: &Color,
+                location: &AtlasLocation,
+                texture_mode: f32) {
+        let clipped_rect = match clip_rect(bounds, &clip.rect) {
+            None => return,
+            Some(clipped_rect) => clipped_rect,
+        };
+        self.add_vertices_for_rect(context,
+                                   &clipped_rect.rect,
+                                   NEAR_DEPTH_VALUE,
+                                   clip.rounded_rect.as_ref());
+        self.add_solid_colors(4, color);
+        if texture_mode == TEXTURE_MODE_COVERAGE {
+            self.add_dummy_buffer_gamma(4)
+        } else {
+            self.add_buffer_gamma(4, BUFFER, GAMMA)
+        }
+        let page_size = context.asset_manager.atlas.borrow().page_size(location.page);
+        self.add_texture_coords_for_rect(&page_size, &location.rect, &clipped_rect.fraction);
+        self.add_texture_modes(4, texture_mode);
+        self.add_elements_for_counterclockwise_wound_rect();
+    }
+
+    /// Adds a quad drawing the part of the image at `location` given by `source_rect`, in image
+    /// pixels, stretched over `bounds`.
+    fn add_image(&mut self,
+                 context: &Context,
+                 bounds: &Rect<Au>,
+                 clip: &QuadClip,
+                 source_rect: &Rect<u32>,
+                 location: &AtlasLocation) {
+        // Keep the source rectangle inside the image, so that it can't sample the image's
+        // neighbors on the atlas page.
+        let image_size = &location.rect.size;
+        let source_origin = Point2D::new(cmp::min(source_rect.origin.x, image_size.width),
+                                         cmp::min(source_rect.origin.y, image_size.height));
+        let source_size = Size2D::new(cmp::min(source_rect.size.width,
+                                               image_size.width - source_origin.x),
+                                      cmp::min(source_rect.size.height,
+                                               image_size.height - source_origin.y));
+        if source_size.width == 0 || source_size.height == 0 {
+            return
+        }
+
+        let clipped_rect = match clip_rect(bounds, &clip.rect) {
+            None => return,
+            Some(clipped_rect) => clipped_rect,
+        };
+        self.add_vertices_for_rect(context,
+                                   &clipped_rect.rect,
+                                   NEAR_DEPTH_VALUE,
+                                   clip.rounded_rect.as_ref());
+        self.add_solid_colors(4, &WHITE);
+        self.add_dummy_buffer_gamma(4);
+        let texture_rect = Rect::new(location.rect.origin + source_origin, source_size);
+        let page_size = context.asset_manager.atlas.borrow().page_size(location.page);
+        self.add_texture_coords_for_rect(&page_size, &texture_rect, &clipped_rect.fraction);
+        self.add_texture_modes(4, TEXTURE_MODE_IMAGE);
+        self.add_elements_for_counterclockwise_wound_rect();
+    }
+
+    /// Adds a linear gradient as a strip of polygons, one for each pair of adjacent color stops,
+    /// whose vertex colors interpolate between the stops. As in CSS, the gradient line runs
+    /// through the center of `bounds` at `angle` degrees clockwise from upward, and is just long
+    /// enough for its ends to reach the corners.
+    fn add_linear_gradient(&mut self,
+                           context: &Context,
+                           bounds: &Rect<Au>,
+                           clip: &QuadClip,
+                           angle: f32,
+                           stops: &[GradientStop]) {
+        let visible_rect = match invalidation::intersect_rects(bounds, &clip.rect) {
+            None => return,
+            Some(visible_rect) => visible_rect,
+        };
+        if stops.is_empty() {
+            return
+        }
+
+        let visible_polygon = device_polygon_for_rect(context, &visible_rect);
+        let origin = bounds.origin.to_device_point(context);
+        let (width, height) = (bounds.size.width.to_device_px(context),
+                               bounds.size.height.to_device_px(context));
+        let angle = angle.to_radians();
+        let direction = Point2D::new(f32::sin(angle), -f32::cos(angle));
+        let length = f32::abs(width * direction.x) + f32::abs(height * direction.y);
+        if length <= 0.0 {
+            return
+        }
+        let start = Point2D::new(origin.x + width * 0.5 - direction.x * length * 0.5,
+                                 origin.y + height * 0.5 - direction.y * length * 0.5);
+        let offset_at = |point: &Point2D<f32>| {
+            ((point.x - start.x) * direction.x + (point.y - start.y) * direction.y) / length
+        };
+
+        let stops = split_gradient_stops(&extend_gradient_stops(stops, 1.0, ExtendMode::Clamp));
+
+        for pair in stops.windows(2) {
+            let (start_stop, end_stop) = (&pair[0], &pair[1]);
+            if end_stop.offset <= start_stop.offset {
+                continue
+            }
+            let polygon = clip_polygon(&visible_polygon,
+                                       |point| offset_at(point) - start_stop.offset);
+            let polygon = clip_polygon(&polygon, |point| end_stop.offset - offset_at(point));
+            self.add_gradient_polygon(context, &polygon, clip, |point| {
+                let fraction = (offset_at(point) - start_stop.offset) /
+                    (end_stop.offset - start_stop.offset);
+                lerp_color(&start_stop.color, &end_stop.color, fraction)
+            })
+        }
+    }
+
+    /// Adds a radial gradient as rings of polygons, one ring for each pair of adjacent color
+    /// stops, whose vertex colors interpolate between the stops. The gradient's ending shape is
+    /// the ellipse with the given radii about `center`, and offsets are measured outward from
+    /// `center` as fractions of the way to it, as in CSS.
+    fn add_radial_gradient(&mut self,
+                           context: &Context,
+                           bounds: &Rect<Au>,
+                           clip: &QuadClip,
+                           center: &Point2D<Au>,
+                           radii: &Size2D<Au>,
+                           stops: &[GradientStop],
+                           extend_mode: ExtendMode) {
+        let visible_rect = match invalidation::intersect_rects(bounds, &clip.rect) {
+            None => return,
+            Some(visible_rect) => visible_rect,
+        };
+        if stops.is_empty() {
+            return
+        }
+
+        let visible_polygon = device_polygon_for_rect(context, &visible_rect);
+        let center = center.to_device_point(context);
+        let radii = Size2D::new(radii.width.to_device_px(context),
+                                radii.height.to_device_px(context));
+        if radii.width <= 0.0 || radii.height <= 0.0 {
+            // The ending shape is degenerate, so everything is past the last stop.
+            let last_color = stops[stops.len() - 1].color;
+            self.add_gradient_polygon(context, &visible_polygon, clip, |_| last_color);
+            return
+        }
+
+        // Rings finer than a pixel can't be told apart, and there would be no bound on how many
+        // there are, so draw the gradient in its average color instead.
+        let period = stops[stops.len() - 1].offset - stops[0].offset;
+        if extend_mode == ExtendMode::Repeat && period > 0.0 &&
+                period * f32::min(radii.width, radii.height) < MIN_REPEAT_PERIOD {
+            let average_color = average_gradient_color(stops);
+            self.add_gradient_polygon(context, &visible_polygon, clip, |_| average_color);
+            return
+        }
+
+        let offset_at = |point: &Point2D<f32>| {
+            let (x, y) = ((point.x - center.x) / radii.width, (point.y - center.y) / radii.height);
+            f32::sqrt(x * x + y * y)
+        };
+
+        // Rings need to reach as far as the farthest corner of the visible area.
+        let max_offset = visible_polygon.iter().map(|point| offset_at(point)).fold(0.0, f32::max);
+        let stops = split_gradient_stops(&extend_gradient_stops(stops, max_offset, extend_mode));
+
+        // Use enough segments that the chords of the largest ring stray from it by no more than a
+        // quarter of a device pixel.
+        let max_radius = f32::max(radii.width, radii.height) * max_offset;
+        let segment_count = if max_radius <= MAX_RING_CHORD_ERROR {
+            MIN_RING_SEGMENTS
+        } else {
+            let segment_angle = f32::acos(1.0 - MAX_RING_CHORD_ERROR / max_radius);
+            let segment_count = f32::ceil(PI / segment_angle) as usize;
+            cmp::max(MIN_RING_SEGMENTS, cmp::min(segment_count, MAX_RING_SEGMENTS))
+        };
+        let ring_point = |offset: f32, segment: usize| {
+            let angle = (segment as f32) * 2.0 * PI / (segment_count as f32);
+            Point2D::new(center.x + f32::cos(angle) * radii.width * offset,
+                         center.y + f32::sin(angle) * radii.height * offset)
+        };
+
+        for pair in stops.windows(2) {
+            let (start_stop, end_stop) = (&pair[0], &pair[1]);
+            let (inner_offset, outer_offset) = (f32::max(start_stop.offset, 0.0), end_stop.offset);
+            if outer_offset <= inner_offset {
+                continue
             }
-            Some(blurred_glyph_asset) => {
-                // TODO(pcwalton): We should have a service that automatically starts rasterizing
-                // dependencies so we don't have to block on it here!
-                context.asset_manager.start_rasterizing_asset_if_necessary(blurred_glyph_asset);
-                context.asset_manager.atlas.borrow_mut().require_asset(blurred_glyph_asset,
-                                                                       Priority::Retained);
-                let atlas_handle = blurred_glyph_asset.get_atlas_handle();
-
-                self.add_vertices_for_rect(context, bounds, NEAR_DEPTH_VALUE);
-                self.add_solid_colors(4, &TRANSPARENT_BLACK);
-                self.add_dummy_buffer_gamma(4);
-                self.add_texture_coords_for_rect(&atlas_handle.borrow().location.rect);
-                self.add_elements_for_counterclockwise_wound_rect();
+            let color_at = |point: &Point2D<f32>| {
+                let fraction = (offset_at(point) - start_stop.offset) /
+                    (end_stop.offset - start_stop.offset);
+                lerp_color(&start_stop.color, &end_stop.color, fraction)
+            };
+
+            // Angles increase clockwise on the screen, so go around each piece the other way.
+            for segment in 0..segment_count {
+                let piece = if inner_offset == 0.0 {
+                    vec![
+                        center,
+                        ring_point(outer_offset, segment + 1),
+                        ring_point(outer_offset, segment),
+                    ]
+                } else {
+                    vec![
+                        ring_point(inner_offset, segment),
+                        ring_point(inner_offset, segment + 1),
+                        ring_point(outer_offset, segment + 1),
+                        ring_point(outer_offset, segment),
+                    ]
+                };
+                let piece = clip_polygon_to_polygon(&piece, &visible_polygon);
+                self.add_gradient_polygon(context, &piece, clip, &color_at)
             }
         }
     }
 
-    fn add_border(&mut self,
-                  context: &mut Context,
-                  bounds: &Rect<Au>,
-                  width: Au,
-                  color: &Color,
-                  radius: Au,
-                  arc_asset: &mut Asset,
-                  inverted_arc_asset: &mut Asset) {
-        context.asset_manager.atlas.borrow_mut().require_asset(arc_asset, Priority::Retained);
-        context.asset_manager.atlas.borrow_mut().require_asset(inverted_arc_asset,
-                                                               Priority::Retained);
-
-        let arc_atlas_handle = arc_asset.get_atlas_handle();
-        let inverted_arc_atlas_handle = inverted_arc_asset.get_atlas_handle();
-
-        // +---+-------+       +-+---+
-        // |  /|#######|###    |1| 2 |
-        // | /#|#######|###    +-+---+
-        // |/##|#######|###    |  3  |
-        // +---+-------+###    +---+-+
-        // |###########|###    | 4 |5|
-        // |###########|###    +---+-+
-        // |###########|###
-        // +-------+---+
-        // |#######|##/|
-        // |#######|#/ |
-        // |#######|/  |
-        // +-------+---+
-        //  #######
-        //  #######
-        //  #######
-
-        // 1
+    /// Adds a convex polygon, given in device pixels from the top left and wound counterclockwise
+    /// on the screen, with each vertex in the color that `color_at` returns for it. Polygons with
+    /// fewer than three vertices, such as those clipped out entirely, are skipped.
+    fn add_gradient_polygon<F>(&mut self,
+                               context: &Context,
+                               polygon: &[Point2D<f32>],
+                               clip: &QuadClip,
+                               color_at: F)
+                               where F: Fn(&Point2D<f32>) -> Color {
+        if polygon.len() < 3 {
+            return
+        }
+
+        let (target_width, target_height) = (context.render_target_size.width as f32,
+                                             context.render_target_size.height as f32);
+        let positions: Vec<Point2D<f32>> = polygon.iter().map(|point| {
+            Point2D::new(point.x / target_width * 2.0 - 1.0, point.y / target_height * 2.0 - 1.0)
+        }).collect();
+        self.add_vertices(context, &positions[..], NEAR_DEPTH_VALUE, clip.rounded_rect.as_ref());
+        self.colors.extend(polygon.iter().map(|point| color_at(point)));
+        self.add_dummy_buffer_gamma(polygon.len());
+        self.add_dummy_texture_coords(polygon.len());
+        self.add_texture_modes(polygon.len(), TEXTURE_MODE_NONE);
+        self.add_elements_for_counterclockwise_wound_polygon(polygon.len());
+    }
+
+    // Borders are drawn in five pieces, as below. The two corner pieces (1 and 5) are arcs from
+    // the atlas, which may live on different pages; the other three are solid colors.
+    //
+    // +---+-------+       +-+---+
+    // |  /|#######|###    |1| 2 |
+    // | /#|#######|###    +-+---+
+    // |/##|#######|###    |  3  |
+    // +---+-------+###    +---+-+
+    // |###########|###    | 4 |5|
+    // |###########|###    +---+-+
+    // |###########|###
+    // +-------+---+
+    // |#######|##/|
+    // |#######|#/ |
+    // |#######|/  |
+    // +-------+---+
+    //  #######
+    //  #######
+    //  #######
+
+    /// Adds piece 1 of a border.
+    fn add_border_outer_corner(&mut self,
+                               context: &Context,
+                               bounds: &Rect<Au>,
+                               clip: &QuadClip,
+                               color: &Color,
+                               radius: Au,
+                               arc_location: &AtlasLocation) {
         let outer_corner_rect = Rect::new(bounds.origin, Size2D::new(radius, radius));
-        self.add_vertices_for_rect(context, &outer_corner_rect, NEAR_DEPTH_VALUE);
-        let arc_rect = &arc_atlas_handle.borrow().location.rect;
+        let clipped_rect = match clip_rect(&outer_corner_rect, &clip.rect) {
+            None => return,
+            Some(clipped_rect) => clipped_rect,
+        };
+        self.add_vertices_for_rect(context,
+                                   &clipped_rect.rect,
+                                   NEAR_DEPTH_VALUE,
+                                   clip.rounded_rect.as_ref());
+        let arc_rect = &arc_location.rect;
         let arc_rect = Rect::new(arc_rect.bottom_right() - Point2D::new(ARC_RADIUS, ARC_RADIUS),
                                  Size2D::new(ARC_RADIUS, ARC_RADIUS));
-        self.add_texture_coords_for_rect(&arc_rect);
-        self.add_solid_colors(4, &TRANSPARENT_BLACK);
+        let page_size = context.asset_manager.atlas.borrow().page_size(arc_location.page);
+        self.add_texture_coords_for_rect(&page_size, &arc_rect, &clipped_rect.fraction);
+        self.add_texture_modes(4, TEXTURE_MODE_DISTANCE_FIELD);
+        self.add_solid_colors(4, color);
         self.add_buffer_gamma(4, BUFFER, GAMMA);
         self.add_elements_for_counterclockwise_wound_rect();
+    }
 
-        // 2
+    /// Adds pieces 2, 3, and 4 of a border.
+    fn add_border_edges(&mut self,
+                        context: &Context,
+                        bounds: &Rect<Au>,
+                        clip: &QuadClip,
+                        width: Au,
+                        color: &Color,
+                        radius: Au) {
         let top_corner_rect = Rect::new(bounds.origin + Point2D::new(radius, Au(0)),
                                         Size2D::new(width, radius));
-        self.add_vertices_for_rect(context, &top_corner_rect, NEAR_DEPTH_VALUE);
-        self.add_dummy_texture_coords(4);
-        self.add_solid_colors(4, color);
-        self.add_dummy_buffer_gamma(4);
-        self.add_elements_for_counterclockwise_wound_rect();
-
-        // 3
         let corner_center_rect = Rect::new(bounds.origin + Point2D::new(Au(0), radius),
                                            Size2D::new(width + radius, width - radius));
-        self.add_vertices_for_rect(context, &corner_center_rect, NEAR_DEPTH_VALUE);
-        self.add_dummy_texture_coords(4);
-        self.add_solid_colors(4, color);
-        self.add_dummy_buffer_gamma(4);
-        self.add_elements_for_counterclockwise_wound_rect();
-
-        // 4
         let left_corner_rect = Rect::new(bounds.origin + Point2D::new(Au(0), width),
                                         Size2D::new(width, radius));
-        self.add_vertices_for_rect(context, &left_corner_rect, NEAR_DEPTH_VALUE);
-        self.add_dummy_texture_coords(4);
-        self.add_solid_colors(4, color);
-        self.add_dummy_buffer_gamma(4);
-        self.add_elements_for_counterclockwise_wound_rect();
+        for rect in [top_corner_rect, corner_center_rect, left_corner_rect].iter() {
+            self.add_solid_color_rect(context, rect, clip, color)
+        }
+    }
 
-        // 5
+    /// Adds piece 5 of a border.
+    fn add_border_inner_corner(&mut self,
+                               context: &Context,
+                               bounds: &Rect<Au>,
+                               clip: &QuadClip,
+                               color: &Color,
+                               width: Au,
+                               radius: Au,
+                               inverted_arc_location: &AtlasLocation) {
         let inner_corner_rect = Rect::new(bounds.origin + Point2D::new(width, width),
                                           Size2D::new(radius, radius));
-        self.add_vertices_for_rect(context, &inner_corner_rect, NEAR_DEPTH_VALUE);
-        let inverted_arc_rect = &inverted_arc_atlas_handle.borrow().location.rect;
+        let clipped_rect = match clip_rect(&inner_corner_rect, &clip.rect) {
+            None => return,
+            Some(clipped_rect) => clipped_rect,
+        };
+        self.add_vertices_for_rect(context,
+                                   &clipped_rect.rect,
+                                   NEAR_DEPTH_VALUE,
+                                   clip.rounded_rect.as_ref());
+        let inverted_arc_rect = &inverted_arc_location.rect;
         let inverted_arc_rect =
             Rect::new(inverted_arc_rect.bottom_right() - Point2D::new(ARC_RADIUS, ARC_RADIUS),
                       Size2D::new(ARC_RADIUS, ARC_RADIUS));
-        self.add_texture_coords_for_rect(&inverted_arc_rect);
-        self.add_solid_colors(4, &TRANSPARENT_BLACK);
+        let page_size =
+            context.asset_manager.atlas.borrow().page_size(inverted_arc_location.page);
+        self.add_texture_coords_for_rect(&page_size, &inverted_arc_rect, &clipped_rect.fraction);
+        self.add_texture_modes(4, TEXTURE_MODE_DISTANCE_FIELD);
+        self.add_solid_colors(4, color);
         self.add_buffer_gamma(4, BUFFER, GAMMA);
         self.add_elements_for_counterclockwise_wound_rect();
     }
 
+    /// Returns the device pixels, from the top left, that this batch's vertices span, or `None` if
+    /// it has none. Batches that set a stencil clip also clear it over the whole render target, so
+    /// they span all of it.
+    pub fn device_bounds(&self) -> Option<Rect<i32>> {
+        let mut points = self.clip_coords.iter();
+        let first_point = match points.next() {
+            None => return None,
+            Some(first_point) => *first_point,
+        };
+        let (mut min, mut max) = (first_point, first_point);
+        for point in points {
+            min = Point2D::new(f32::min(min.x, point.x), f32::min(min.y, point.y));
+            max = Point2D::new(f32::max(max.x, point.x), f32::max(max.y, point.y))
+        }
+        let origin = Point2D::new(f32::floor(min.x) as i32, f32::floor(min.y) as i32);
+        Some(Rect::new(origin, Size2D::new(f32::ceil(max.x) as i32 - origin.x,
+                                           f32::ceil(max.y) as i32 - origin.y)))
+    }
+
     pub fn vertex_count(&self) -> usize {
         self.vertices.len()
     }
+
+    /// Moves the given vertices onto a single point, so that the triangles using them draw
+    /// nothing.
+    fn collapse_vertices(&mut self, vertices: &Range<usize>) {
+        let point = self.vertices[vertices.start];
+        for vertex in self.vertices[vertices.clone()].iter_mut() {
+            *vertex = point
+        }
+    }
+
+    /// Overwrites the vertices starting at `start` with the given vertices of `other`.
+    fn copy_vertices(&mut self, start: usize, other: &Batch, other_vertices: &Range<usize>) {
+        let end = start + other_vertices.len();
+        self.vertices[start..end].clone_from_slice(&other.vertices[other_vertices.clone()]);
+        self.colors[start..end].clone_from_slice(&other.colors[other_vertices.clone()]);
+        self.buffer_gamma[start..end].clone_from_slice(&other.buffer_gamma[other_vertices.clone()]);
+        self.texture_coords[start..end]
+            .clone_from_slice(&other.texture_coords[other_vertices.clone()]);
+        self.texture_modes[start..end]
+            .clone_from_slice(&other.texture_modes[other_vertices.clone()]);
+        self.clip_coords[start..end].clone_from_slice(&other.clip_coords[other_vertices.clone()]);
+        self.clip_rects[start..end].clone_from_slice(&other.clip_rects[other_vertices.clone()]);
+        self.clip_radii[start..end].clone_from_slice(&other.clip_radii[other_vertices.clone()]);
+    }
 }
 
+/// Sorts display items into batches. A new batch is started whenever an item needs a different
+/// atlas page from the one the current batch uses.
 pub struct Batcher {
+    batches: Vec<Batch>,
     pending_batch: Batch,
+    /// The clip that the stencil holds after the batches so far, if it's known.
+    stencil_clip: Option<Rect<Au>>,
 }
 
 impl Batcher {
     pub fn new() -> Batcher {
         Batcher {
+            batches: Vec::new(),
             pending_batch: Batch::new(),
+            stencil_clip: None,
         }
     }
 
     pub fn add(&mut self, context: &mut Context, display_item: &mut DisplayItem) {
-        /*self.pending_batch.clear_clip(context);
-        self.pending_batch.add_clip(context, &display_item.base().clip);*/
+        // Items are clipped to rectangles by trimming their quads, so those wholly outside the
+        // clip draw nothing. Rounded corners are left to the fragment shader.
+        let device_pixel =
+            cmp::max(Au::from_f64_px(1.0 / (context.device_pixel_ratio as f64)), Au(1));
+        let clips = quad_clips(&display_item.base().bounds,
+                               &display_item.base().clip,
+                               device_pixel);
+        for clip in clips.into_iter() {
+            let clip = match context.clip_mode {
+                ClipMode::Geometry => clip,
+                ClipMode::Stencil => {
+                    self.set_stencil_clip(context, &clip.rect);
+                    QuadClip {
+                        rect: ClippingRegion::max().main,
+                        rounded_rect: clip.rounded_rect,
+                    }
+                }
+            };
+            self.add_clipped(context, display_item, &clip)
+        }
+    }
 
+    fn add_clipped(&mut self,
+                   context: &mut Context,
+                   display_item: &mut DisplayItem,
+                   clip: &QuadClip) {
         match *display_item {
             DisplayItem::SolidColor(ref mut solid_color_display_item) => {
                 self.pending_batch.add_solid_color_rect(context,
                                                         &solid_color_display_item.base.bounds,
+                                                        clip,
                                                         &solid_color_display_item.color);
             }
             DisplayItem::Text(ref mut text_display_item) => {
                 let text_display_item = &mut **text_display_item;
-                match *text_display_item {
-                    TextDisplayItem {
-                        base: ref mut base,
-                        glyph_asset: ref mut glyph_asset,
-                        blurred_glyph_asset: None,
-                        ..
-                    } => {
-                        self.pending_batch.add_text(context, &base.bounds, &*glyph_asset, None);
+                let bounds = text_display_item.base.bounds;
+                let color = text_display_item.color;
+                match text_display_item.blurred_glyph_asset {
+                    None => {
+                        let glyph_location =
+                            require_asset(context,
+                                          &mut *text_display_item.glyph_asset.borrow_mut());
+                        let texture_mode = if text_display_item.glyph_asset
+                                                               .borrow()
+                                                               .description()
+                                                               .is_multichannel() {
+                            TEXTURE_MODE_MULTICHANNEL
+                        } else {
+                            TEXTURE_MODE_DISTANCE_FIELD
+                        };
+                        self.batch_for_page(glyph_location.page).add_text(context,
+                                                                          &bounds,
+                                                                          clip,
+                                                                          &color,
+                                                                          &glyph_location,
+                                                                          texture_mode);
                     }
-                    TextDisplayItem {
-                        base: ref mut base,
-                        glyph_asset: ref mut glyph_asset,
-                        blurred_glyph_asset: Some(ref mut blurred_glyph_asset),
-                        ..
-                    } => {
-                        self.pending_batch.add_text(context,
-                                                    &base.bounds,
-                                                    &*glyph_asset,
-                                                    Some(&mut blurred_glyph_asset.borrow_mut()));
+                    Some(ref blurred_glyph_asset) => {
+                        let mut blurred_glyph_asset = blurred_glyph_asset.borrow_mut();
+
+                        // TODO(pcwalton): We should have a service that automatically starts
+                        // rasterizing dependencies so we don't have to block on it here!
+                        context.asset_manager
+                               .start_rasterizing_asset_if_necessary(&mut *blurred_glyph_asset);
+                        let location = require_asset(context, &mut *blurred_glyph_asset);
+                        self.batch_for_page(location.page).add_text(context,
+                                                                    &bounds,
+                                                                    clip,
+                                                                    &color,
+                                                                    &location,
+                                                                    TEXTURE_MODE_COVERAGE);
                     }
                 }
             }
             DisplayItem::Border(ref mut border_display_item) => {
-                self.pending_batch.add_border(context,
-                                              &border_display_item.base.bounds,
-                                              border_display_item.width,
-                                              &border_display_item.color,
-                                              border_display_item.radius,
-                                              &mut *border_display_item.arc_asset.borrow_mut(),
-                                              &mut *border_display_item.inverted_arc_asset
-                                                                       .borrow_mut());
+                let border_display_item = &mut **border_display_item;
+                let bounds = &border_display_item.base.bounds;
+                let arc_location =
+                    require_asset(context, &mut *border_display_item.arc_asset.borrow_mut());
+                let inverted_arc_location =
+                    require_asset(context,
+                                  &mut *border_display_item.inverted_arc_asset.borrow_mut());
+
+                self.batch_for_page(arc_location.page)
+                    .add_border_outer_corner(context,
+                                             bounds,
+                                             clip,
+                                             &border_display_item.color,
+                                             border_display_item.radius,
+                                             &arc_location);
+                self.pending_batch.add_border_edges(context,
+                                                    bounds,
+                                                    clip,
+                                                    border_display_item.width,
+                                                    &border_display_item.color,
+                                                    border_display_item.radius);
+                self.batch_for_page(inverted_arc_location.page)
+                    .add_border_inner_corner(context,
+                                             bounds,
+                                             clip,
+                                             &border_display_item.color,
+                                             border_display_item.width,
+                                             border_display_item.radius,
+                                             &inverted_arc_location);
+            }
+            DisplayItem::LinearGradient(ref linear_gradient_display_item) => {
+                self.pending_batch.add_linear_gradient(context,
+                                                       &linear_gradient_display_item.base.bounds,
+                                                       clip,
+                                                       linear_gradient_display_item.angle,
+                                                       &linear_gradient_display_item.stops);
+            }
+            DisplayItem::RadialGradient(ref radial_gradient_display_item) => {
+                let radial_gradient_display_item = &**radial_gradient_display_item;
+                let bounds = &radial_gradient_display_item.base.bounds;
+                let center = bounds.origin + radial_gradient_display_item.center;
+                self.pending_batch.add_radial_gradient(context,
+                                                       bounds,
+                                                       clip,
+                                                       &center,
+                                                       &radial_gradient_display_item.radii,
+                                                       &radial_gradient_display_item.stops,
+                                                       radial_gradient_display_item.extend_mode);
+            }
+            DisplayItem::Image(ref mut image_display_item) => {
+                let image_display_item = &mut **image_display_item;
+                let location =
+                    require_asset(context, &mut *image_display_item.image_asset.borrow_mut());
+                self.batch_for_page(location.page).add_image(context,
+                                                             &image_display_item.base.bounds,
+                                                             clip,
+                                                             &image_display_item.source_rect,
+                                                             &location);
+            }
+        }
+    }
+
+    /// Continues batching after the given batches, as though their items had been added to this
+    /// batcher.
+    fn resume(mut batches: Vec<Batch>) -> Batcher {
+        let pending_batch = batches.pop().unwrap_or_else(Batch::new);
+        Batcher {
+            batches: batches,
+            pending_batch: pending_batch,
+            stencil_clip: None,
+        }
+    }
+
+    /// Makes the stencil hold the given clip, adding clip quads unless it already does.
+    fn set_stencil_clip(&mut self, context: &Context, clip: &Rect<Au>) {
+        if self.stencil_clip == Some(*clip) {
+            return
+        }
+        self.pending_batch.clear_clip(context);
+        self.pending_batch.add_clip(context, clip);
+        self.stencil_clip = Some(*clip)
+    }
+
+    /// Adds the display item and returns where its vertices went. Batch indices are those in the
+    /// result of `finish()`.
+    fn add_and_locate(&mut self, context: &mut Context, display_item: &mut DisplayItem)
+                      -> Vec<ItemRange> {
+        let first_batch = self.batches.len();
+        let vertex_start = self.pending_batch.vertices.len();
+        let element_start = self.pending_batch.elements.len();
+        self.add(context, display_item);
+
+        let mut ranges = vec![];
+        for batch_index in first_batch..(self.batches.len() + 1) {
+            let batch = if batch_index < self.batches.len() {
+                &self.batches[batch_index]
+            } else {
+                &self.pending_batch
+            };
+            let (vertex_start, element_start) = if batch_index == first_batch {
+                (vertex_start, element_start)
+            } else {
+                (0, 0)
+            };
+            if batch.vertices.len() > vertex_start {
+                ranges.push(ItemRange {
+                    batch: batch_index,
+                    vertices: vertex_start..batch.vertices.len(),
+                    elements: element_start..batch.elements.len(),
+                })
             }
         }
+        ranges
     }
 
-    pub fn finish(self) -> Vec<Batch> {
-        vec![self.pending_batch]
+    pub fn finish(mut self) -> Vec<Batch> {
+        if !self.pending_batch.elements.is_empty() || self.batches.is_empty() {
+            self.batches.push(self.pending_batch)
+        }
+        self.batches
+    }
+
+    /// Returns a batch that can draw from the given atlas page, starting a new one if the pending
+    /// batch already draws from a different page.
+    fn batch_for_page(&mut self, page: usize) -> &mut Batch {
+        let needs_new_batch = match self.pending_batch.page {
+            Some(pending_page) => pending_page != page,
+            None => false,
+        };
+        if needs_new_batch {
+            let batch = mem::replace(&mut self.pending_batch, Batch::new());
+            self.batches.push(batch);
+        }
+        self.pending_batch.page = Some(page);
+        &mut self.pending_batch
     }
 }
 
+/// Starts a new atlas generation, makes sure every asset the display list needs is being
+/// rasterized, and sorts its items into batches.
+pub fn batch_display_list(context: &mut Context, display_list: &mut DisplayList) -> Vec<Batch> {
+    context.asset_manager.start_rasterizing_assets_in_display_list_as_necessary(display_list);
+    context.asset_manager.atlas.borrow_mut().advance_generation();
+    build_batches(context, display_list)
+}
+
+/// Sorts the display list's items into batches, waiting for and uploading assets as they're
+/// needed.
+pub fn build_batches(context: &mut Context, display_list: &mut DisplayList) -> Vec<Batch> {
+    let mut batcher = Batcher::new();
+    for item in display_list.items.iter_mut() {
+        batcher.add(context, item)
+    }
+    batcher.finish()
+}
+
+/// Where some of a display item's vertices are in the batches.
+#[derive(Clone, Debug)]
+struct ItemRange {
+    batch: usize,
+    vertices: Range<usize>,
+    elements: Range<usize>,
+}
+
+/// What the retained batches know about one display item.
+struct RetainedItem {
+    ranges: Vec<ItemRange>,
+    /// Where the item's assets were in the atlas when its vertices were generated.
+    asset_locations: Vec<AtlasLocation>,
+}
+
+/// Batches kept from one frame to the next along with the display list they were built from, so
+/// that a new version of the display list can be applied by patching the vertices of the items
+/// that changed instead of rebuilding everything.
+pub struct RetainedBatches {
+    display_list: DisplayList,
+    batches: Vec<Batch>,
+    items: HashMap<ItemId, RetainedItem>,
+    /// The number of vertices of removed items that are still in the batches, collapsed so that
+    /// they draw nothing.
+    dead_vertex_count: usize,
+}
+
+impl RetainedBatches {
+    pub fn new(context: &mut Context, display_list: DisplayList) -> RetainedBatches {
+        let mut retained_batches = RetainedBatches {
+            display_list: DisplayList {
+                items: vec![],
+            },
+            batches: vec![],
+            items: HashMap::new(),
+            dead_vertex_count: 0,
+        };
+        retained_batches.update(context, display_list);
+        retained_batches
+    }
+
+    pub fn batches(&self) -> &[Batch] {
+        &self.batches[..]
+    }
+
+    pub fn display_list(&self) -> &DisplayList {
+        &self.display_list
+    }
+
+    /// Replaces the retained display list with a new version of it and brings the batches up to
+    /// date. Returns the differences between the two versions.
+    pub fn update(&mut self, context: &mut Context, mut display_list: DisplayList)
+                  -> DisplayListDiff {
+        // Display lists whose items share IDs can't be diffed or patched, so they're rebuilt.
+        let (diff, patchable) = match invalidation::diff(&self.display_list, &display_list) {
+            Ok(diff) => (diff, true),
+            Err(DuplicateItemId(id)) => {
+                warn!("two display items have the ID {:?}; rebuilding the batches", id);
+                (invalidation::diff_replacing_everything(&self.display_list, &display_list), false)
+            }
+        };
+
+        // Require every asset before generating any vertices, so that making room in the atlas
+        // for new assets can't evict those of items whose vertices are kept. Compacting the atlas
+        // first moves assets around, but `patch()` regenerates the vertices of every item whose
+        // assets moved.
+        context.asset_manager.start_rasterizing_assets_in_display_list_as_necessary(
+            &mut display_list);
+        context.asset_manager.finish_rasterizing_assets_in_display_list(&mut display_list);
+        context.asset_manager.atlas.borrow_mut().advance_generation();
+        if context.asset_manager.compact_atlas_if_fragmented() {
+            debug!("compacted the atlas");
+        }
+        context.asset_manager.upload_assets_in_display_list(&display_list, &context.debug_dump);
+
+        if patchable && self.patch(context, &mut display_list, &diff) {
+            debug!("patched batches: {} added, {} removed, {} changed",
+                   diff.added.len(),
+                   diff.removed.len(),
+                   diff.changed.len());
+        } else {
+            debug!("rebuilding batches");
+            self.items.clear();
+            self.batches.clear();
+            self.dead_vertex_count = 0;
+            self.append(context, &mut display_list.items[..]);
+        }
+        self.display_list = display_list;
+        diff
+    }
+
+    /// Tries to bring the batches up to date without rebuilding them, and returns false if that
+    /// isn't possible: when items moved in the painting order, when items were added anywhere
+    /// but at the end, or when a changed item's vertices don't fit where its old ones were. It
+    /// also returns false when too many of the vertices would belong to removed items, so that
+    /// rebuilding reclaims them.
+    fn patch(&mut self,
+             context: &mut Context,
+             display_list: &mut DisplayList,
+             diff: &DisplayListDiff)
+             -> bool {
+        // With stencil clipping, items rely on the clip quads of the items before them that have
+        // the same clip, which removing or changing those items would take away.
+        if context.clip_mode == ClipMode::Stencil &&
+                (!diff.removed.is_empty() || !diff.changed.is_empty()) {
+            return false
+        }
+
+        let added: HashSet<ItemId> = diff.added.iter().cloned().collect();
+        let removed: HashSet<ItemId> = diff.removed.iter().cloned().collect();
+        let changed: HashSet<ItemId> = diff.changed.iter().cloned().collect();
+
+        let kept_ids: Vec<ItemId> = display_list.items
+                                                .iter()
+                                                .map(|item| item.id())
+                                                .take_while(|id| !added.contains(id))
+                                                .collect();
+        if kept_ids.len() + added.len() != display_list.items.len() {
+            return false
+        }
+        let old_kept_ids = self.display_list
+                               .items
+                               .iter()
+                               .map(|item| item.id())
+                               .filter(|id| !removed.contains(id));
+        if !old_kept_ids.eq(kept_ids.iter().cloned()) {
+            return false
+        }
+
+        let mut dead_vertex_count = self.dead_vertex_count;
+        for id in diff.removed.iter() {
+            for range in self.items[id].ranges.iter() {
+                dead_vertex_count += range.vertices.len()
+            }
+        }
+        let vertex_count = self.batches.iter().fold(0, |count, batch| count + batch.vertex_count());
+        if dead_vertex_count as f32 > vertex_count as f32 * MAX_DEAD_VERTEX_FRACTION {
+            return false
+        }
+
+        for id in diff.removed.iter() {
+            for range in self.items.remove(id).unwrap().ranges.iter() {
+                self.batches[range.batch].collapse_vertices(&range.vertices)
+            }
+        }
+        self.dead_vertex_count = dead_vertex_count;
+
+        // Items also have to be patched if their assets moved in the atlas.
+        for item in display_list.items[..kept_ids.len()].iter_mut() {
+            let locations = asset_locations(item);
+            let retained_item = self.items.get_mut(&item.id()).unwrap();
+            if !changed.contains(&item.id()) && locations == retained_item.asset_locations {
+                continue
+            }
+
+            let mut batcher = Batcher::new();
+            let new_ranges = batcher.add_and_locate(context, item);
+            let new_batches = batcher.finish();
+            if !patch_item(&mut self.batches, &retained_item.ranges, &new_batches, &new_ranges) {
+                return false
+            }
+            retained_item.asset_locations = locations;
+        }
+
+        self.append(context, &mut display_list.items[kept_ids.len()..]);
+        true
+    }
+
+    /// Adds items after all the others.
+    fn append(&mut self, context: &mut Context, items: &mut [DisplayItem]) {
+        let mut batcher = Batcher::resume(mem::replace(&mut self.batches, vec![]));
+        for item in items.iter_mut() {
+            let ranges = batcher.add_and_locate(context, item);
+            self.items.insert(item.id(), RetainedItem {
+                ranges: ranges,
+                asset_locations: asset_locations(item),
+            });
+        }
+        self.batches = batcher.finish();
+    }
+}
+
+/// Brings retained batches up to date with a new version of their display list, creating them if
+/// there are none yet, and returns the rectangles of the render target that need repainting.
+pub fn update_retained_batches(context: &mut Context,
+                               retained_batches: &mut Option<RetainedBatches>,
+                               display_list: DisplayList)
+                               -> Vec<Rect<i32>> {
+    if let Some(ref mut retained_batches) = *retained_batches {
+        let diff = retained_batches.update(context, display_list);
+        return diff.invalidated_region
+                   .and_then(|invalidated_region| {
+                       invalidation::damaged_rect(context, &invalidated_region)
+                   })
+                   .into_iter()
+                   .collect()
+    }
+
+    *retained_batches = Some(RetainedBatches::new(context, display_list));
+    vec![Rect::new(Point2D::new(0, 0), context.render_target_size)]
+}
+
+/// Copies an item's new vertices over its old ones, if they're laid out the same way: the same
+/// number of vertices, connected in the same way, drawing from the same atlas pages. Returns
+/// false, changing nothing, if they aren't.
+fn patch_item(batches: &mut [Batch],
+              ranges: &[ItemRange],
+              new_batches: &[Batch],
+              new_ranges: &[ItemRange])
+              -> bool {
+    if ranges.len() != new_ranges.len() {
+        return false
+    }
+    for (range, new_range) in ranges.iter().zip(new_ranges.iter()) {
+        let (batch, new_batch) = (&batches[range.batch], &new_batches[new_range.batch]);
+        if range.vertices.len() != new_range.vertices.len() ||
+                range.elements.len() != new_range.elements.len() {
+            return false
+        }
+        match (batch.page, new_batch.page) {
+            (_, None) => {}
+            (Some(page), Some(new_page)) if page == new_page => {}
+            _ => return false,
+        }
+        let elements = batch.elements[range.elements.clone()].iter();
+        let new_elements = new_batch.elements[new_range.elements.clone()].iter();
+        for (&element, &new_element) in elements.zip(new_elements) {
+            if element as usize - range.vertices.start !=
+                    new_element as usize - new_range.vertices.start {
+                return false
+            }
+        }
+    }
+
+    for (range, new_range) in ranges.iter().zip(new_ranges.iter()) {
+        batches[range.batch].copy_vertices(range.vertices.start,
+                                           &new_batches[new_range.batch],
+                                           &new_range.vertices)
+    }
+    true
+}
+
+/// How the quads of a display item are clipped.
+struct QuadClip {
+    /// The rectangle that quads are trimmed to.
+    rect: Rect<Au>,
+    /// The rounded rectangle that the fragment shader clips to, if the quads reach any of its
+    /// rounded corners.
+    rounded_rect: Option<ComplexClippingRegion>,
+}
+
+/// Works out how to clip the quads of an item with the given bounds, returning no clips if the
+/// item is clipped out entirely. The fragment shader clips to only one rounded rectangle, so items
+/// that reach the rounded corners of several are drawn in pieces, each clipped by one.
+/// `device_pixel` is the size of a device pixel.
+fn quad_clips(bounds: &Rect<Au>, clipping_region: &ClippingRegion, device_pixel: Au)
+              -> Vec<QuadClip> {
+    let mut rect = clipping_region.main;
+    for complex in clipping_region.complex.iter() {
+        rect = match invalidation::intersect_rects(&rect, &complex.rect) {
+            None => return vec![],
+            Some(rect) => rect,
+        }
+    }
+    let visible_rect = match invalidation::intersect_rects(bounds, &rect) {
+        None => return vec![],
+        Some(visible_rect) => visible_rect,
+    };
+
+    // Items that don't reach any rounded corner are clipped by the rectangle alone.
+    let rounded_rects: Vec<ComplexClippingRegion> =
+        clipping_region.complex.iter().filter(|complex| {
+            !complex.radii.is_square() && reaches_corner(&visible_rect, complex)
+        }).cloned().collect();
+    if rounded_rects.len() < 2 {
+        return vec![QuadClip {
+            rect: rect,
+            rounded_rect: rounded_rects.first().cloned(),
+        }]
+    }
+
+    // Cut the visible rectangle along the edges of the corners, so that each cell is wholly inside
+    // or outside of each of them, and then merge the cells of each row that reach the same corners.
+    let (min, max) = (visible_rect.origin, visible_rect.bottom_right());
+    let (mut xs, mut ys) = (vec![min.x, max.x], vec![min.y, max.y]);
+    for complex in rounded_rects.iter() {
+        for corner_rect in complex.corner_rects().iter() {
+            let corner_max = corner_rect.bottom_right();
+            for &x in [corner_rect.origin.x, corner_max.x].iter() {
+                if x > min.x && x < max.x {
+                    xs.push(x)
+                }
+            }
+            for &y in [corner_rect.origin.y, corner_max.y].iter() {
+                if y > min.y && y < max.y {
+                    ys.push(y)
+                }
+            }
+        }
+    }
+    xs.sort();
+    xs.dedup();
+    ys.sort();
+    ys.dedup();
+
+    let mut quad_clips = vec![];
+    for row in ys.windows(2) {
+        let piece = |min_x: Au, max_x: Au, rounded_rect: Option<usize>| {
+            QuadClip {
+                rect: Rect::new(Point2D::new(min_x, row[0]),
+                                Size2D::new(max_x - min_x, row[1] - row[0])),
+                rounded_rect: rounded_rect.map(|index| rounded_rects[index]),
+            }
+        };
+        let mut run: Option<(Au, Option<usize>)> = None;
+        for column in xs.windows(2) {
+            let cell = Rect::new(Point2D::new(column[0], row[0]),
+                                 Size2D::new(column[1] - column[0], row[1] - row[0]));
+            let reached: Vec<usize> = (0..rounded_rects.len()).filter(|&index| {
+                reaches_corner(&cell, &rounded_rects[index])
+            }).collect();
+            if reached.len() > 1 {
+                if let Some((run_start, run_rounded_rect)) = run.take() {
+                    quad_clips.push(piece(run_start, column[0], run_rounded_rect))
+                }
+                add_quad_clips_for_corners(&mut quad_clips,
+                                           &cell,
+                                           &rounded_rects,
+                                           &reached,
+                                           device_pixel);
+                continue
+            }
+
+            let rounded_rect = reached.first().cloned();
+            run = match run {
+                Some((run_start, run_rounded_rect)) if run_rounded_rect != rounded_rect => {
+                    quad_clips.push(piece(run_start, column[0], run_rounded_rect));
+                    Some((column[0], rounded_rect))
+                }
+                Some(run) => Some(run),
+                None => Some((column[0], rounded_rect)),
+            }
+        }
+        if let Some((run_start, run_rounded_rect)) = run {
+            quad_clips.push(piece(run_start, max.x, run_rounded_rect))
+        }
+    }
+    quad_clips
+}
+
+/// Adds clips for a cell that lies in the corners of several rounded rectangles, given by their
+/// indices. Rounded rectangles that cover all of a piece of the cell needn't clip it, and pieces
+/// that one covers none of draw nothing, so the cell is halved until at most one rounded rectangle
+/// clips each piece. Pieces a device pixel across that are still crossed by several arcs are
+/// clipped by whichever covers the least of their centers.
+fn add_quad_clips_for_corners(quad_clips: &mut Vec<QuadClip>,
+                              cell: &Rect<Au>,
+                              rounded_rects: &[ComplexClippingRegion],
+                              indices: &[usize],
+                              device_pixel: Au) {
+    let margin = (device_pixel.0 as f32) * 0.5;
+    let mut clipping_indices = vec![];
+    for &index in indices.iter() {
+        match corner_coverage(cell, &rounded_rects[index], margin) {
+            CornerCoverage::None => return,
+            CornerCoverage::Full => {}
+            CornerCoverage::Partial => clipping_indices.push(index),
+        }
+    }
+
+    if clipping_indices.len() > 1 &&
+            (cell.size.width > device_pixel || cell.size.height > device_pixel) {
+        let half = |length: Au| if length > device_pixel { Au(length.0 / 2) } else { length };
+        let (half_width, half_height) = (half(cell.size.width), half(cell.size.height));
+        let columns = [(cell.origin.x, half_width),
+                       (cell.origin.x + half_width, cell.size.width - half_width)];
+        let rows = [(cell.origin.y, half_height),
+                    (cell.origin.y + half_height, cell.size.height - half_height)];
+        for &(y, height) in rows.iter() {
+            for &(x, width) in columns.iter() {
+                if width > Au(0) && height > Au(0) {
+                    add_quad_clips_for_corners(quad_clips,
+                                               &Rect::new(Point2D::new(x, y),
+                                                          Size2D::new(width, height)),
+                                               rounded_rects,
+                                               &clipping_indices,
+                                               device_pixel)
+                }
+            }
+        }
+        return
+    }
+
+    let center = Point2D::new((cell.origin.x.0 as f32) + (cell.size.width.0 as f32) * 0.5,
+                              (cell.origin.y.0 as f32) + (cell.size.height.0 as f32) * 0.5);
+    let rounded_rect = clipping_indices.iter().map(|&index| {
+        (distance_outside_corners(&center, &rounded_rects[index]), index)
+    }).fold(None, |tightest: Option<(f32, usize)>, (distance, index)| {
+        match tightest {
+            Some((tightest_distance, _)) if tightest_distance >= distance => tightest,
+            _ => Some((distance, index)),
+        }
+    }).map(|(_, index)| rounded_rects[index]);
+    quad_clips.push(QuadClip {
+        rect: *cell,
+        rounded_rect: rounded_rect,
+    })
+}
+
+/// How much of a rectangle that lies wholly inside or outside each corner of a rounded rectangle
+/// the rounded rectangle covers, to within `margin`.
+enum CornerCoverage {
+    None,
+    Partial,
+    Full,
+}
+
+fn corner_coverage(rect: &Rect<Au>, complex: &ComplexClippingRegion, margin: f32)
+                   -> CornerCoverage {
+    let (min, max) = (to_f32_point(&rect.origin), to_f32_point(&rect.bottom_right()));
+    let mut coverage = CornerCoverage::Full;
+    for (corner_rect, &(center, radius)) in complex.corner_rects()
+                                                   .iter()
+                                                   .zip(corner_circles(complex).iter()) {
+        if radius <= 0.0 || invalidation::intersect_rects(rect, corner_rect).is_none() {
+            continue
+        }
+        let nearest = Point2D::new(f32::max(min.x, f32::min(center.x, max.x)),
+                                   f32::max(min.y, f32::min(center.y, max.y)));
+        let farthest_from = |center: f32, min: f32, max: f32| {
+            if center - min > max - center { min } else { max }
+        };
+        let farthest = Point2D::new(farthest_from(center.x, min.x, max.x),
+                                    farthest_from(center.y, min.y, max.y));
+        if distance(&nearest, &center) >= radius + margin {
+            return CornerCoverage::None
+        }
+        if distance(&farthest, &center) > radius - margin {
+            coverage = CornerCoverage::Partial
+        }
+    }
+    coverage
+}
+
+/// Returns how far the point is outside the arcs of the corners of the rounded rectangle that it
+/// lies in, negative if it's inside all of them.
+fn distance_outside_corners(point: &Point2D<f32>, complex: &ComplexClippingRegion) -> f32 {
+    let mut distance_outside = -f32::INFINITY;
+    for (corner_rect, &(center, radius)) in complex.corner_rects()
+                                                   .iter()
+                                                   .zip(corner_circles(complex).iter()) {
+        let (min, max) = (to_f32_point(&corner_rect.origin),
+                          to_f32_point(&corner_rect.bottom_right()));
+        if radius > 0.0 && point.x >= min.x && point.x <= max.x && point.y >= min.y &&
+                point.y <= max.y {
+            distance_outside = f32::max(distance_outside, distance(point, &center) - radius)
+        }
+    }
+    distance_outside
+}
+
+/// Returns the center and radius of the circle that each corner of the rounded rectangle follows,
+/// in app units and in the order of `corner_rects()`.
+fn corner_circles(complex: &ComplexClippingRegion) -> [(Point2D<f32>, f32); 4] {
+    let corner_rects = complex.corner_rects();
+    let circle = |index: usize, x: Au, y: Au| {
+        (Point2D::new(x.0 as f32, y.0 as f32), corner_rects[index].size.width.0 as f32)
+    };
+    let (top_left, top_right) = (corner_rects[0].bottom_right(), &corner_rects[1]);
+    let (bottom_right, bottom_left) = (&corner_rects[2], &corner_rects[3]);
+    [
+        circle(0, top_left.x, top_left.y),
+        circle(1, top_right.origin.x, top_right.origin.y + top_right.size.height),
+        circle(2, bottom_right.origin.x, bottom_right.origin.y),
+        circle(3, bottom_left.origin.x + bottom_left.size.width, bottom_left.origin.y),
+    ]
+}
+
+fn to_f32_point(point: &Point2D<Au>) -> Point2D<f32> {
+    Point2D::new(point.x.0 as f32, point.y.0 as f32)
+}
+
+fn distance(a: &Point2D<f32>, b: &Point2D<f32>) -> f32 {
+    let (x, y) = (a.x - b.x, a.y - b.y);
+    f32::sqrt(x * x + y * y)
+}
+
+/// Returns true if any of the rounded rectangle's corners overlap the given rectangle.
+fn reaches_corner(rect: &Rect<Au>, complex: &ComplexClippingRegion) -> bool {
+    complex.corner_rects().iter().any(|corner_rect| {
+        invalidation::intersect_rects(rect, corner_rect).is_some()
+    })
+}
+
+/// What remains of a quad after clipping.
+struct ClippedRect {
+    rect: Rect<Au>,
+    /// The part of the original quad that remains, as fractions of its width and height, for
+    /// trimming texture coordinates to match.
+    fraction: Rect<f32>,
+}
+
+/// Clips a quad to the clip rectangle, or returns `None` if nothing of it remains. Quads wholly
+/// inside the clip are returned unchanged.
+fn clip_rect(rect: &Rect<Au>, clip: &Rect<Au>) -> Option<ClippedRect> {
+    let far_corner = rect.origin + Point2D::new(rect.size.width, rect.size.height);
+    if clip_contains(clip, &rect.origin) && clip_contains(clip, &far_corner) {
+        return Some(ClippedRect {
+            rect: *rect,
+            fraction: Rect::new(Point2D::new(0.0, 0.0), Size2D::new(1.0, 1.0)),
+        })
+    }
+
+    // Quads of negative size, like the middle piece of borders whose radius is larger than their
+    // width, cover the same area as their normalized rectangles, so clip those instead.
+    let normalized_rect = normalize_rect(rect);
+    let clipped_rect = match invalidation::intersect_rects(&normalized_rect, clip) {
+        None => return None,
+        Some(clipped_rect) => clipped_rect,
+    };
+    let fraction_x = |x: Au| ((x - rect.origin.x).0 as f32) / (rect.size.width.0 as f32);
+    let fraction_y = |y: Au| ((y - rect.origin.y).0 as f32) / (rect.size.height.0 as f32);
+    let (min_x, min_y) = (fraction_x(clipped_rect.origin.x), fraction_y(clipped_rect.origin.y));
+    let (max_x, max_y) = (fraction_x(clipped_rect.origin.x + clipped_rect.size.width),
+                          fraction_y(clipped_rect.origin.y + clipped_rect.size.height));
+    Some(ClippedRect {
+        rect: clipped_rect,
+        fraction: Rect::new(Point2D::new(min_x, min_y), Size2D::new(max_x - min_x, max_y - min_y)),
+    })
+}
+
+/// Returns the rectangle covering the same area as the given one, but with a nonnegative size.
+fn normalize_rect(rect: &Rect<Au>) -> Rect<Au> {
+    let far_corner = rect.origin + Point2D::new(rect.size.width, rect.size.height);
+    let origin = Point2D::new(cmp::min(rect.origin.x, far_corner.x),
+                              cmp::min(rect.origin.y, far_corner.y));
+    let max = Point2D::new(cmp::max(rect.origin.x, far_corner.x),
+                           cmp::max(rect.origin.y, far_corner.y));
+    Rect::new(origin, Size2D::new(max.x - origin.x, max.y - origin.y))
+}
+
+fn clip_contains(clip: &Rect<Au>, point: &Point2D<Au>) -> bool {
+    point.x >= clip.origin.x && point.x <= clip.origin.x + clip.size.width &&
+        point.y >= clip.origin.y && point.y <= clip.origin.y + clip.size.height
+}
+
+/// Clips a convex polygon to the half-plane where `distance`, which must vary linearly across the
+/// plane, is positive or zero.
+fn clip_polygon<F>(polygon: &[Point2D<f32>], distance: F) -> Vec<Point2D<f32>>
+                   where F: Fn(&Point2D<f32>) -> f32 {
+    let mut clipped_polygon = vec![];
+    for (index, point) in polygon.iter().enumerate() {
+        let next_point = &polygon[(index + 1) % polygon.len()];
+        let (distance_here, distance_next) = (distance(point), distance(next_point));
+        if distance_here >= 0.0 {
+            clipped_polygon.push(*point)
+        }
+        if (distance_here >= 0.0) != (distance_next >= 0.0) {
+            let fraction = distance_here / (distance_here - distance_next);
+            clipped_polygon.push(Point2D::new(point.x + (next_point.x - point.x) * fraction,
+                                              point.y + (next_point.y - point.y) * fraction))
+        }
+    }
+    clipped_polygon
+}
+
+/// Clips a convex polygon to another convex polygon, which must wind counterclockwise on the
+/// screen.
+fn clip_polygon_to_polygon(polygon: &[Point2D<f32>], clip: &[Point2D<f32>]) -> Vec<Point2D<f32>> {
+    let mut polygon = polygon.to_vec();
+    for (index, edge_start) in clip.iter().enumerate() {
+        let edge_end = &clip[(index + 1) % clip.len()];
+        polygon = clip_polygon(&polygon, |point| {
+            (edge_end.y - edge_start.y) * (point.x - edge_start.x) -
+                (edge_end.x - edge_start.x) * (point.y - edge_start.y)
+        })
+    }
+    polygon
+}
+
+/// Returns the corners of the rectangle in device pixels from the top left, wound
+/// counterclockwise on the screen. Like quads, the polygon stops short of the right and bottom
+/// edges.
+fn device_polygon_for_rect(context: &Context, rect: &Rect<Au>) -> [Point2D<f32>; 4] {
+    let (min, max) = (rect.origin.to_device_point(context),
+                      rect.bottom_right().to_device_point(context));
+    [
+        min,
+        Point2D::new(min.x, max.y - 0.5),
+        Point2D::new(max.x - 0.5, max.y - 0.5),
+        Point2D::new(max.x - 0.5, min.y),
+    ]
+}
+
+/// Returns the stops of a gradient with stops added so that they cover offsets from 0 to
+/// `max_offset`, as the extend mode says. Stops must be in order of offset.
+fn extend_gradient_stops(stops: &[GradientStop], max_offset: f32, extend_mode: ExtendMode)
+                         -> Vec<GradientStop> {
+    let (first_stop, last_stop) = (stops[0], stops[stops.len() - 1]);
+    let period = last_stop.offset - first_stop.offset;
+    let mut extended_stops = vec![];
+    match extend_mode {
+        ExtendMode::Repeat if period > 0.0 => {
+            let mut start = first_stop.offset - f32::ceil(first_stop.offset / period) * period;
+            while start < max_offset {
+                for stop in stops.iter() {
+                    extended_stops.push(GradientStop {
+                        offset: stop.offset - first_stop.offset + start,
+                        color: stop.color,
+                    })
+                }
+                start += period
+            }
+        }
+        ExtendMode::Clamp | ExtendMode::Repeat => {
+            // Before the first stop and after the last, the gradient is the color of that stop.
+            extended_stops.push(GradientStop {
+                offset: f32::min(first_stop.offset, 0.0),
+                color: first_stop.color,
+            });
+            extended_stops.extend(stops.iter().cloned());
+            extended_stops.push(GradientStop {
+                offset: f32::max(last_stop.offset, max_offset),
+                color: last_stop.color,
+            });
+        }
+    }
+    extended_stops
+}
+
+/// Splits the spans between stops whose alphas differ into pieces, adding stops in between.
+///
+/// CSS interpolates between stops in premultiplied space, but the shader premultiplies vertex
+/// colors after interpolating them. Within a piece, the two differ by at most a quarter of the
+/// product of how much its color and its alpha change, so the pieces are made small enough to
+/// keep that within one unit.
+fn split_gradient_stops(stops: &[GradientStop]) -> Vec<GradientStop> {
+    let mut split_stops = Vec::with_capacity(stops.len());
+    for pair in stops.windows(2) {
+        let (start_stop, end_stop) = (&pair[0], &pair[1]);
+        split_stops.push(*start_stop);
+        if end_stop.offset <= start_stop.offset {
+            continue
+        }
+
+        let change = |start: u8, end: u8| ((start as i32) - (end as i32)).abs();
+        let (start_color, end_color) = (&start_stop.color, &end_stop.color);
+        let color_change = cmp::max(change(start_color.r, end_color.r),
+                                    cmp::max(change(start_color.g, end_color.g),
+                                             change(start_color.b, end_color.b)));
+        let alpha_change = change(start_color.a, end_color.a);
+        let piece_count = f32::ceil(((color_change * alpha_change) as f32) / (4.0 * 255.0)) as u32;
+        for piece in 1..piece_count {
+            let fraction = (piece as f32) / (piece_count as f32);
+            split_stops.push(GradientStop {
+                offset: start_stop.offset + (end_stop.offset - start_stop.offset) * fraction,
+                color: lerp_color_premultiplied(start_color, end_color, fraction),
+            })
+        }
+    }
+    if let Some(last_stop) = stops.last() {
+        split_stops.push(*last_stop)
+    }
+    split_stops
+}
+
+/// Returns the average color of a gradient between its first and last stops, which must be at
+/// different offsets. Like the gradient itself, it's averaged in premultiplied space.
+fn average_gradient_color(stops: &[GradientStop]) -> Color {
+    let period = stops[stops.len() - 1].offset - stops[0].offset;
+    let channels = |color: &Color| {
+        let alpha = color.a as f32;
+        [
+            (color.r as f32) * alpha / 255.0,
+            (color.g as f32) * alpha / 255.0,
+            (color.b as f32) * alpha / 255.0,
+            alpha,
+        ]
+    };
+    let mut sums = [0.0; 4];
+    for pair in stops.windows(2) {
+        let (start_channels, end_channels) = (channels(&pair[0].color), channels(&pair[1].color));
+        let weight = (pair[1].offset - pair[0].offset) / period * 0.5;
+        for channel in 0..4 {
+            sums[channel] += (start_channels[channel] + end_channels[channel]) * weight
+        }
+    }
+    unpremultiply(&sums)
+}
+
+fn lerp_color(a: &Color, b: &Color, fraction: f32) -> Color {
+    let fraction = f32::max(0.0, f32::min(fraction, 1.0));
+    let lerp = |a: u8, b: u8| f32::round((a as f32) + ((b as f32) - (a as f32)) * fraction) as u8;
+    Color::new(lerp(a.r, b.r), lerp(a.g, b.g), lerp(a.b, b.b), lerp(a.a, b.a))
+}
+
+fn lerp_color_premultiplied(a: &Color, b: &Color, fraction: f32) -> Color {
+    let fraction = f32::max(0.0, f32::min(fraction, 1.0));
+    let (a_alpha, b_alpha) = (a.a as f32, b.a as f32);
+    let lerp = |a: u8, b: u8| {
+        ((a as f32) * a_alpha * (1.0 - fraction) + (b as f32) * b_alpha * fraction) / 255.0
+    };
+    unpremultiply(&[
+        lerp(a.r, b.r),
+        lerp(a.g, b.g),
+        lerp(a.b, b.b),
+        a_alpha + (b_alpha - a_alpha) * fraction,
+    ])
+}
+
+/// Converts premultiplied channels, from 0 to 255, to a color. Transparent black stands in for
+/// colors with no alpha.
+fn unpremultiply(channels: &[f32; 4]) -> Color {
+    let alpha = channels[3];
+    if alpha <= 0.0 {
+        return Color::new(0, 0, 0, 0)
+    }
+    let channel = |value: f32| f32::round(f32::max(0.0, f32::min(value, 255.0))) as u8;
+    Color::new(channel(channels[0] * 255.0 / alpha),
+               channel(channels[1] * 255.0 / alpha),
+               channel(channels[2] * 255.0 / alpha),
+               channel(alpha))
+}
+
+/// Returns where the assets that the item draws with are in the atlas. They must be there.
+fn asset_locations(display_item: &DisplayItem) -> Vec<AtlasLocation> {
+    let assets: Vec<&Rc<RefCell<Asset>>> = match *display_item {
+        DisplayItem::SolidColor(_) => vec![],
+        DisplayItem::Text(ref text_display_item) => {
+            match text_display_item.blurred_glyph_asset {
+                Some(ref blurred_glyph_asset) => vec![blurred_glyph_asset],
+                None => vec![&text_display_item.glyph_asset],
+            }
+        }
+        DisplayItem::Border(ref border_display_item) => {
+            vec![&border_display_item.arc_asset, &border_display_item.inverted_arc_asset]
+        }
+        DisplayItem::Image(ref image_display_item) => vec![&image_display_item.image_asset],
+        DisplayItem::LinearGradient(_) | DisplayItem::RadialGradient(_) => vec![],
+    };
+    assets.iter().map(|asset| asset.borrow().get_atlas_handle().borrow().location).collect()
+}
+
+/// Makes sure that the asset is in the atlas and returns its location there.
+fn require_asset(context: &Context, asset: &mut Asset) -> AtlasLocation {
+    context.asset_manager.atlas.borrow_mut().require_asset(asset,
+                                                           Priority::Retained,
+                                                           &context.debug_dump);
+    let atlas_handle = asset.get_atlas_handle();
+    let location = atlas_handle.borrow().location;
+    location
+}
+
 trait ToNormalizedDevicePosition {
     type To;
 
@@ -348,10 +1698,10 @@ impl ToNormalizedDevicePosition for Point2D<Au> {
     type To = Point2D<f32>;
 
     fn to_normalized_device_position(&self, context: &Context) -> Point2D<f32> {
-        Point2D::new(((self.x.to_px() as f32) / (context.render_target_size.width as f32) - 0.5) *
-                     2.0,
-                     ((self.y.to_px() as f32) / (context.render_target_size.height as f32) - 0.5) *
-                     2.0)
+        Point2D::new((self.x.to_device_px(context) / (context.render_target_size.width as f32) -
+                      0.5) * 2.0,
+                     (self.y.to_device_px(context) / (context.render_target_size.height as f32) -
+                      0.5) * 2.0)
     }
 }
 
@@ -359,47 +1709,29 @@ impl ToNormalizedDevicePosition for Size2D<Au> {
     type To = Size2D<f32>;
 
     fn to_normalized_device_position(&self, context: &Context) -> Size2D<f32> {
-        Size2D::new((self.width.to_px() as f32) / (context.render_target_size.width as f32) * 2.0,
-                    (self.height.to_px() as f32) / (context.render_target_size.height as f32) *
-                     2.0)
+        Size2D::new(self.width.to_device_px(context) / (context.render_target_size.width as f32) *
+                    2.0,
+                    self.height.to_device_px(context) /
+                    (context.render_target_size.height as f32) * 2.0)
     }
 }
 
-trait ToAu {
-    type To;
-
-    fn to_au(&self) -> Self::To;
+trait ToDevicePx {
+    fn to_device_px(&self, context: &Context) -> f32;
 }
 
-impl ToAu for Rect<i32> {
-    type To = Rect<Au>;
-
-    fn to_au(&self) -> Rect<Au> {
-        Rect::new(self.origin.to_au(), self.size.to_au())
+impl ToDevicePx for Au {
+    fn to_device_px(&self, context: &Context) -> f32 {
+        (self.to_f64_px() as f32) * context.device_pixel_ratio
     }
 }
 
-impl ToAu for Point2D<i32> {
-    type To = Point2D<Au>;
-
-    fn to_au(&self) -> Point2D<Au> {
-        Point2D::new(self.x.to_au(), self.y.to_au())
-    }
-}
-
-impl ToAu for Size2D<i32> {
-    type To = Size2D<Au>;
-
-    fn to_au(&self) -> Size2D<Au> {
-        Size2D::new(self.width.to_au(), self.height.to_au())
-    }
+trait ToDevicePoint {
+    fn to_device_point(&self, context: &Context) -> Point2D<f32>;
 }
 
-impl ToAu for i32 {
-    type To = Au;
-
-    fn to_au(&self) -> Au {
-        Au::from_px(*self)
+impl ToDevicePoint for Point2D<Au> {
+    fn to_device_point(&self, context: &Context) -> Point2D<f32> {
+        Point2D::new(self.x.to_device_px(context), self.y.to_device_px(context))
     }
 }
-
@@ -0,0 +1,180 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Timing the phases of rendering, for tracking performance across changes.
+
+use clock_ticks;
+use rustc_serialize::json::Json;
+use std::collections::BTreeMap;
+
+/// The phases of rendering a frame, in the order they happen.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Phase {
+    /// Rasterizing glyphs, arcs, and blurs on the job server, and waiting for them.
+    AssetRasterization = 0,
+    /// Packing assets into the atlas and uploading them to its textures.
+    AtlasUpload = 1,
+    /// Sorting display items into batches and generating their vertices.
+    BatchBuilding = 2,
+    /// Issuing the GL calls that draw the batches.
+    GlSubmission = 3,
+    /// Waiting in `glFinish()` for the GPU to finish drawing.
+    GlFinish = 4,
+}
+
+pub static PHASES: [Phase; 5] = [
+    Phase::AssetRasterization,
+    Phase::AtlasUpload,
+    Phase::BatchBuilding,
+    Phase::GlSubmission,
+    Phase::GlFinish,
+];
+
+impl Phase {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Phase::AssetRasterization => "asset-rasterization",
+            Phase::AtlasUpload => "atlas-upload",
+            Phase::BatchBuilding => "batch-building",
+            Phase::GlSubmission => "gl-submission",
+            Phase::GlFinish => "gl-finish",
+        }
+    }
+}
+
+/// How long each phase of one frame took, in nanoseconds.
+#[derive(Copy, Clone, Debug)]
+pub struct FrameTimings {
+    times: [u64; 5],
+}
+
+impl FrameTimings {
+    pub fn new() -> FrameTimings {
+        FrameTimings {
+            times: [0; 5],
+        }
+    }
+
+    pub fn get(&self, phase: Phase) -> u64 {
+        self.times[phase as usize]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.times.iter().fold(0, |total, &time| total + time)
+    }
+
+    /// Runs `f`, adding the time it takes to the given phase.
+    pub fn time<F, R>(&mut self, phase: Phase, f: F) -> R where F: FnOnce() -> R {
+        let start_time = clock_ticks::precise_time_ns();
+        let result = f();
+        self.times[phase as usize] += clock_ticks::precise_time_ns() - start_time;
+        result
+    }
+}
+
+/// Summary statistics of a set of times, in milliseconds.
+#[derive(Copy, Clone, Debug)]
+pub struct Statistics {
+    pub mean: f64,
+    pub median: f64,
+    /// The 95th percentile, by the nearest-rank method.
+    pub p95: f64,
+}
+
+impl Statistics {
+    /// Computes statistics of times given in nanoseconds.
+    pub fn new(times: &[u64]) -> Statistics {
+        if times.is_empty() {
+            return Statistics {
+                mean: 0.0,
+                median: 0.0,
+                p95: 0.0,
+            }
+        }
+
+        let mut times = times.to_vec();
+        times.sort();
+        let count = times.len();
+        let sum = times.iter().fold(0, |sum, &time| sum + time);
+        let median = if count % 2 == 0 {
+            (times[count / 2 - 1] + times[count / 2]) / 2
+        } else {
+            times[count / 2]
+        };
+        let p95_rank = ((count as f64) * 0.95).ceil() as usize;
+        Statistics {
+            mean: nanoseconds_to_milliseconds(sum) / (count as f64),
+            median: nanoseconds_to_milliseconds(median),
+            p95: nanoseconds_to_milliseconds(times[p95_rank - 1]),
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert("mean_ms".to_string(), Json::F64(self.mean));
+        object.insert("median_ms".to_string(), Json::F64(self.median));
+        object.insert("p95_ms".to_string(), Json::F64(self.p95));
+        Json::Object(object)
+    }
+}
+
+/// The timings of every frame of a benchmark run.
+pub struct BenchmarkResults {
+    pub frames: Vec<FrameTimings>,
+}
+
+impl BenchmarkResults {
+    pub fn new() -> BenchmarkResults {
+        BenchmarkResults {
+            frames: vec![],
+        }
+    }
+
+    pub fn phase_statistics(&self, phase: Phase) -> Statistics {
+        let times: Vec<u64> = self.frames.iter().map(|frame| frame.get(phase)).collect();
+        Statistics::new(&times)
+    }
+
+    pub fn total_statistics(&self) -> Statistics {
+        let times: Vec<u64> = self.frames.iter().map(|frame| frame.total()).collect();
+        Statistics::new(&times)
+    }
+
+    /// Returns the frame count and the statistics of each phase and of whole frames, keyed by
+    /// phase name and "total".
+    pub fn to_json(&self) -> Json {
+        let mut phases = BTreeMap::new();
+        for phase in PHASES.iter() {
+            phases.insert(phase.name().to_string(), self.phase_statistics(*phase).to_json());
+        }
+        phases.insert("total".to_string(), self.total_statistics().to_json());
+
+        let mut object = BTreeMap::new();
+        object.insert("frames".to_string(), Json::U64(self.frames.len() as u64));
+        object.insert("phases".to_string(), Json::Object(phases));
+        Json::Object(object)
+    }
+
+    /// Prints a table of the statistics of each phase.
+    pub fn print(&self) {
+        println!("{} frames", self.frames.len());
+        println!("{:<20} {:>10} {:>10} {:>10}", "phase (ms)", "mean", "median", "p95");
+        for phase in PHASES.iter() {
+            print_statistics(phase.name(), &self.phase_statistics(*phase))
+        }
+        print_statistics("total", &self.total_statistics())
+    }
+}
+
+fn print_statistics(name: &str, statistics: &Statistics) {
+    println!("{:<20} {:>10.3} {:>10.3} {:>10.3}",
+             name,
+             statistics.mean,
+             statistics.median,
+             statistics.p95)
+}
+
+fn nanoseconds_to_milliseconds(nanoseconds: u64) -> f64 {
+    (nanoseconds as f64) / 1000000.0
+}
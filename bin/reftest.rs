@@ -0,0 +1,103 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Runs the reftests and reports the ones that fail.
+//!
+//! Usage: `reftest [options] [filter...]`. Only tests whose names contain one of the filters are
+//! run, or all of them if there are no filters. A test's name is its scene's file stem followed by
+//! its conditions, such as `clip-outside-clip-mode-stencil` or `caret-on-update-caret-off`.
+
+extern crate euclid;
+extern crate getopts;
+extern crate webrast;
+
+use webrast::headless::HeadlessRenderer;
+use webrast::reftest;
+use webrast::renderer::Renderer;
+use webrast::software::SoftwareRenderer;
+
+use euclid::Size2D;
+use getopts::Options;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+const REFTEST_WIDTH: i32 = 256;
+const REFTEST_HEIGHT: i32 = 256;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut options = Options::new();
+    options.optflag("s", "software", "render with the CPU reference rasterizer instead of GL");
+    options.optopt("m",
+                   "manifest",
+                   "the manifest listing the reftests (default: reftests/reftest.list)",
+                   "FILE");
+    options.optopt("f",
+                   "fonts",
+                   "the directory to look up fonts in (default: the directory of each scene)",
+                   "DIR");
+    options.optopt("o", "output", "write images of failing tests to this directory", "DIR");
+    options.optflag("h", "help", "print this help");
+    let matches = match options.parse(&args[1..]) {
+        Ok(matches) => matches,
+        Err(error) => panic!("{}", error),
+    };
+    if matches.opt_present("h") {
+        print!("{}", options.usage(&format!("Usage: {} [options] [filter...]", args[0])));
+        return
+    }
+
+    let manifest_path = PathBuf::from(matches.opt_str("m")
+                                             .unwrap_or("reftests/reftest.list".to_string()));
+    let font_directory = matches.opt_str("f").map(PathBuf::from);
+    let output_directory = matches.opt_str("o").map(PathBuf::from);
+    if let Some(ref output_directory) = output_directory {
+        fs::create_dir_all(output_directory).unwrap();
+    }
+
+    let size = Size2D::new(REFTEST_WIDTH, REFTEST_HEIGHT);
+    let mut renderer: Box<Renderer> = if matches.opt_present("s") {
+        Box::new(SoftwareRenderer::new(&size))
+    } else {
+        Box::new(HeadlessRenderer::new(&size))
+    };
+
+    let reftests = match reftest::read_manifest(&manifest_path) {
+        Ok(reftests) => reftests,
+        Err(error) => {
+            println!("reftest: couldn't read {}: {}", manifest_path.display(), error);
+            process::exit(2)
+        }
+    };
+
+    let (mut passed_count, mut failed_count) = (0, 0);
+    for reftest in reftests.iter() {
+        if !matches.free.is_empty() &&
+                !matches.free.iter().any(|filter| reftest.name.contains(&**filter)) {
+            continue
+        }
+
+        let result = reftest.run(&mut *renderer,
+                                 font_directory.as_ref().map(|path| path.as_path()),
+                                 output_directory.as_ref().map(|path| path.as_path()));
+        if result.passed {
+            passed_count += 1;
+            println!("PASS {} ({})", reftest.name, result.comparison)
+        } else {
+            failed_count += 1;
+            println!("FAIL {} ({}; allowed max difference {}, {} differing pixels)",
+                     reftest.name,
+                     result.comparison,
+                     reftest.fuzz.max_difference,
+                     reftest.fuzz.max_differing_pixels)
+        }
+    }
+
+    println!("{} passed, {} failed", passed_count, failed_count);
+    if failed_count > 0 {
+        process::exit(1)
+    }
+}
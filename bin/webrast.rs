@@ -0,0 +1,374 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Renders a scene file, either in a window or offscreen.
+//!
+//! Usage: `webrast [options] SCENE`. Run with `--help` for the options.
+
+extern crate clock_ticks;
+extern crate euclid;
+extern crate getopts;
+extern crate gleam;
+extern crate glutin;
+extern crate log;
+extern crate num_cpus;
+extern crate rustc_serialize;
+extern crate webrast;
+
+use webrast::assets::AssetManager;
+use webrast::atlas::Atlas;
+use webrast::batch::{self, ClipMode};
+use webrast::benchmark::BenchmarkResults;
+use webrast::context::Context;
+use webrast::debug::DebugDump;
+use webrast::display_list::DisplayList;
+use webrast::draw::DrawContext;
+use webrast::headless::HeadlessRenderer;
+use webrast::job_server::JobServer;
+use webrast::png;
+use webrast::renderer::Renderer;
+use webrast::scene;
+use webrast::software::SoftwareRenderer;
+
+use euclid::Size2D;
+use getopts::{Matches, Options};
+use gleam::gl;
+use glutin::{Api, Event, GlRequest, WindowBuilder};
+use log::{Log, LogLevelFilter, LogMetadata, LogRecord};
+use rustc_serialize::json::{self, Json};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+
+const DEFAULT_WIDTH: i32 = 800;
+const DEFAULT_HEIGHT: i32 = 600;
+const DEFAULT_BENCHMARK_FRAME_COUNT: u32 = 100;
+
+struct SimpleLogger;
+
+impl Log for SimpleLogger {
+    fn enabled(&self, _: &LogMetadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &LogRecord) {
+        println!("{}", record.args());
+    }
+}
+
+/// The settings common to windowed and headless rendering.
+struct Settings {
+    scene_path: PathBuf,
+    font_directory: Option<PathBuf>,
+    size: Size2D<i32>,
+    device_pixel_ratio: f32,
+    clip_mode: ClipMode,
+    /// The number of frames to draw, or `None` to draw until the window is closed.
+    frame_count: Option<u32>,
+    debug_dump: Option<PathBuf>,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut options = Options::new();
+    options.optopt("f",
+                   "fonts",
+                   "the directory to look up fonts in (default: the directory of the scene)",
+                   "DIR");
+    options.optopt("s", "size", "the window size in device pixels (default: 800x600)", "WxH");
+    options.optopt("r", "device-pixel-ratio", "device pixels per CSS pixel (default: 1)", "RATIO");
+    options.optopt("n",
+                   "frames",
+                   "the number of frames to draw (default: until the window is closed, or 1 \
+                    if headless)",
+                   "N");
+    options.optopt("",
+                   "clip-mode",
+                   "how to clip items: \"geometry\" to trim their quads, or \"stencil\" to use \
+                    the stencil buffer (default: geometry)",
+                   "MODE");
+    options.optflag("", "headless", "render offscreen instead of in a window");
+    options.optflag("",
+                    "software",
+                    "render offscreen with the CPU reference rasterizer instead of GL");
+    options.optopt("o", "output", "write the last frame to a PNG file (headless only)", "FILE");
+    options.optopt("", "debug-dump", "write assets and atlas snapshots to this directory", "DIR");
+    options.optflag("",
+                    "benchmark",
+                    "render offscreen and report how long each phase of rendering takes \
+                     (default: 100 frames)");
+    options.optflag("",
+                    "warm",
+                    "when benchmarking, keep assets and the atlas from one frame to the next");
+    options.optopt("", "json", "when benchmarking, also write the results to this file", "FILE");
+    options.optflag("v", "verbose", "log debugging messages");
+    options.optflag("h", "help", "print this help");
+    let matches = match options.parse(&args[1..]) {
+        Ok(matches) => matches,
+        Err(error) => usage_error(&options, &args[0], &error.to_string()),
+    };
+    if matches.opt_present("h") {
+        print!("{}", options.usage(&format!("Usage: {} [options] SCENE", args[0])));
+        return
+    }
+    if matches.free.len() != 1 {
+        usage_error(&options, &args[0], "expected exactly one scene file")
+    }
+
+    let max_log_level = if matches.opt_present("v") {
+        LogLevelFilter::Debug
+    } else {
+        LogLevelFilter::Warn
+    };
+    log::set_logger(|max_log_level_setter| {
+        max_log_level_setter.set(max_log_level);
+        Box::new(SimpleLogger)
+    }).unwrap();
+
+    let settings = match parse_settings(&matches) {
+        Ok(settings) => settings,
+        Err(message) => usage_error(&options, &args[0], &message),
+    };
+    let headless = matches.opt_present("headless") || matches.opt_present("software");
+    if matches.opt_present("benchmark") {
+        run_benchmark(&settings,
+                      matches.opt_present("warm"),
+                      matches.opt_str("json").as_ref().map(|path| Path::new(path)))
+    } else if headless {
+        render_headless(&settings,
+                        matches.opt_present("software"),
+                        matches.opt_str("o").as_ref().map(|path| Path::new(path)))
+    } else if matches.opt_present("o") {
+        usage_error(&options, &args[0], "--output requires --headless or --software")
+    } else {
+        render_in_window(&settings)
+    }
+}
+
+fn parse_settings(matches: &Matches) -> Result<Settings, String> {
+    let size = match matches.opt_str("s") {
+        None => Size2D::new(DEFAULT_WIDTH, DEFAULT_HEIGHT),
+        Some(size) => {
+            let dimensions: Vec<i32> = size.split('x')
+                                           .map(|dimension| dimension.parse().unwrap_or(0))
+                                           .collect();
+            if dimensions.len() != 2 || dimensions[0] <= 0 || dimensions[1] <= 0 {
+                return Err(format!("invalid size \"{}\"", size))
+            }
+            Size2D::new(dimensions[0], dimensions[1])
+        }
+    };
+    let device_pixel_ratio = match matches.opt_str("r") {
+        None => 1.0,
+        Some(ratio) => {
+            match ratio.parse::<f32>() {
+                Ok(ratio) if ratio > 0.0 => ratio,
+                _ => return Err(format!("invalid device pixel ratio \"{}\"", ratio)),
+            }
+        }
+    };
+    let clip_mode = match matches.opt_str("clip-mode") {
+        None => ClipMode::Geometry,
+        Some(clip_mode) => {
+            match &*clip_mode {
+                "geometry" => ClipMode::Geometry,
+                "stencil" => ClipMode::Stencil,
+                _ => return Err(format!("invalid clip mode \"{}\"", clip_mode)),
+            }
+        }
+    };
+    let frame_count = match matches.opt_str("n") {
+        None => None,
+        Some(frame_count) => {
+            match frame_count.parse::<u32>() {
+                Ok(frame_count) if frame_count > 0 => Some(frame_count),
+                _ => return Err(format!("invalid frame count \"{}\"", frame_count)),
+            }
+        }
+    };
+    Ok(Settings {
+        scene_path: PathBuf::from(&matches.free[0]),
+        font_directory: matches.opt_str("f").map(PathBuf::from),
+        size: size,
+        device_pixel_ratio: device_pixel_ratio,
+        clip_mode: clip_mode,
+        frame_count: frame_count,
+        debug_dump: matches.opt_str("debug-dump").map(PathBuf::from),
+    })
+}
+
+fn usage_error(options: &Options, program: &str, message: &str) -> ! {
+    println!("{}: {}", program, message);
+    print!("{}", options.usage(&format!("Usage: {} [options] SCENE", program)));
+    process::exit(2)
+}
+
+/// Loads the scene to render, exiting with an error if it can't be.
+fn load_scene(settings: &Settings, asset_manager: &AssetManager) -> DisplayList {
+    match scene::load_scene(&settings.scene_path,
+                            asset_manager,
+                            settings.font_directory.as_ref().map(|path| path.as_path())) {
+        Ok(display_list) => display_list,
+        Err(error) => {
+            println!("webrast: couldn't load {}: {}", settings.scene_path.display(), error);
+            process::exit(1)
+        }
+    }
+}
+
+fn debug_dump(settings: &Settings) -> DebugDump {
+    match settings.debug_dump {
+        None => DebugDump::disabled(),
+        Some(ref directory) => DebugDump::new(directory.clone()),
+    }
+}
+
+/// Writes out the atlas through the debug dump, if `--debug-dump` turned it on.
+fn snapshot_atlas(context: &Context) {
+    context.asset_manager.atlas.borrow().snapshot(&context.debug_dump)
+}
+
+fn render_headless(settings: &Settings, software: bool, output_path: Option<&Path>) {
+    let mut renderer: Box<Renderer> = if software {
+        Box::new(SoftwareRenderer::new(&settings.size))
+    } else {
+        Box::new(HeadlessRenderer::new(&settings.size))
+    };
+    renderer.context().device_pixel_ratio = settings.device_pixel_ratio;
+    renderer.context().clip_mode = settings.clip_mode;
+    renderer.context().debug_dump = debug_dump(settings);
+
+    let mut display_list = load_scene(settings, &renderer.context().asset_manager);
+    let frame_count = settings.frame_count.unwrap_or(1);
+    let mut pixels = vec![];
+    let mut frame_times = vec![];
+    for _ in 0..frame_count {
+        let start_time = clock_ticks::precise_time_ns();
+        pixels = renderer.render(&mut display_list);
+        frame_times.push(clock_ticks::precise_time_ns() - start_time);
+    }
+    print_frame_times(&frame_times);
+    snapshot_atlas(renderer.context());
+
+    if let Some(output_path) = output_path {
+        png::write_png(output_path,
+                       &pixels,
+                       &Size2D::new(settings.size.width as u32, settings.size.height as u32))
+    }
+}
+
+fn run_benchmark(settings: &Settings, warm: bool, json_path: Option<&Path>) {
+    let mut renderer = HeadlessRenderer::new(&settings.size);
+    renderer.context.device_pixel_ratio = settings.device_pixel_ratio;
+    renderer.context.clip_mode = settings.clip_mode;
+    renderer.context.debug_dump = debug_dump(settings);
+
+    let mut results = BenchmarkResults::new();
+    let mut display_list = None;
+    for _ in 0..settings.frame_count.unwrap_or(DEFAULT_BENCHMARK_FRAME_COUNT) {
+        if !warm || display_list.is_none() {
+            // Start from scratch, so that every asset is rasterized and uploaded again.
+            drop(display_list.take());
+            renderer.context.asset_manager.atlas.borrow_mut().clear();
+            display_list = Some(load_scene(settings, &renderer.context.asset_manager));
+        }
+        results.frames.push(renderer.draw_with_timings(display_list.as_mut().unwrap()))
+    }
+    results.print();
+
+    if let Some(json_path) = json_path {
+        let mut object = BTreeMap::new();
+        object.insert("scene".to_string(),
+                      Json::String(settings.scene_path.to_string_lossy().into_owned()));
+        object.insert("size".to_string(),
+                      Json::Array(vec![
+                          Json::I64(settings.size.width as i64),
+                          Json::I64(settings.size.height as i64),
+                      ]));
+        object.insert("device_pixel_ratio".to_string(),
+                      Json::F64(settings.device_pixel_ratio as f64));
+        object.insert("warm".to_string(), Json::Boolean(warm));
+        object.insert("results".to_string(), results.to_json());
+        let mut file = File::create(json_path).unwrap();
+        writeln!(&mut file, "{}", json::as_pretty_json(&Json::Object(object))).unwrap();
+    }
+}
+
+fn render_in_window(settings: &Settings) {
+    let window = WindowBuilder::new().with_gl(GlRequest::Specific(Api::OpenGl, (2, 1)))
+                                     .with_dimensions(settings.size.width as u32,
+                                                      settings.size.height as u32)
+                                     .with_title(format!("webrast: {}",
+                                                         settings.scene_path.display()))
+                                     .build()
+                                     .unwrap();
+    unsafe {
+        window.make_current().unwrap();
+    }
+    gl::load_with(|symbol| window.get_proc_address(symbol));
+
+    let atlas = Rc::new(RefCell::new(Atlas::new()));
+    let job_server = Rc::new(RefCell::new(JobServer::new(num_cpus::get() as u32)));
+    let mut context = Context {
+        asset_manager: AssetManager::new(job_server, atlas.clone()),
+        render_target_size: settings.size,
+        device_pixel_ratio: settings.device_pixel_ratio,
+        clip_mode: settings.clip_mode,
+        debug_dump: debug_dump(settings),
+    };
+    let mut display_list = load_scene(settings, &context.asset_manager);
+    let batches = batch::batch_display_list(&mut context, &mut display_list);
+    snapshot_atlas(&context);
+
+    let mut draw_context = DrawContext::new(atlas);
+    draw_context.init_gl_state();
+
+    let mut frame_times = vec![];
+    loop {
+        if let Some(frame_count) = settings.frame_count {
+            if frame_times.len() == frame_count as usize {
+                break
+            }
+        }
+
+        let start_time = clock_ticks::precise_time_ns();
+        draw_context.clear();
+        for batch in batches.iter() {
+            draw_context.draw_batch(batch)
+        }
+        draw_context.finish();
+        frame_times.push(clock_ticks::precise_time_ns() - start_time);
+
+        window.swap_buffers().unwrap();
+
+        let closed = window.poll_events().any(|event| {
+            match event {
+                Event::Closed => true,
+                _ => false,
+            }
+        });
+        if closed {
+            break
+        }
+    }
+    print_frame_times(&frame_times);
+}
+
+fn print_frame_times(frame_times: &[u64]) {
+    if frame_times.is_empty() {
+        return
+    }
+    let mut sum = 0;
+    for time in frame_times.iter() {
+        sum += *time;
+    }
+    println!("{} frames, mean {}ms",
+             frame_times.len(),
+             ((sum / (frame_times.len() as u64)) as f64) / 1000000.0);
+}
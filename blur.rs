@@ -2,20 +2,22 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use atlas;
+use atlas::PixelFormat;
 use distance_field::BUFFER;
 
 use euclid::Size2D;
 use std::f32::consts::PI;
 use std::f32;
 
+/// Blurs the shape described by a distance field, reading distances from the alpha channel of
+/// `distance_field`. The result has one byte per pixel.
 pub fn approximate_gaussian_blur_with_distance_field(distance_field: &[u8],
+                                                     format: PixelFormat,
                                                      distance_scaling_factor: f32,
                                                      size: &Size2D<u32>,
                                                      sigma: f32)
                                                      -> Vec<u8> {
-    atlas::write_tga("distance-field.tga", distance_field, size);
-
+    let bytes_per_pixel = format.bytes_per_pixel();
     let blur_radius = f32::ceil(sigma * 3.0) as i32;
     let mut convolution = Vec::with_capacity(blur_radius as usize * 2 + 1);
     let two_sigma_squared = 2.0 * sigma * sigma;
@@ -40,10 +42,11 @@ pub fn approximate_gaussian_blur_with_distance_field(distance_field: &[u8],
         precomputed_values.push(sum)
     }
 
-    let mut result = Vec::with_capacity((size.width * size.height * 4) as usize);
+    let mut result = Vec::with_capacity((size.width * size.height) as usize);
     for y in 0..size.height {
         for x in 0..size.width {
-            let mut distance = distance_field[((y * size.width + x) * 4 + 3) as usize] as f32;
+            let alpha_index = (y * size.width + x + 1) * bytes_per_pixel - 1;
+            let mut distance = distance_field[alpha_index as usize] as f32;
             distance = (distance - (BUFFER as f32)) / distance_scaling_factor;
 
             let color = if distance < -(blur_radius as f32) {
@@ -62,7 +65,7 @@ pub fn approximate_gaussian_blur_with_distance_field(distance_field: &[u8],
                 255
             };*/
 
-            result.extend([ color, color, color, color ].iter())
+            result.push(color)
         }
     }
     result
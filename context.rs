@@ -5,11 +5,19 @@
 use euclid::Size2D;
 
 use assets::AssetManager;
+use batch::ClipMode;
+use debug::DebugDump;
 
 pub struct Context {
     /// The asset manager.
     pub asset_manager: AssetManager,
     /// The size of the render target in pixels.
     pub render_target_size: Size2D<i32>,
+    /// The number of device pixels per CSS pixel.
+    pub device_pixel_ratio: f32,
+    /// How display items are clipped.
+    pub clip_mode: ClipMode,
+    /// Where to write intermediate images for debugging, if anywhere.
+    pub debug_dump: DebugDump,
 }
 
@@ -0,0 +1,108 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Dumping of intermediate images for debugging.
+//!
+//! Dumps are off by default. When they're turned on, every asset is written out as it's uploaded
+//! to the atlas, named after its description, and snapshots of the whole atlas can be taken on
+//! demand. `webrast --debug-dump` takes one once it has drawn the scene.
+
+use atlas::PixelFormat;
+
+use euclid::Size2D;
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+pub struct DebugDump {
+    /// The directory to write images to, or `None` if dumping is off.
+    directory: Option<PathBuf>,
+    /// The number of atlas snapshots taken so far, used to name the next one.
+    snapshot_count: Cell<u32>,
+}
+
+impl DebugDump {
+    /// Returns a debug dump that writes nothing.
+    pub fn disabled() -> DebugDump {
+        DebugDump {
+            directory: None,
+            snapshot_count: Cell::new(0),
+        }
+    }
+
+    /// Returns a debug dump that writes images into `directory`, creating it if necessary.
+    pub fn new(directory: PathBuf) -> DebugDump {
+        fs::create_dir_all(&directory).unwrap();
+        DebugDump {
+            directory: Some(directory),
+            snapshot_count: Cell::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.directory.is_some()
+    }
+
+    /// Writes an image to `name`.tga in the dump directory. Alpha images are written in grayscale.
+    pub fn dump(&self, name: &str, buffer: &[u8], size: &Size2D<u32>, format: PixelFormat) {
+        let directory = match self.directory {
+            None => return,
+            Some(ref directory) => directory,
+        };
+        let mut path = directory.clone();
+        path.push(&format!("{}.tga", sanitize_file_name(name)));
+        write_tga(&path, buffer, size, format)
+    }
+
+    /// Returns a fresh name for an atlas snapshot, so that successive snapshots don't overwrite
+    /// each other.
+    pub fn next_snapshot_name(&self) -> String {
+        let snapshot_index = self.snapshot_count.get();
+        self.snapshot_count.set(snapshot_index + 1);
+        format!("atlas-snapshot{}", snapshot_index)
+    }
+}
+
+/// Replaces characters that can't safely appear in file names.
+fn sanitize_file_name(name: &str) -> String {
+    name.chars().map(|character| {
+        match character {
+            'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '_' | '.' | '+' => character,
+            _ => '_',
+        }
+    }).collect()
+}
+
+fn write_tga(path: &PathBuf, buffer: &[u8], size: &Size2D<u32>, format: PixelFormat) {
+    let bytes_per_pixel = format.bytes_per_pixel() as usize;
+    let mut header = [ 0; 18 ];
+    header[2] = 2;
+    header[12] = size.width as u8;
+    header[13] = (size.width >> 8) as u8;
+    header[14] = size.height as u8;
+    header[15] = (size.height >> 8) as u8;
+    match format {
+        PixelFormat::Alpha => header[16] = 24,
+        PixelFormat::Rgba => {
+            header[16] = 32;
+            header[17] = 8;
+        }
+    }
+
+    let mut data = Vec::with_capacity(header.len() + (size.width * size.height * 4) as usize);
+    data.extend(header.iter());
+    for y in 0..(size.height as usize) {
+        let y = (size.height as usize) - y - 1;
+        for x in 0..(size.width as usize) {
+            let index = bytes_per_pixel * (y * (size.width as usize) + x);
+            let pixel = &buffer[index..(index + bytes_per_pixel)];
+            match format {
+                PixelFormat::Alpha => data.extend([ pixel[0], pixel[0], pixel[0] ].iter()),
+                PixelFormat::Rgba => data.extend([ pixel[2], pixel[1], pixel[0], pixel[3] ].iter()),
+            }
+        }
+    }
+    File::create(path).unwrap().write_all(&data).unwrap()
+}
@@ -4,13 +4,17 @@
 
 use assets::Asset;
 
-use euclid::Rect;
+use euclid::{Point2D, Rect, Size2D};
 use std::cell::RefCell;
 use std::ops::{Add, Sub};
 use std::rc::Rc;
 
 const AU_PER_PX: i32 = 60;
 
+/// The distance from the origin to the edges of the largest clipping region, chosen so that the
+/// region's size comfortably fits in app units.
+const MAX_CLIP_PX: i32 = 1 << 22;
+
 pub static BLACK: Color = Color {
     r: 0,
     g: 0,
@@ -56,9 +60,58 @@ pub enum DisplayItem {
     SolidColor(Box<SolidColorDisplayItem>),
     Text(Box<TextDisplayItem>),
     Border(Box<BorderDisplayItem>),
+    Image(Box<ImageDisplayItem>),
+    LinearGradient(Box<LinearGradientDisplayItem>),
+    RadialGradient(Box<RadialGradientDisplayItem>),
 }
 
 impl DisplayItem {
+    pub fn id(&self) -> ItemId {
+        self.base().id
+    }
+
+    /// Returns true if this item draws exactly what `other` does, ignoring their IDs.
+    pub fn draws_same_as(&self, other: &DisplayItem) -> bool {
+        let (base, other_base) = (self.base(), other.base());
+        if base.bounds != other_base.bounds || base.clip != other_base.clip {
+            return false
+        }
+        match (self, other) {
+            (&DisplayItem::SolidColor(ref item), &DisplayItem::SolidColor(ref other_item)) => {
+                item.color == other_item.color
+            }
+            (&DisplayItem::Text(ref item), &DisplayItem::Text(ref other_item)) => {
+                let blurred_glyphs_match = match (&item.blurred_glyph_asset,
+                                                  &other_item.blurred_glyph_asset) {
+                    (&None, &None) => true,
+                    (&Some(ref asset), &Some(ref other_asset)) => same_asset(asset, other_asset),
+                    _ => false,
+                };
+                item.color == other_item.color &&
+                    same_asset(&item.glyph_asset, &other_item.glyph_asset) &&
+                    blurred_glyphs_match
+            }
+            (&DisplayItem::Border(ref item), &DisplayItem::Border(ref other_item)) => {
+                item.color == other_item.color && item.width == other_item.width &&
+                    item.radius == other_item.radius
+            }
+            (&DisplayItem::Image(ref item), &DisplayItem::Image(ref other_item)) => {
+                item.image_key == other_item.image_key &&
+                    item.source_rect == other_item.source_rect
+            }
+            (&DisplayItem::LinearGradient(ref item),
+             &DisplayItem::LinearGradient(ref other_item)) => {
+                item.angle == other_item.angle && item.stops == other_item.stops
+            }
+            (&DisplayItem::RadialGradient(ref item),
+             &DisplayItem::RadialGradient(ref other_item)) => {
+                item.center == other_item.center && item.radii == other_item.radii &&
+                    item.stops == other_item.stops && item.extend_mode == other_item.extend_mode
+            }
+            _ => false,
+        }
+    }
+
     pub fn base(&self) -> &BaseDisplayItem {
         match *self {
             DisplayItem::SolidColor(ref solid_color_display_item) => {
@@ -66,6 +119,13 @@ impl DisplayItem {
             }
             DisplayItem::Text(ref text_display_item) => &text_display_item.base,
             DisplayItem::Border(ref border_display_item) => &border_display_item.base,
+            DisplayItem::Image(ref image_display_item) => &image_display_item.base,
+            DisplayItem::LinearGradient(ref linear_gradient_display_item) => {
+                &linear_gradient_display_item.base
+            }
+            DisplayItem::RadialGradient(ref radial_gradient_display_item) => {
+                &radial_gradient_display_item.base
+            }
         }
     }
 }
@@ -79,6 +139,7 @@ pub struct SolidColorDisplayItem {
 #[derive(Clone)]
 pub struct TextDisplayItem {
     pub base: BaseDisplayItem,
+    pub color: Color,
     pub glyph_asset: Rc<RefCell<Asset>>,
     pub blurred_glyph_asset: Option<Rc<RefCell<Asset>>>,
 }
@@ -93,7 +154,56 @@ pub struct BorderDisplayItem {
     pub inverted_arc_asset: Rc<RefCell<Asset>>,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone)]
+pub struct ImageDisplayItem {
+    pub base: BaseDisplayItem,
+    pub image_key: ImageKey,
+    /// The part of the image, in image pixels, that is stretched over the item's bounds. Whatever
+    /// of it lies outside the image is cut off when the item is batched.
+    pub source_rect: Rect<u32>,
+    pub image_asset: Rc<RefCell<Asset>>,
+}
+
+#[derive(Clone)]
+pub struct LinearGradientDisplayItem {
+    pub base: BaseDisplayItem,
+    /// The direction of the gradient line, in degrees clockwise from upward, as in CSS. The line
+    /// runs through the center of the item's bounds.
+    pub angle: f32,
+    /// The color stops, in order of offset.
+    pub stops: Vec<GradientStop>,
+}
+
+#[derive(Clone)]
+pub struct RadialGradientDisplayItem {
+    pub base: BaseDisplayItem,
+    /// The center of the gradient, relative to the origin of the item's bounds.
+    pub center: Point2D<Au>,
+    /// The horizontal and vertical radii of the ellipse that is the gradient's ending shape.
+    pub radii: Size2D<Au>,
+    /// The color stops, in order of offset.
+    pub stops: Vec<GradientStop>,
+    pub extend_mode: ExtendMode,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct GradientStop {
+    /// How far along the gradient line the stop is, from 0 at its start to 1 at its end. For
+    /// radial gradients, the line runs from the center to the ending shape.
+    pub offset: f32,
+    pub color: Color,
+}
+
+/// What a gradient draws beyond its last color stop.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ExtendMode {
+    /// The color of the last stop.
+    Clamp,
+    /// The stops over again, as for `repeating-radial-gradient`.
+    Repeat,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 #[repr(C)]
 pub struct Color {
     pub r: u8,
@@ -114,18 +224,86 @@ impl Color {
     }
 }
 
-#[derive(Copy, Clone)]
+/// Identifies a display item from one display list to the next, so that the changes between them
+/// can be found. The items in a display list should have distinct IDs; if they don't, the changes
+/// can't be found, and everything is redrawn.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct ItemId(pub u64);
+
+/// Identifies the encoded data of an image. Images with the same key must have the same data, so
+/// that they can share one decoded copy.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ImageKey(pub u64);
+
+#[derive(Clone)]
 pub struct BaseDisplayItem {
+    pub id: ItemId,
     pub bounds: Rect<Au>,
     pub clip: ClippingRegion,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone, PartialEq)]
 pub struct ClippingRegion {
     pub main: Rect<Au>,
+    /// Rounded rectangles that the item is also clipped to, as for `overflow: hidden` on elements
+    /// with a `border-radius`.
+    pub complex: Vec<ComplexClippingRegion>,
+}
+
+impl ClippingRegion {
+    /// Returns a clipping region that clips nothing out.
+    pub fn max() -> ClippingRegion {
+        ClippingRegion {
+            main: Rect::new(Point2D::new(Au::from_px(-MAX_CLIP_PX), Au::from_px(-MAX_CLIP_PX)),
+                            Size2D::new(Au::from_px(MAX_CLIP_PX * 2),
+                                        Au::from_px(MAX_CLIP_PX * 2))),
+            complex: vec![],
+        }
+    }
+}
+
+/// A rounded rectangle to clip to.
+#[derive(Copy, Clone, PartialEq)]
+pub struct ComplexClippingRegion {
+    pub rect: Rect<Au>,
+    pub radii: BorderRadii,
+}
+
+impl ComplexClippingRegion {
+    /// Returns the square at each corner, in the order of the fields of `BorderRadii`, that the
+    /// rounding of that corner lies in.
+    pub fn corner_rects(&self) -> [Rect<Au>; 4] {
+        let (rect, radii) = (&self.rect, &self.radii);
+        let (max_x, max_y) = (rect.origin.x + rect.size.width, rect.origin.y + rect.size.height);
+        [
+            Rect::new(rect.origin, Size2D::new(radii.top_left, radii.top_left)),
+            Rect::new(Point2D::new(max_x - radii.top_right, rect.origin.y),
+                      Size2D::new(radii.top_right, radii.top_right)),
+            Rect::new(Point2D::new(max_x - radii.bottom_right, max_y - radii.bottom_right),
+                      Size2D::new(radii.bottom_right, radii.bottom_right)),
+            Rect::new(Point2D::new(rect.origin.x, max_y - radii.bottom_left),
+                      Size2D::new(radii.bottom_left, radii.bottom_left)),
+        ]
+    }
+}
+
+/// The radius of each corner of a rounded rectangle.
+#[derive(Copy, Clone, PartialEq)]
+pub struct BorderRadii {
+    pub top_left: Au,
+    pub top_right: Au,
+    pub bottom_right: Au,
+    pub bottom_left: Au,
+}
+
+impl BorderRadii {
+    pub fn is_square(&self) -> bool {
+        self.top_left == Au(0) && self.top_right == Au(0) && self.bottom_right == Au(0) &&
+            self.bottom_left == Au(0)
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct Au(pub i32);
 
 impl Add for Au {
@@ -152,5 +330,20 @@ impl Au {
     pub fn to_px(&self) -> i32 {
         self.0 / AU_PER_PX
     }
+
+    #[inline]
+    pub fn from_f64_px(pixels: f64) -> Au {
+        Au((pixels * (AU_PER_PX as f64)).round() as i32)
+    }
+
+    #[inline]
+    pub fn to_f64_px(&self) -> f64 {
+        (self.0 as f64) / (AU_PER_PX as f64)
+    }
 }
 
+/// Returns true if the two assets are the same, or would be if they were created by the same asset
+/// manager.
+fn same_asset(a: &Rc<RefCell<Asset>>, b: &Rc<RefCell<Asset>>) -> bool {
+    a.borrow().key() == b.borrow().key()
+}
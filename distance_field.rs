@@ -2,81 +2,324 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-//! A very naïve O((width * height)²) implementation of distance field generation.
+//! Distance field generation.
 //!
-//! TODO(pcwalton): Replace with a better algorithm.
+//! Glyph distance fields are built with the exact Euclidean distance transform described in:
+//!
+//! > Pedro F. Felzenszwalb and Daniel P. Huttenlocher, "Distance Transforms of Sampled
+//! > Functions", Theory of Computing 8 (2012).
+//!
+//! This runs in time linear in the number of pixels of the source bitmap: a one-dimensional
+//! transform is run over every column and then over every row of the result.
+//!
+//! When the glyph's outline is available, the field is instead computed directly from the
+//! outline's lines and curves, which avoids rasterizing the glyph at a large size first. Outlines
+//! can also be turned into multichannel fields, which preserve sharp corners under magnification.
+//!
+//! Single-channel fields have one byte per texel and multichannel fields have four (RGBA).
 
 use euclid::{Point2D, Size2D};
 use std::cmp;
 use std::f32;
 
 use assets::ArcMode;
+use outline::{BLUE, GREEN, Outline, RED};
 
 pub const BUFFER: u8 = 192;
 
 pub const GLYPH_DISTANCE_SCALING_FACTOR: f32 = 2.0;
 const ARC_DISTANCE_SCALING_FACTOR: f32 = 256.0;
 
+/// The distance, in glyph pixels, reported when no pixel of the opposite kind exists.
+const MAX_GLYPH_DISTANCE: f32 = 127.0;
+
+/// Distances to outline segments closer together than this are considered equal.
+const DISTANCE_EPSILON: f32 = 1e-4;
+
+/// Stands in for an infinite squared distance in the distance transform.
+const INFINITY: f32 = 1e20;
+
 pub fn build_distance_field_for_glyph(data: &[u8],
                                       glyph_size: &Size2D<u32>,
                                       glyph_size_in_field: &Size2D<u32>,
                                       field_size: &Size2D<u32>)
                                       -> Vec<u8> {
-    let mut result = Vec::with_capacity((field_size.width * field_size.height * 4) as usize);
+    let mut result = Vec::with_capacity((field_size.width * field_size.height) as usize);
     let offset_from_field_to_glyph =
         Point2D::new(((field_size.width - glyph_size_in_field.width) / 2),
                      ((field_size.height - glyph_size_in_field.height) / 2));
     let ratio = (glyph_size.width as f32) / (glyph_size_in_field.width as f32);
+    let field_point_to_glyph_point = |x0: u32, y0: u32| {
+        Point2D::new(((((x0 as i32) - (offset_from_field_to_glyph.x as i32)) as f32) * ratio)
+                        as i32,
+                     ((((y0 as i32) - (offset_from_field_to_glyph.y as i32)) as f32) * ratio)
+                        as i32)
+    };
+
+    // The field samples points around the glyph as well as inside it, so run the transform over
+    // a grid that covers both the bitmap and every sampled point.
+    let first_sample = field_point_to_glyph_point(0, 0);
+    let last_sample = field_point_to_glyph_point(field_size.width - 1, field_size.height - 1);
+    let grid_origin = Point2D::new(cmp::min(first_sample.x, 0), cmp::min(first_sample.y, 0));
+    let grid_size = Size2D::new(
+        (cmp::max(last_sample.x + 1, glyph_size.width as i32) - grid_origin.x) as usize,
+        (cmp::max(last_sample.y + 1, glyph_size.height as i32) - grid_origin.y) as usize);
+
+    let is_inside_glyph = |point: &Point2D<i32>| {
+        point.x >= 0 && point.y >= 0 &&
+            point.x < glyph_size.width as i32 && point.y < glyph_size.height as i32 &&
+            data[(point.y * (glyph_size.width as i32) + point.x) as usize] != 0
+    };
+    let is_outside_glyph = |point: &Point2D<i32>| {
+        point.x >= 0 && point.y >= 0 &&
+            point.x < glyph_size.width as i32 && point.y < glyph_size.height as i32 &&
+            data[(point.y * (glyph_size.width as i32) + point.x) as usize] == 0
+    };
+
+    // Only pixels of the bitmap count as edges, so the padding around it is neither inside nor
+    // outside the glyph.
+    let distances_to_inside =
+        squared_distance_transform(&grid_origin, &grid_size, &is_inside_glyph);
+    let distances_to_outside =
+        squared_distance_transform(&grid_origin, &grid_size, &is_outside_glyph);
+
     for y0 in 0..field_size.height {
         for x0 in 0..field_size.width {
-            let glyph_point_inside_field =
-                Point2D::new((x0 as i32) - (offset_from_field_to_glyph.x as i32),
-                             (y0 as i32) - (offset_from_field_to_glyph.y as i32));
-            let glyph_point = Point2D::new(((glyph_point_inside_field.x as f32) * ratio) as i32,
-                                           ((glyph_point_inside_field.y as f32) * ratio) as i32);
-            let inside_glyph = glyph_point.x > 0 && glyph_point.y > 0 &&
-                glyph_point.x < glyph_size.width as i32 &&
-                glyph_point.y < glyph_size.height as i32 &&
-                data[(glyph_point.y * (glyph_size.width as i32) + glyph_point.x) as usize] != 0;
-            let mut distance = 127.0;
-            for y1 in 0..glyph_size.height {
-                for x1 in 0..glyph_size.width {
-                    if glyph_point == Point2D::new(x1 as i32, y1 as i32) {
-                        continue
-                    }
-                    let test_point_inside_glyph = data[(y1 * glyph_size.width + x1) as usize] != 0;
-                    if test_point_inside_glyph == inside_glyph {
+            let glyph_point = field_point_to_glyph_point(x0, y0);
+            let index = ((glyph_point.y - grid_origin.y) as usize) * grid_size.width +
+                ((glyph_point.x - grid_origin.x) as usize);
+            let inside_glyph = is_inside_glyph(&glyph_point);
+            let squared_distance = if inside_glyph {
+                distances_to_outside[index]
+            } else {
+                distances_to_inside[index]
+            };
+            let distance = if squared_distance >= INFINITY {
+                MAX_GLYPH_DISTANCE
+            } else {
+                f32::min(f32::sqrt(squared_distance), MAX_GLYPH_DISTANCE)
+            };
+            result.push(encode_glyph_distance(distance, inside_glyph));
+        }
+    }
+    result
+}
+
+/// Builds a distance field from a glyph outline that has already been transformed into field
+/// coordinates. `distance_scale` converts distances in field texels to pixels of the glyph
+/// rasterized at full size, so that the result has the same encoding as a field built from a
+/// bitmap.
+pub fn build_distance_field_for_outline(outline: &Outline,
+                                        field_size: &Size2D<u32>,
+                                        distance_scale: f32)
+                                        -> Vec<u8> {
+    let mut result = Vec::with_capacity((field_size.width * field_size.height) as usize);
+    let flattened_outline = outline.flatten();
+    for y in 0..field_size.height {
+        for x in 0..field_size.width {
+            let point = Point2D::new((x as f32) + 0.5, (y as f32) + 0.5);
+            let inside_glyph = flattened_outline.contains(&point);
+            let distance = f32::min(outline.distance(&point) * distance_scale,
+                                    MAX_GLYPH_DISTANCE);
+            result.push(encode_glyph_distance(distance, inside_glyph));
+        }
+    }
+    result
+}
+
+/// Builds a multichannel distance field from a glyph outline that has already been transformed
+/// into field coordinates. The red, green, and blue channels hold signed pseudo-distances to the
+/// edges of each color, and the median of the three reconstructs the glyph with sharp corners.
+/// The alpha channel holds the ordinary single-channel field, for consumers that don't decode the
+/// other channels (such as blurs).
+pub fn build_multichannel_distance_field_for_outline(outline: &Outline,
+                                                     field_size: &Size2D<u32>,
+                                                     distance_scale: f32)
+                                                     -> Vec<u8> {
+    let mut result = Vec::with_capacity((field_size.width * field_size.height * 4) as usize);
+    let flattened_outline = outline.flatten();
+    let colored_contours = outline.color_edges();
+
+    // Pseudo-distances are signed by which side of its edge a point is on, so account for the
+    // direction the contours wind in.
+    let orientation = if outline.signed_area() < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+
+    for y in 0..field_size.height {
+        for x in 0..field_size.width {
+            let point = Point2D::new((x as f32) + 0.5, (y as f32) + 0.5);
+
+            let mut pixel = [ 0; 4 ];
+            for (channel, &channel_color) in [ RED, GREEN, BLUE ].iter().enumerate() {
+                let (mut closest_distance, mut closest_orthogonality) = (f32::INFINITY, 1.0);
+                let mut closest_segment = None;
+                for &(ref segment, color) in colored_contours.iter()
+                                                             .flat_map(|contour| contour.iter()) {
+                    if color & channel_color == 0 {
                         continue
                     }
-                    let (x0, y0) = (glyph_point.x as f32, glyph_point.y as f32);
-                    let (x1, y1) = (x1 as f32, y1 as f32);
-                    let (y_delta, x_delta) = (y1 - y0, x1 - x0);
-                    let this_distance = f32::sqrt(y_delta * y_delta + x_delta * x_delta);
-                    if this_distance < distance {
-                        distance = this_distance
+                    let (distance, orthogonality) = segment.distance_and_orthogonality(&point);
+                    if distance < closest_distance - DISTANCE_EPSILON ||
+                            (distance < closest_distance + DISTANCE_EPSILON &&
+                             orthogonality < closest_orthogonality) {
+                        closest_distance = distance;
+                        closest_orthogonality = orthogonality;
+                        closest_segment = Some(segment)
                     }
                 }
+
+                let signed_distance = match closest_segment {
+                    None => -MAX_GLYPH_DISTANCE,
+                    Some(segment) => {
+                        segment.signed_pseudo_distance(&point) * orientation * distance_scale
+                    }
+                };
+                pixel[channel] = encode_glyph_distance(f32::min(signed_distance.abs(),
+                                                                MAX_GLYPH_DISTANCE),
+                                                       signed_distance > 0.0);
             }
-            let mut value = if inside_glyph {
-                (BUFFER as i64 + (((distance * GLYPH_DISTANCE_SCALING_FACTOR) -
-                                   GLYPH_DISTANCE_SCALING_FACTOR) as i64))
+
+            let inside_glyph = flattened_outline.contains(&point);
+            let distance = f32::min(outline.distance(&point) * distance_scale,
+                                    MAX_GLYPH_DISTANCE);
+            pixel[3] = encode_glyph_distance(distance, inside_glyph);
+            result.extend(pixel.iter());
+        }
+    }
+    result
+}
+
+/// Encodes a distance, in pixels of the glyph rasterized at full size, into a distance field
+/// value.
+pub fn encode_glyph_distance(distance: f32, inside_glyph: bool) -> u8 {
+    let mut value = if inside_glyph {
+        (BUFFER as i64 + (((distance * GLYPH_DISTANCE_SCALING_FACTOR) -
+                           GLYPH_DISTANCE_SCALING_FACTOR) as i64))
+    } else {
+        (BUFFER as i64 - ((distance * GLYPH_DISTANCE_SCALING_FACTOR) as i64))
+    };
+    if value < 0 {
+        value = 0
+    } else if value > 255 {
+        value = 255
+    }
+    value as u8
+}
+
+/// Computes, for every point of the grid, the squared Euclidean distance to the nearest point for
+/// which `is_feature` returns true. Points with no feature anywhere in the grid get `INFINITY`.
+fn squared_distance_transform<F>(grid_origin: &Point2D<i32>,
+                                 grid_size: &Size2D<usize>,
+                                 is_feature: &F)
+                                 -> Vec<f32>
+                                 where F: Fn(&Point2D<i32>) -> bool {
+    let (width, height) = (grid_size.width, grid_size.height);
+    let mut distances = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let point = Point2D::new(grid_origin.x + x as i32, grid_origin.y + y as i32);
+            distances.push(if is_feature(&point) {
+                0.0
             } else {
-                (BUFFER as i64 - ((distance * GLYPH_DISTANCE_SCALING_FACTOR) as i64))
-            };
-            if value < 0 {
-                value = 0
-            } else if value > 255 {
-                value = 255
+                INFINITY
+            })
+        }
+    }
+
+    let mut scratch = DistanceTransformScratch::new(cmp::max(width, height));
+
+    // Columns first...
+    for x in 0..width {
+        for y in 0..height {
+            scratch.input[y] = distances[y * width + x]
+        }
+        scratch.transform(height);
+        for y in 0..height {
+            distances[y * width + x] = scratch.output[y]
+        }
+    }
+
+    // ...then rows.
+    for y in 0..height {
+        for x in 0..width {
+            scratch.input[x] = distances[y * width + x]
+        }
+        scratch.transform(width);
+        for x in 0..width {
+            distances[y * width + x] = scratch.output[x]
+        }
+    }
+
+    distances
+}
+
+/// Buffers for the one-dimensional distance transform, reused across rows and columns.
+struct DistanceTransformScratch {
+    input: Vec<f32>,
+    output: Vec<f32>,
+    /// Locations of the parabolas in the lower envelope.
+    parabolas: Vec<usize>,
+    /// Boundaries between the parabolas in the lower envelope.
+    boundaries: Vec<f32>,
+}
+
+impl DistanceTransformScratch {
+    fn new(length: usize) -> DistanceTransformScratch {
+        DistanceTransformScratch {
+            input: vec![0.0; length],
+            output: vec![0.0; length],
+            parabolas: vec![0; length],
+            boundaries: vec![0.0; length + 1],
+        }
+    }
+
+    /// Transforms the first `length` values of `input` into `output`.
+    fn transform(&mut self, length: usize) {
+        if length == 0 {
+            return
+        }
+
+        let input = &self.input;
+        let intersection = |q: usize, p: usize| {
+            ((input[q] + (q * q) as f32) - (input[p] + (p * p) as f32)) /
+                (2.0 * (q as f32 - p as f32))
+        };
+
+        // Compute the lower envelope of the parabolas rooted at each point.
+        let mut k = 0;
+        self.parabolas[0] = 0;
+        self.boundaries[0] = -INFINITY;
+        self.boundaries[1] = INFINITY;
+        for q in 1..length {
+            let mut s = intersection(q, self.parabolas[k]);
+            while s <= self.boundaries[k] {
+                k -= 1;
+                s = intersection(q, self.parabolas[k]);
             }
-            let value = value as u8;
-            result.extend([ 255, 255, 255, value ].iter());
+            k += 1;
+            self.parabolas[k] = q;
+            self.boundaries[k] = s;
+            self.boundaries[k + 1] = INFINITY;
+        }
+
+        // Sample it.
+        k = 0;
+        for q in 0..length {
+            while self.boundaries[k + 1] < q as f32 {
+                k += 1
+            }
+            let p = self.parabolas[k];
+            let delta = q as f32 - p as f32;
+            self.output[q] = delta * delta + input[p];
         }
     }
-    result
 }
 
 pub fn build_distance_field_for_arc(size: u32, radius: u32, mode: ArcMode) -> Vec<u8> {
-    let mut result = Vec::with_capacity((size * size * 4) as usize);
+    let mut result = Vec::with_capacity((size * size) as usize);
     let radius = radius as f32;
     for y in 0..size {
         for x in 0..size {
@@ -95,11 +338,216 @@ pub fn build_distance_field_for_arc(size: u32, radius: u32, mode: ArcMode) -> Ve
                 scaled_distance = 255.0
             }
 
-
-            let value = scaled_distance as u8;
-            result.extend([ 255, 255, 255, value ].iter());
+            result.push(scaled_distance as u8);
         }
     }
     result
 }
 
+
+#[cfg(test)]
+mod tests {
+    use euclid::{Point2D, Size2D};
+    use std::cmp;
+    use std::f32;
+
+    use outline::{Outline, OutlineSegment, length, sub};
+    use super::{build_distance_field_for_glyph, build_distance_field_for_outline};
+    use super::{build_multichannel_distance_field_for_outline, encode_glyph_distance};
+
+    /// A quadratic-time reference for the distance transform: every sample measures the distance
+    /// to each pixel of the bitmap on the other side of the edge. This is the construction the
+    /// module started with, except that samples in the bitmap's first row and column are inside
+    /// if their pixel is set, where it treated them as outside. That made it skip each sample's
+    /// own pixel, which is never on the other side of the edge now.
+    fn build_distance_field_for_glyph_naively(data: &[u8],
+                                              glyph_size: &Size2D<u32>,
+                                              glyph_size_in_field: &Size2D<u32>,
+                                              field_size: &Size2D<u32>)
+                                              -> Vec<u8> {
+        let mut result = Vec::with_capacity((field_size.width * field_size.height) as usize);
+        let offset_from_field_to_glyph =
+            Point2D::new(((field_size.width - glyph_size_in_field.width) / 2),
+                         ((field_size.height - glyph_size_in_field.height) / 2));
+        let ratio = (glyph_size.width as f32) / (glyph_size_in_field.width as f32);
+        for y0 in 0..field_size.height {
+            for x0 in 0..field_size.width {
+                let glyph_point_inside_field =
+                    Point2D::new((x0 as i32) - (offset_from_field_to_glyph.x as i32),
+                                 (y0 as i32) - (offset_from_field_to_glyph.y as i32));
+                let glyph_point =
+                    Point2D::new(((glyph_point_inside_field.x as f32) * ratio) as i32,
+                                 ((glyph_point_inside_field.y as f32) * ratio) as i32);
+                let inside_glyph = glyph_point.x >= 0 && glyph_point.y >= 0 &&
+                    glyph_point.x < glyph_size.width as i32 &&
+                    glyph_point.y < glyph_size.height as i32 &&
+                    data[(glyph_point.y * (glyph_size.width as i32) + glyph_point.x) as usize] !=
+                    0;
+                let mut distance = 127.0;
+                for y1 in 0..glyph_size.height {
+                    for x1 in 0..glyph_size.width {
+                        let test_point_inside_glyph =
+                            data[(y1 * glyph_size.width + x1) as usize] != 0;
+                        if test_point_inside_glyph == inside_glyph {
+                            continue
+                        }
+                        let (x0, y0) = (glyph_point.x as f32, glyph_point.y as f32);
+                        let (x1, y1) = (x1 as f32, y1 as f32);
+                        let (y_delta, x_delta) = (y1 - y0, x1 - x0);
+                        let this_distance = f32::sqrt(y_delta * y_delta + x_delta * x_delta);
+                        if this_distance < distance {
+                            distance = this_distance
+                        }
+                    }
+                }
+                result.push(encode_glyph_distance(distance, inside_glyph));
+            }
+        }
+        result
+    }
+
+    fn bitmap<F>(size: &Size2D<u32>, is_inside: F) -> Vec<u8> where F: Fn(u32, u32) -> bool {
+        let mut data = Vec::with_capacity((size.width * size.height) as usize);
+        for y in 0..size.height {
+            for x in 0..size.width {
+                data.push(if is_inside(x, y) {
+                    255
+                } else {
+                    0
+                })
+            }
+        }
+        data
+    }
+
+    /// Checks the field of the bitmap drawn at its own size with a border of four texels, and
+    /// drawn at half its size, against the naive construction.
+    fn check_against_naive_field(data: &[u8], glyph_size: &Size2D<u32>) {
+        let half_size = Size2D::new(glyph_size.width / 2, glyph_size.height / 2);
+        for &glyph_size_in_field in &[*glyph_size, half_size] {
+            let field_size = Size2D::new(glyph_size_in_field.width + 8,
+                                         glyph_size_in_field.height + 8);
+            assert_eq!(build_distance_field_for_glyph(data,
+                                                      glyph_size,
+                                                      &glyph_size_in_field,
+                                                      &field_size),
+                       build_distance_field_for_glyph_naively(data,
+                                                              glyph_size,
+                                                              &glyph_size_in_field,
+                                                              &field_size));
+        }
+    }
+
+    #[test]
+    fn single_dot() {
+        let size = Size2D::new(8, 8);
+        check_against_naive_field(&bitmap(&size, |x, y| x == 3 && y == 4), &size)
+    }
+
+    #[test]
+    fn rectangle() {
+        let size = Size2D::new(12, 10);
+        check_against_naive_field(&bitmap(&size, |x, y| x >= 2 && x < 9 && y >= 3 && y < 7),
+                                  &size)
+    }
+
+    #[test]
+    fn diagonal_edge() {
+        let size = Size2D::new(10, 10);
+        check_against_naive_field(&bitmap(&size, |x, y| x + y < 9), &size)
+    }
+
+    #[test]
+    fn empty_bitmap() {
+        let size = Size2D::new(6, 6);
+        check_against_naive_field(&bitmap(&size, |_, _| false), &size)
+    }
+
+    #[test]
+    fn full_bitmap() {
+        let size = Size2D::new(6, 6);
+        check_against_naive_field(&bitmap(&size, |_, _| true), &size)
+    }
+
+    /// Checks that the median of the channels of the multichannel field is within one of the
+    /// single-channel field, except where the closest point on the outline is one of `corners`,
+    /// which the multichannel field keeps sharp instead of rounding off.
+    fn check_multichannel_median(outline: &Outline, corners: &[(f32, f32)]) {
+        let field_size = Size2D::new(32, 32);
+        let field = build_distance_field_for_outline(outline, &field_size, 4.0);
+        let multichannel_field =
+            build_multichannel_distance_field_for_outline(outline, &field_size, 4.0);
+        for y in 0..field_size.height {
+            for x in 0..field_size.width {
+                let point = Point2D::new((x as f32) + 0.5, (y as f32) + 0.5);
+                let corner_distance = corners.iter().map(|&(corner_x, corner_y)| {
+                    length(&sub(&Point2D::new(corner_x, corner_y), &point))
+                }).fold(f32::INFINITY, f32::min);
+                if corner_distance < outline.distance(&point) + 1.0 {
+                    continue
+                }
+
+                let index = (y * field_size.width + x) as usize;
+                let pixel = &multichannel_field[(index * 4)..(index * 4 + 4)];
+                let (red, green, blue) = (pixel[0], pixel[1], pixel[2]);
+                let median = cmp::max(cmp::min(red, green), cmp::min(cmp::max(red, green), blue));
+                assert!((median as i32 - field[index] as i32).abs() <= 1,
+                        "the median at {:?} is {}, but the field is {}",
+                        point,
+                        median,
+                        field[index]);
+                assert_eq!(pixel[3], field[index]);
+            }
+        }
+    }
+
+    fn polygon(points: &[(f32, f32)]) -> Vec<OutlineSegment> {
+        (0..points.len()).map(|index| {
+            let (start, end) = (points[index], points[(index + 1) % points.len()]);
+            OutlineSegment::Line(Point2D::new(start.0, start.1), Point2D::new(end.0, end.1))
+        }).collect()
+    }
+
+    #[test]
+    fn multichannel_triangle() {
+        let corners = [(6.0, 26.0), (16.0, 6.0), (26.0, 26.0)];
+        let mut outline = Outline::new();
+        outline.contours.push(polygon(&corners));
+        check_multichannel_median(&outline, &corners)
+    }
+
+    #[test]
+    fn multichannel_square_with_hole() {
+        let outer_corners = [(6.0, 6.0), (26.0, 6.0), (26.0, 26.0), (6.0, 26.0)];
+        let inner_corners = [(12.0, 12.0), (12.0, 20.0), (20.0, 20.0), (20.0, 12.0)];
+        let mut outline = Outline::new();
+        outline.contours.push(polygon(&outer_corners));
+        outline.contours.push(polygon(&inner_corners));
+        let corners: Vec<_> = outer_corners.iter().chain(inner_corners.iter()).cloned().collect();
+        check_multichannel_median(&outline, &corners)
+    }
+
+    #[test]
+    fn multichannel_curves() {
+        // A "D", with corners where its straight side meets its curved one.
+        let mut outline = Outline::new();
+        outline.contours.push(vec![
+            OutlineSegment::Line(Point2D::new(8.0, 26.0), Point2D::new(8.0, 6.0)),
+            OutlineSegment::Cubic(Point2D::new(8.0, 6.0),
+                                  Point2D::new(30.0, 6.0),
+                                  Point2D::new(30.0, 26.0),
+                                  Point2D::new(8.0, 26.0)),
+        ]);
+        check_multichannel_median(&outline, &[(8.0, 6.0), (8.0, 26.0)]);
+
+        // A teardrop, whose single corner is where its curve meets itself.
+        let mut outline = Outline::new();
+        outline.contours.push(vec![
+            OutlineSegment::Cubic(Point2D::new(6.0, 16.0),
+                                  Point2D::new(40.0, -8.0),
+                                  Point2D::new(40.0, 40.0),
+                                  Point2D::new(6.0, 16.0)),
+        ]);
+        check_multichannel_median(&outline, &[(6.0, 16.0)])
+    }
+}
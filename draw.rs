@@ -5,25 +5,46 @@
 use atlas::Atlas;
 use batch::Batch;
 
+use euclid::{Rect, Size2D};
 use gleam::gl::{self, GLenum, GLint, GLuint};
 use std::cell::RefCell;
 use std::mem;
 use std::rc::Rc;
 
+/// The depth the depth buffer is cleared to. Items are drawn without writing depth, and only
+/// those nearer than this are drawn.
+pub const CLEAR_DEPTH: f64 = 0.5;
+
+/// The value the stencil buffer is cleared to. Items are drawn only where the stencil is set, so
+/// clearing it to set lets items draw everywhere until a clip is drawn into it.
+pub const CLEAR_STENCIL: u8 = 1;
+
 static VERTEX_SHADER: &'static str = "
     attribute vec3 aVertexPosition;
     attribute vec4 aVertexColor;
     attribute vec2 aBufferGamma;
     attribute vec2 aTextureCoord;
+    attribute float aTextureMode;
+    attribute vec2 aClipCoord;
+    attribute vec4 aClipRect;
+    attribute vec4 aClipRadii;
 
     varying vec4 vVertexColor;
     varying vec2 vBufferGamma;
     varying vec2 vTextureCoord;
+    varying float vTextureMode;
+    varying vec2 vClipCoord;
+    varying vec4 vClipRect;
+    varying vec4 vClipRadii;
 
     void main() {
         vVertexColor = aVertexColor / 255.0;
         vBufferGamma = aBufferGamma;
         vTextureCoord = aTextureCoord;
+        vTextureMode = aTextureMode;
+        vClipCoord = aClipCoord;
+        vClipRect = aClipRect;
+        vClipRadii = aClipRadii;
         gl_Position = vec4(aVertexPosition, 1.0);
     }
 ";
@@ -38,19 +59,72 @@ static FRAGMENT_SHADER: &'static str = "
     varying vec4 vVertexColor;
     varying vec2 vBufferGamma;
     varying vec2 vTextureCoord;
+    varying float vTextureMode;
+    varying vec2 vClipCoord;
+    varying vec4 vClipRect;
+    varying vec4 vClipRadii;
+
+    float median(float a, float b, float c) {
+        return max(min(a, b), min(max(a, b), c));
+    }
+
+    // Returns how much of the pixel at the given position, in device pixels, is inside the rounded
+    // corners of the clip. The straight edges are clipped by trimming vertices instead, and
+    // vertices that aren't clipped to a rounded rectangle have all radii zero.
+    float roundedClipCoverage(vec2 position, vec4 rect, vec4 radii) {
+        vec2 lCenter;
+        float lRadius;
+        if (radii.x > 0.0 && position.x < rect.x + radii.x && position.y < rect.y + radii.x) {
+            lRadius = radii.x;
+            lCenter = vec2(rect.x + lRadius, rect.y + lRadius);
+        } else if (radii.y > 0.0 && position.x > rect.z - radii.y &&
+                   position.y < rect.y + radii.y) {
+            lRadius = radii.y;
+            lCenter = vec2(rect.z - lRadius, rect.y + lRadius);
+        } else if (radii.z > 0.0 && position.x > rect.z - radii.z &&
+                   position.y > rect.w - radii.z) {
+            lRadius = radii.z;
+            lCenter = vec2(rect.z - lRadius, rect.w - lRadius);
+        } else if (radii.w > 0.0 && position.x < rect.x + radii.w &&
+                   position.y > rect.w - radii.w) {
+            lRadius = radii.w;
+            lCenter = vec2(rect.x + lRadius, rect.w - lRadius);
+        } else {
+            return 1.0;
+        }
+        return clamp(lRadius - distance(position, lCenter) + 0.5, 0.0, 1.0);
+    }
 
     void main() {
-        /*vec4 lTextureColor = vec4(0.0, 0.0, 0.0, 0.0);
-        if (vTextureCoord != vec2(0.0, 0.0))
-            lTextureColor = texture2D(uTexture, vTextureCoord);
-        float lAlpha = smoothstep(vBufferGamma[0] - vBufferGamma[1],
-                                  vBufferGamma[0] + vBufferGamma[1],
-                                  lTextureColor.a);
-        vec4 lColor = vec4(lTextureColor.rgb, lAlpha) + vVertexColor;
-        if (lColor.ga == vec2(0.0, 0.0))
-            discard;*/
-        vec4 lColor = vVertexColor;
-        gl_FragColor = lColor;
+        // Sample even for untextured items, since texture lookups inside branches that vary
+        // across a primitive have undefined derivatives.
+        vec4 lTextureColor = texture2D(uTexture, vTextureCoord);
+
+        // The texture mode values match the `TEXTURE_MODE_*` constants in `batch.rs`. Images
+        // are drawn in their own colors below.
+        float lCoverage = 1.0;
+        if (vTextureMode > 3.5) {
+        } else if (vTextureMode > 2.5) {
+            lCoverage = lTextureColor.a;
+        } else if (vTextureMode > 0.5) {
+            // Multichannel distance fields store one distance per color channel; the median of
+            // the three is the distance to the glyph's edge, with corners preserved.
+            float lDistance = lTextureColor.a;
+            if (vTextureMode > 1.5)
+                lDistance = median(lTextureColor.r, lTextureColor.g, lTextureColor.b);
+            lCoverage = smoothstep(vBufferGamma[0] - vBufferGamma[1],
+                                   vBufferGamma[0] + vBufferGamma[1],
+                                   lDistance);
+        }
+
+        lCoverage *= roundedClipCoverage(vClipCoord, vClipRect, vClipRadii);
+
+        // Output premultiplied alpha. Images are premultiplied already.
+        float lAlpha = vVertexColor.a * lCoverage;
+        if (vTextureMode > 3.5)
+            gl_FragColor = lTextureColor * lAlpha;
+        else
+            gl_FragColor = vec4(vVertexColor.rgb * lAlpha, lAlpha);
     }
 ";
 
@@ -59,16 +133,24 @@ struct DrawBuffers {
     vertex_color_buffer: GLuint,
     buffer_gamma_buffer: GLuint,
     texture_coord_buffer: GLuint,
+    texture_mode_buffer: GLuint,
+    clip_coord_buffer: GLuint,
+    clip_rect_buffer: GLuint,
+    clip_radii_buffer: GLuint,
 }
 
 impl DrawBuffers {
     fn new() -> DrawBuffers {
-        let buffers = gl::gen_buffers(4);
+        let buffers = gl::gen_buffers(8);
         DrawBuffers {
             vertex_position_buffer: buffers[0],
             vertex_color_buffer: buffers[1],
             buffer_gamma_buffer: buffers[2],
             texture_coord_buffer: buffers[3],
+            texture_mode_buffer: buffers[4],
+            clip_coord_buffer: buffers[5],
+            clip_rect_buffer: buffers[6],
+            clip_radii_buffer: buffers[7],
         }
     }
 }
@@ -85,6 +167,10 @@ struct Program {
     vertex_color_attribute: GLuint,
     buffer_gamma_attribute: GLuint,
     texture_coord_attribute: GLuint,
+    texture_mode_attribute: GLuint,
+    clip_coord_attribute: GLuint,
+    clip_rect_attribute: GLuint,
+    clip_radii_attribute: GLuint,
     texture_uniform: GLuint,
 }
 
@@ -118,17 +204,29 @@ impl Program {
         let vertex_color_attribute = gl::get_attrib_location(program, "aVertexColor");
         let buffer_gamma_attribute = gl::get_attrib_location(program, "aBufferGamma");
         let texture_coord_attribute = gl::get_attrib_location(program, "aTextureCoord");
+        let texture_mode_attribute = gl::get_attrib_location(program, "aTextureMode");
+        let clip_coord_attribute = gl::get_attrib_location(program, "aClipCoord");
+        let clip_rect_attribute = gl::get_attrib_location(program, "aClipRect");
+        let clip_radii_attribute = gl::get_attrib_location(program, "aClipRadii");
         let texture_uniform = gl::get_uniform_location(program, "uTexture");
         gl::enable_vertex_attrib_array(vertex_position_attribute as GLuint);
         gl::enable_vertex_attrib_array(vertex_color_attribute as GLuint);
         gl::enable_vertex_attrib_array(buffer_gamma_attribute as GLuint);
         gl::enable_vertex_attrib_array(texture_coord_attribute as GLuint);
+        gl::enable_vertex_attrib_array(texture_mode_attribute as GLuint);
+        gl::enable_vertex_attrib_array(clip_coord_attribute as GLuint);
+        gl::enable_vertex_attrib_array(clip_rect_attribute as GLuint);
+        gl::enable_vertex_attrib_array(clip_radii_attribute as GLuint);
         Program {
             program: program,
             vertex_position_attribute: vertex_position_attribute as GLuint,
             vertex_color_attribute: vertex_color_attribute as GLuint,
             buffer_gamma_attribute: buffer_gamma_attribute as GLuint,
             texture_coord_attribute: texture_coord_attribute as GLuint,
+            texture_mode_attribute: texture_mode_attribute as GLuint,
+            clip_coord_attribute: clip_coord_attribute as GLuint,
+            clip_rect_attribute: clip_rect_attribute as GLuint,
+            clip_radii_attribute: clip_radii_attribute as GLuint,
             texture_uniform: texture_uniform as GLuint,
         }
     }
@@ -149,9 +247,11 @@ impl DrawContext {
         gl::enable(gl::BLEND);
         gl::enable(gl::STENCIL_TEST);
         gl::enable(gl::DEPTH_TEST);
-        gl::blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        gl::blend_func(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+        // Back-facing clip quads zero the stencil where they fail the depth test and set it where
+        // they pass; front-facing items draw only where it's set. See `Batch::add_clip()`.
         gl::stencil_mask(1);
-        gl::stencil_func_separate(gl::FRONT, gl::GREATER, 1, 1);
+        gl::stencil_func_separate(gl::FRONT, gl::EQUAL, 1, 1);
         gl::stencil_func_separate(gl::BACK, gl::ALWAYS, 1, 1);
         gl::stencil_op_separate(gl::FRONT, gl::KEEP, gl::KEEP, gl::KEEP);
         gl::stencil_op_separate(gl::BACK, gl::KEEP, gl::ZERO, gl::REPLACE);
@@ -159,14 +259,52 @@ impl DrawContext {
 
     pub fn clear(&mut self) {
         gl::depth_mask(true);
-        gl::clear_depth(0.5);
+        gl::clear_depth(CLEAR_DEPTH);
+        gl::clear_stencil(CLEAR_STENCIL as GLint);
         gl::clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
         gl::depth_mask(false);
     }
 
+    /// Restricts drawing and clearing to a rectangle of the render target, in device pixels from
+    /// the top left, or lifts the restriction if there is none.
+    pub fn set_scissor_rect(&mut self, rect: Option<&Rect<i32>>, render_target_size: &Size2D<i32>) {
+        match rect {
+            None => gl::disable(gl::SCISSOR_TEST),
+            Some(rect) => {
+                // GL window coordinates start from the bottom left.
+                gl::enable(gl::SCISSOR_TEST);
+                gl::scissor(rect.origin.x,
+                            render_target_size.height - rect.max_y(),
+                            rect.size.width,
+                            rect.size.height);
+            }
+        }
+    }
+
+    /// Clears and redraws only the damaged rectangles of the render target, leaving the rest of
+    /// the last frame in place.
+    pub fn draw_damaged_rects(&mut self,
+                              batches: &[Batch],
+                              damaged_rects: &[Rect<i32>],
+                              render_target_size: &Size2D<i32>) {
+        let batch_bounds: Vec<_> = batches.iter().map(|batch| batch.device_bounds()).collect();
+        for damaged_rect in damaged_rects.iter() {
+            self.set_scissor_rect(Some(damaged_rect), render_target_size);
+            self.clear();
+            for (batch, bounds) in batches.iter().zip(batch_bounds.iter()) {
+                if bounds.map_or(false, |bounds| bounds.intersects(damaged_rect)) {
+                    self.draw_batch(batch)
+                }
+            }
+        }
+        self.set_scissor_rect(None, render_target_size)
+    }
+
     pub fn draw_batch(&mut self, batch: &Batch) {
+        // Batches without textured items can use any page.
         gl::active_texture(gl::TEXTURE0);
-        gl::bind_texture(gl::TEXTURE_2D, self.atlas.borrow().texture);
+        gl::bind_texture(gl::TEXTURE_2D,
+                         self.atlas.borrow().texture_for_page(batch.page.unwrap_or(0)));
         gl::uniform_1i(self.program.texture_uniform as GLint, 0);
 
         self.buffer_data_for_batch(batch);
@@ -200,6 +338,24 @@ impl DrawContext {
         gl::buffer_data(gl::ARRAY_BUFFER, &batch.texture_coords[..], gl::DYNAMIC_DRAW);
         gl::vertex_attrib_pointer_f32(self.program.texture_coord_attribute, 2, false, 0, 0);
         debug!("... texture coords: {:?}", &batch.texture_coords[..]);
+
+        gl::bind_buffer(gl::ARRAY_BUFFER, self.buffers.texture_mode_buffer);
+        gl::buffer_data(gl::ARRAY_BUFFER, &batch.texture_modes[..], gl::DYNAMIC_DRAW);
+        gl::vertex_attrib_pointer_f32(self.program.texture_mode_attribute, 1, false, 0, 0);
+        debug!("... texture modes: {:?}", &batch.texture_modes[..]);
+
+        gl::bind_buffer(gl::ARRAY_BUFFER, self.buffers.clip_coord_buffer);
+        gl::buffer_data(gl::ARRAY_BUFFER, &batch.clip_coords[..], gl::DYNAMIC_DRAW);
+        gl::vertex_attrib_pointer_f32(self.program.clip_coord_attribute, 2, false, 0, 0);
+
+        gl::bind_buffer(gl::ARRAY_BUFFER, self.buffers.clip_rect_buffer);
+        gl::buffer_data(gl::ARRAY_BUFFER, &batch.clip_rects[..], gl::DYNAMIC_DRAW);
+        gl::vertex_attrib_pointer_f32(self.program.clip_rect_attribute, 4, false, 0, 0);
+
+        gl::bind_buffer(gl::ARRAY_BUFFER, self.buffers.clip_radii_buffer);
+        gl::buffer_data(gl::ARRAY_BUFFER, &batch.clip_radii[..], gl::DYNAMIC_DRAW);
+        gl::vertex_attrib_pointer_f32(self.program.clip_radii_attribute, 4, false, 0, 0);
+        debug!("... clip rects: {:?}, radii: {:?}", &batch.clip_rects[..], &batch.clip_radii[..]);
     }
 
     pub fn finish(&self) {
@@ -207,4 +363,82 @@ impl DrawContext {
     }
 }
 
+/// An offscreen framebuffer to draw into, for rendering without a window.
+pub struct OffscreenRenderTarget {
+    framebuffer: GLuint,
+    color_renderbuffer: GLuint,
+    depth_stencil_renderbuffer: GLuint,
+    pub size: Size2D<i32>,
+}
+
+impl OffscreenRenderTarget {
+    pub fn new(size: &Size2D<i32>) -> OffscreenRenderTarget {
+        let framebuffer = gl::gen_framebuffers(1)[0];
+        let renderbuffers = gl::gen_renderbuffers(2);
+        let (color_renderbuffer, depth_stencil_renderbuffer) = (renderbuffers[0], renderbuffers[1]);
+
+        gl::bind_renderbuffer(gl::RENDERBUFFER, color_renderbuffer);
+        gl::renderbuffer_storage(gl::RENDERBUFFER, gl::RGBA8, size.width, size.height);
+        gl::bind_renderbuffer(gl::RENDERBUFFER, depth_stencil_renderbuffer);
+        gl::renderbuffer_storage(gl::RENDERBUFFER, gl::DEPTH24_STENCIL8, size.width, size.height);
+
+        gl::bind_framebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl::framebuffer_renderbuffer(gl::FRAMEBUFFER,
+                                     gl::COLOR_ATTACHMENT0,
+                                     gl::RENDERBUFFER,
+                                     color_renderbuffer);
+        gl::framebuffer_renderbuffer(gl::FRAMEBUFFER,
+                                     gl::DEPTH_ATTACHMENT,
+                                     gl::RENDERBUFFER,
+                                     depth_stencil_renderbuffer);
+        gl::framebuffer_renderbuffer(gl::FRAMEBUFFER,
+                                     gl::STENCIL_ATTACHMENT,
+                                     gl::RENDERBUFFER,
+                                     depth_stencil_renderbuffer);
+        if gl::check_frame_buffer_status(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+            panic!("Offscreen framebuffer is incomplete!")
+        }
+
+        OffscreenRenderTarget {
+            framebuffer: framebuffer,
+            color_renderbuffer: color_renderbuffer,
+            depth_stencil_renderbuffer: depth_stencil_renderbuffer,
+            size: *size,
+        }
+    }
+
+    /// Directs subsequent drawing to this target.
+    pub fn bind(&self) {
+        gl::bind_framebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        gl::viewport(0, 0, self.size.width, self.size.height);
+    }
+
+    /// Returns the contents of the target as RGBA pixels, with the top row first.
+    pub fn read_pixels(&self) -> Vec<u8> {
+        gl::bind_framebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        gl::pixel_store_i(gl::PACK_ALIGNMENT, 1);
+        let pixels = gl::read_pixels(0,
+                                     0,
+                                     self.size.width,
+                                     self.size.height,
+                                     gl::RGBA,
+                                     gl::UNSIGNED_BYTE);
+
+        // OpenGL returns the bottom row first.
+        let row_length = (self.size.width * 4) as usize;
+        let mut flipped_pixels = Vec::with_capacity(pixels.len());
+        for row in pixels.chunks(row_length).rev() {
+            flipped_pixels.extend(row.iter())
+        }
+        flipped_pixels
+    }
+}
+
+impl Drop for OffscreenRenderTarget {
+    fn drop(&mut self) {
+        gl::delete_framebuffers(&[self.framebuffer]);
+        gl::delete_renderbuffers(&[self.color_renderbuffer, self.depth_stencil_renderbuffer]);
+    }
+}
+
 
@@ -0,0 +1,141 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Rendering display lists without a window, for tests and tools.
+//!
+//! The GL context comes from glutin's headless renderer, which uses a software implementation
+//! (OSMesa) where available, so this works on machines without a display or a GPU. Drawing goes
+//! to an offscreen framebuffer, which is read back as RGBA pixels.
+
+use assets::AssetManager;
+use atlas::Atlas;
+use batch::{self, ClipMode, RetainedBatches};
+use benchmark::{FrameTimings, Phase};
+use context::Context;
+use debug::DebugDump;
+use display_list::DisplayList;
+use draw::{DrawContext, OffscreenRenderTarget};
+use job_server::JobServer;
+use renderer::Renderer;
+
+use euclid::Size2D;
+use gleam::gl;
+use glutin::{Api, GlRequest, HeadlessContext, HeadlessRendererBuilder};
+use num_cpus;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub struct HeadlessRenderer {
+    /// The context that assets are created in and display lists are batched with.
+    pub context: Context,
+    draw_context: DrawContext,
+    render_target: OffscreenRenderTarget,
+    /// The batches of the display list last given to `render_update()`, if the render target
+    /// still shows it.
+    retained_batches: Option<RetainedBatches>,
+    /// Keeps the GL context alive for as long as the renderer.
+    gl_context: HeadlessContext,
+}
+
+impl HeadlessRenderer {
+    pub fn new(size: &Size2D<i32>) -> HeadlessRenderer {
+        let gl_context = HeadlessRendererBuilder::new(size.width as u32, size.height as u32)
+            .with_gl(GlRequest::Specific(Api::OpenGl, (2, 1)))
+            .build()
+            .unwrap();
+        unsafe {
+            gl_context.make_current().unwrap();
+        }
+        gl::load_with(|symbol| gl_context.get_proc_address(symbol));
+
+        let atlas = Rc::new(RefCell::new(Atlas::new()));
+        let job_server = Rc::new(RefCell::new(JobServer::new(num_cpus::get() as u32)));
+        let render_target = OffscreenRenderTarget::new(size);
+        HeadlessRenderer {
+            context: Context {
+                asset_manager: AssetManager::new(job_server, atlas.clone()),
+                render_target_size: *size,
+                device_pixel_ratio: 1.0,
+                clip_mode: ClipMode::Geometry,
+                debug_dump: DebugDump::disabled(),
+            },
+            draw_context: DrawContext::new(atlas),
+            render_target: render_target,
+            retained_batches: None,
+            gl_context: gl_context,
+        }
+    }
+
+    /// Draws the display list into the render target, timing each phase of rendering. Each phase
+    /// runs to completion before the next one starts, so that they can be timed separately.
+    pub fn draw_with_timings(&mut self, display_list: &mut DisplayList) -> FrameTimings {
+        let HeadlessRenderer {
+            ref mut context,
+            ref mut draw_context,
+            ref render_target,
+            ref mut retained_batches,
+            ..
+        } = *self;
+
+        let mut timings = FrameTimings::new();
+        timings.time(Phase::AssetRasterization, || {
+            context.asset_manager
+                   .start_rasterizing_assets_in_display_list_as_necessary(display_list);
+            context.asset_manager.finish_rasterizing_assets_in_display_list(display_list);
+        });
+        timings.time(Phase::AtlasUpload, || {
+            context.asset_manager.atlas.borrow_mut().advance_generation();
+            context.asset_manager.upload_assets_in_display_list(display_list, &context.debug_dump);
+        });
+        let batches = timings.time(Phase::BatchBuilding, || {
+            batch::build_batches(context, display_list)
+        });
+        timings.time(Phase::GlSubmission, || {
+            render_target.bind();
+            draw_context.init_gl_state();
+            draw_context.clear();
+            for batch in batches.iter() {
+                draw_context.draw_batch(batch)
+            }
+        });
+        timings.time(Phase::GlFinish, || draw_context.finish());
+        *retained_batches = None;
+        timings
+    }
+
+    /// Draws a new version of the display list last given to this method, repainting only the
+    /// parts of the render target that changed.
+    pub fn draw_update(&mut self, display_list: DisplayList) {
+        let damaged_rects = batch::update_retained_batches(&mut self.context,
+                                                           &mut self.retained_batches,
+                                                           display_list);
+        debug!("repainting damaged rects: {:?}", damaged_rects);
+        self.render_target.bind();
+        self.draw_context.init_gl_state();
+        self.draw_context.draw_damaged_rects(self.retained_batches.as_ref().unwrap().batches(),
+                                             &damaged_rects,
+                                             &self.render_target.size);
+        self.draw_context.finish()
+    }
+}
+
+impl Renderer for HeadlessRenderer {
+    fn context(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
+    fn render(&mut self, display_list: &mut DisplayList) -> Vec<u8> {
+        self.draw_with_timings(display_list);
+        self.render_target.read_pixels()
+    }
+
+    fn render_update(&mut self, display_list: DisplayList) -> Vec<u8> {
+        self.draw_update(display_list);
+        self.render_target.read_pixels()
+    }
+
+    fn forget_retained_display_list(&mut self) {
+        self.retained_batches = None
+    }
+}
@@ -0,0 +1,174 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Display-list-based invalidation.
+//!
+//! Successive display lists are compared item by item, matching items up by ID, to find out what
+//! changed between them and which part of the screen has to be redrawn as a result.
+
+use context::Context;
+use display_list::{Au, BaseDisplayItem, DisplayList, ItemId};
+
+use euclid::{Point2D, Rect, Size2D};
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+
+pub struct DisplayListDiff {
+    /// Items only in the new display list, in painting order.
+    pub added: Vec<ItemId>,
+    /// Items only in the old display list, in painting order.
+    pub removed: Vec<ItemId>,
+    /// Items in both display lists that draw differently or that moved in the painting order
+    /// relative to the others, in the new painting order.
+    pub changed: Vec<ItemId>,
+    /// The area that has to be redrawn, or `None` if nothing visible changed.
+    pub invalidated_region: Option<Rect<Au>>,
+}
+
+impl DisplayListDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Adds the visible part of the item to the invalidated region.
+    fn invalidate(&mut self, base: &BaseDisplayItem) {
+        let visible_rect = match intersect_rects(&base.bounds, &base.clip.main) {
+            None => return,
+            Some(visible_rect) => visible_rect,
+        };
+        self.invalidated_region = Some(match self.invalidated_region {
+            None => visible_rect,
+            Some(ref invalidated_region) => union_rects(invalidated_region, &visible_rect),
+        })
+    }
+}
+
+/// The error `diff()` returns when items of one display list share an ID, so that they can't be
+/// matched up with those of the other.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DuplicateItemId(pub ItemId);
+
+/// Finds the differences between two display lists.
+pub fn diff(old_display_list: &DisplayList, new_display_list: &DisplayList)
+            -> Result<DisplayListDiff, DuplicateItemId> {
+    let mut old_indices = HashMap::new();
+    for (index, item) in old_display_list.items.iter().enumerate() {
+        if old_indices.insert(item.id(), index).is_some() {
+            return Err(DuplicateItemId(item.id()))
+        }
+    }
+
+    let mut diff = DisplayListDiff {
+        added: vec![],
+        removed: vec![],
+        changed: vec![],
+        invalidated_region: None,
+    };
+
+    // Items that keep their relative order keep increasing old indices. When one is out of
+    // order, it's taken to have moved. This doesn't always find the fewest moves, but the moves it
+    // finds are enough to account for the new order.
+    let mut new_ids = HashSet::new();
+    let mut last_old_index = None;
+    for item in new_display_list.items.iter() {
+        if !new_ids.insert(item.id()) {
+            return Err(DuplicateItemId(item.id()))
+        }
+
+        let old_index = match old_indices.get(&item.id()) {
+            None => {
+                diff.added.push(item.id());
+                diff.invalidate(item.base());
+                continue
+            }
+            Some(&old_index) => old_index,
+        };
+
+        let old_item = &old_display_list.items[old_index];
+        let moved = match last_old_index {
+            Some(previous_old_index) if old_index < previous_old_index => true,
+            _ => {
+                last_old_index = Some(old_index);
+                false
+            }
+        };
+        if moved || !item.draws_same_as(old_item) {
+            diff.changed.push(item.id());
+            diff.invalidate(old_item.base());
+            diff.invalidate(item.base());
+        }
+    }
+
+    for item in old_display_list.items.iter() {
+        if !new_ids.contains(&item.id()) {
+            diff.removed.push(item.id());
+            diff.invalidate(item.base());
+        }
+    }
+
+    Ok(diff)
+}
+
+/// Returns the differences between two display lists taken as though no item of the new one were
+/// in the old one, for when their items can't be matched up.
+pub fn diff_replacing_everything(old_display_list: &DisplayList,
+                                 new_display_list: &DisplayList)
+                                 -> DisplayListDiff {
+    let mut diff = DisplayListDiff {
+        added: vec![],
+        removed: vec![],
+        changed: vec![],
+        invalidated_region: None,
+    };
+    for item in new_display_list.items.iter() {
+        diff.added.push(item.id());
+        diff.invalidate(item.base());
+    }
+    for item in old_display_list.items.iter() {
+        diff.removed.push(item.id());
+        diff.invalidate(item.base());
+    }
+    diff
+}
+
+/// Returns the rectangle of the render target, in device pixels from the top left, that an
+/// invalidated region touches, rounded out to whole pixels, or `None` if it's entirely off the
+/// render target.
+pub fn damaged_rect(context: &Context, invalidated_region: &Rect<Au>) -> Option<Rect<i32>> {
+    let to_device_px = |length: Au| length.to_f64_px() * (context.device_pixel_ratio as f64);
+    let size = &context.render_target_size;
+    let min_x = cmp::max(f64::floor(to_device_px(invalidated_region.origin.x)) as i32, 0);
+    let min_y = cmp::max(f64::floor(to_device_px(invalidated_region.origin.y)) as i32, 0);
+    let max_x = cmp::min(f64::ceil(to_device_px(max_x(invalidated_region))) as i32, size.width);
+    let max_y = cmp::min(f64::ceil(to_device_px(max_y(invalidated_region))) as i32, size.height);
+    if max_x <= min_x || max_y <= min_y {
+        return None
+    }
+    Some(Rect::new(Point2D::new(min_x, min_y), Size2D::new(max_x - min_x, max_y - min_y)))
+}
+
+/// Returns the intersection of two rectangles, or `None` if it's empty.
+pub fn intersect_rects(a: &Rect<Au>, b: &Rect<Au>) -> Option<Rect<Au>> {
+    let origin = Point2D::new(cmp::max(a.origin.x, b.origin.x), cmp::max(a.origin.y, b.origin.y));
+    let max = Point2D::new(cmp::min(max_x(a), max_x(b)), cmp::min(max_y(a), max_y(b)));
+    if max.x <= origin.x || max.y <= origin.y {
+        return None
+    }
+    Some(Rect::new(origin, Size2D::new(max.x - origin.x, max.y - origin.y)))
+}
+
+/// Returns the smallest rectangle that contains both rectangles.
+pub fn union_rects(a: &Rect<Au>, b: &Rect<Au>) -> Rect<Au> {
+    let origin = Point2D::new(cmp::min(a.origin.x, b.origin.x), cmp::min(a.origin.y, b.origin.y));
+    let max = Point2D::new(cmp::max(max_x(a), max_x(b)), cmp::max(max_y(a), max_y(b)));
+    Rect::new(origin, Size2D::new(max.x - origin.x, max.y - origin.y))
+}
+
+fn max_x(rect: &Rect<Au>) -> Au {
+    rect.origin.x + rect.size.width
+}
+
+fn max_y(rect: &Rect<Au>) -> Au {
+    rect.origin.y + rect.size.height
+}
@@ -2,25 +2,37 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-#![feature(main)]
-
+extern crate clock_ticks;
 extern crate euclid;
 extern crate freetype;
 extern crate gleam;
+extern crate glutin;
+extern crate image;
 extern crate num_cpus;
+extern crate rustc_serialize;
 
 #[macro_use]
 extern crate log;
 
+pub mod allocator;
 pub mod assets;
 pub mod atlas;
 pub mod batch;
+pub mod benchmark;
 pub mod context;
-pub mod demo;
+pub mod debug;
 pub mod distance_field;
 pub mod display_list;
 pub mod draw;
+pub mod headless;
+pub mod invalidation;
 pub mod job_server;
+pub mod outline;
+pub mod png;
+pub mod reftest;
+pub mod renderer;
+pub mod scene;
+pub mod software;
 
 mod blur;
 
@@ -0,0 +1,783 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Glyph outlines and the geometry needed to compute exact distances to them.
+
+use euclid::{Point2D, Rect, Size2D};
+use std::f32;
+use std::f64;
+
+/// The number of line segments used to approximate each curve when computing winding numbers.
+const QUADRATIC_FLATTENING_STEPS: u32 = 8;
+const CUBIC_FLATTENING_STEPS: u32 = 16;
+
+/// The number of starting points for Newton's method when finding the closest point on a cubic.
+const CUBIC_SEARCH_STARTS: u32 = 8;
+const CUBIC_NEWTON_ITERATIONS: u32 = 4;
+
+/// The sine of the smallest angle between two segments that is considered a corner for the
+/// purposes of edge coloring.
+const CORNER_CROSS_THRESHOLD: f32 = 0.05;
+
+/// Edge colors for multichannel distance fields. Each color is the set of channels of the field
+/// that the edge contributes to.
+pub type EdgeColor = u8;
+
+pub const RED: EdgeColor = 1;
+pub const GREEN: EdgeColor = 2;
+pub const BLUE: EdgeColor = 4;
+pub const YELLOW: EdgeColor = RED | GREEN;
+pub const MAGENTA: EdgeColor = RED | BLUE;
+pub const CYAN: EdgeColor = GREEN | BLUE;
+pub const WHITE: EdgeColor = RED | GREEN | BLUE;
+
+#[derive(Copy, Clone, Debug)]
+pub enum OutlineSegment {
+    Line(Point2D<f32>, Point2D<f32>),
+    Quadratic(Point2D<f32>, Point2D<f32>, Point2D<f32>),
+    Cubic(Point2D<f32>, Point2D<f32>, Point2D<f32>, Point2D<f32>),
+}
+
+impl OutlineSegment {
+    pub fn start(&self) -> Point2D<f32> {
+        match *self {
+            OutlineSegment::Line(p0, _) |
+            OutlineSegment::Quadratic(p0, _, _) |
+            OutlineSegment::Cubic(p0, _, _, _) => p0,
+        }
+    }
+
+    pub fn end(&self) -> Point2D<f32> {
+        match *self {
+            OutlineSegment::Line(_, p1) |
+            OutlineSegment::Quadratic(_, _, p1) |
+            OutlineSegment::Cubic(_, _, _, p1) => p1,
+        }
+    }
+
+    /// Returns the point at parameter `t` along the segment.
+    pub fn point(&self, t: f32) -> Point2D<f32> {
+        let s = 1.0 - t;
+        match *self {
+            OutlineSegment::Line(p0, p1) => lerp(&p0, &p1, t),
+            OutlineSegment::Quadratic(p0, p1, p2) => {
+                Point2D::new(s * s * p0.x + 2.0 * s * t * p1.x + t * t * p2.x,
+                             s * s * p0.y + 2.0 * s * t * p1.y + t * t * p2.y)
+            }
+            OutlineSegment::Cubic(p0, p1, p2, p3) => {
+                let (a, b, c, d) = (s * s * s, 3.0 * s * s * t, 3.0 * s * t * t, t * t * t);
+                Point2D::new(a * p0.x + b * p1.x + c * p2.x + d * p3.x,
+                             a * p0.y + b * p1.y + c * p2.y + d * p3.y)
+            }
+        }
+    }
+
+    /// Returns the (unnormalized) tangent at parameter `t` along the segment.
+    pub fn direction(&self, t: f32) -> Point2D<f32> {
+        let s = 1.0 - t;
+        let direction = match *self {
+            OutlineSegment::Line(p0, p1) => sub(&p1, &p0),
+            OutlineSegment::Quadratic(p0, p1, p2) => {
+                Point2D::new(2.0 * (s * (p1.x - p0.x) + t * (p2.x - p1.x)),
+                             2.0 * (s * (p1.y - p0.y) + t * (p2.y - p1.y)))
+            }
+            OutlineSegment::Cubic(p0, p1, p2, p3) => {
+                Point2D::new(3.0 * (s * s * (p1.x - p0.x) + 2.0 * s * t * (p2.x - p1.x) +
+                                    t * t * (p3.x - p2.x)),
+                             3.0 * (s * s * (p1.y - p0.y) + 2.0 * s * t * (p2.y - p1.y) +
+                                    t * t * (p3.y - p2.y)))
+            }
+        };
+
+        // Degenerate control points can make the tangent vanish at the ends; fall back to the
+        // chord in that case.
+        if dot(&direction, &direction) == 0.0 {
+            sub(&self.end(), &self.start())
+        } else {
+            direction
+        }
+    }
+
+    /// Returns the parameter of the point on this segment closest to `point`.
+    pub fn closest_parameter(&self, point: &Point2D<f32>) -> f32 {
+        match *self {
+            OutlineSegment::Line(p0, p1) => {
+                let direction = sub(&p1, &p0);
+                let length_squared = dot(&direction, &direction);
+                if length_squared == 0.0 {
+                    return 0.0
+                }
+                clamp_parameter(dot(&sub(point, &p0), &direction) / length_squared)
+            }
+            OutlineSegment::Quadratic(p0, p1, p2) => {
+                // Solve (B(t) - p) · B'(t) = 0, which is a cubic in t.
+                let a = sub(&p1, &p0);
+                let b = Point2D::new(p2.x - 2.0 * p1.x + p0.x, p2.y - 2.0 * p1.y + p0.y);
+                let m = sub(&p0, point);
+                let mut candidates = vec![0.0, 1.0];
+                solve_cubic(dot(&b, &b) as f64,
+                            3.0 * dot(&a, &b) as f64,
+                            (2.0 * dot(&a, &a) + dot(&m, &b)) as f64,
+                            dot(&m, &a) as f64,
+                            &mut candidates);
+                self.closest_of(point, &candidates)
+            }
+            OutlineSegment::Cubic(..) => {
+                let mut candidates = vec![0.0, 1.0];
+                for start in 0..(CUBIC_SEARCH_STARTS + 1) {
+                    let mut t = (start as f32) / (CUBIC_SEARCH_STARTS as f32);
+                    for _ in 0..CUBIC_NEWTON_ITERATIONS {
+                        let offset = sub(&self.point(t), point);
+                        let first_derivative = self.direction(t);
+                        let second_derivative = self.second_derivative(t);
+                        let numerator = dot(&offset, &first_derivative);
+                        let denominator = dot(&first_derivative, &first_derivative) +
+                            dot(&offset, &second_derivative);
+                        if denominator == 0.0 {
+                            break
+                        }
+                        t = clamp_parameter(t - numerator / denominator);
+                    }
+                    candidates.push(t)
+                }
+                self.closest_of(point, &candidates)
+            }
+        }
+    }
+
+    /// Returns the distance from `point` to the closest point on this segment.
+    pub fn distance(&self, point: &Point2D<f32>) -> f32 {
+        let t = self.closest_parameter(point);
+        length(&sub(&self.point(t), point))
+    }
+
+    /// Returns the distance from `point` to the closest point on this segment, along with how far
+    /// the direction to `point` is from perpendicular to the segment there (0 if perpendicular, 1
+    /// if parallel). The latter breaks ties between segments meeting at a corner.
+    pub fn distance_and_orthogonality(&self, point: &Point2D<f32>) -> (f32, f32) {
+        let t = self.closest_parameter(point);
+        let offset = sub(point, &self.point(t));
+        let orthogonality = dot(&normalize(&self.direction(t)), &normalize(&offset)).abs();
+        (length(&offset), orthogonality)
+    }
+
+    /// Returns the signed distance from `point` to the closest point on this segment, where the
+    /// sign indicates which side of the segment `point` lies on. If the closest point is one of the
+    /// endpoints, the distance to the segment's tangent line at that endpoint is returned instead
+    /// when `point` lies beyond it; this is the "pseudo-distance" of multichannel fields, which
+    /// keeps corners sharp.
+    pub fn signed_pseudo_distance(&self, point: &Point2D<f32>) -> f32 {
+        let t = self.closest_parameter(point);
+        let closest_point = self.point(t);
+        let direction = normalize(&self.direction(t));
+        let offset = sub(point, &closest_point);
+        let distance = length(&offset);
+        let signed_distance = if cross(&direction, &offset) < 0.0 {
+            -distance
+        } else {
+            distance
+        };
+
+        let beyond_endpoint = (t <= 0.0 && dot(&offset, &direction) < 0.0) ||
+            (t >= 1.0 && dot(&offset, &direction) > 0.0);
+        if !beyond_endpoint {
+            return signed_distance
+        }
+        let pseudo_distance = cross(&direction, &offset);
+        if pseudo_distance.abs() <= distance {
+            pseudo_distance
+        } else {
+            signed_distance
+        }
+    }
+
+    /// Splits the segment at parameter `t` into two segments of the same kind.
+    pub fn split(&self, t: f32) -> (OutlineSegment, OutlineSegment) {
+        match *self {
+            OutlineSegment::Line(p0, p1) => {
+                let p01 = lerp(&p0, &p1, t);
+                (OutlineSegment::Line(p0, p01), OutlineSegment::Line(p01, p1))
+            }
+            OutlineSegment::Quadratic(p0, p1, p2) => {
+                let (p01, p12) = (lerp(&p0, &p1, t), lerp(&p1, &p2, t));
+                let p012 = lerp(&p01, &p12, t);
+                (OutlineSegment::Quadratic(p0, p01, p012), OutlineSegment::Quadratic(p012, p12, p2))
+            }
+            OutlineSegment::Cubic(p0, p1, p2, p3) => {
+                let (p01, p12, p23) = (lerp(&p0, &p1, t), lerp(&p1, &p2, t), lerp(&p2, &p3, t));
+                let (p012, p123) = (lerp(&p01, &p12, t), lerp(&p12, &p23, t));
+                let p0123 = lerp(&p012, &p123, t);
+                (OutlineSegment::Cubic(p0, p01, p012, p0123),
+                 OutlineSegment::Cubic(p0123, p123, p23, p3))
+            }
+        }
+    }
+
+    fn second_derivative(&self, t: f32) -> Point2D<f32> {
+        match *self {
+            OutlineSegment::Line(..) => Point2D::new(0.0, 0.0),
+            OutlineSegment::Quadratic(p0, p1, p2) => {
+                Point2D::new(2.0 * (p2.x - 2.0 * p1.x + p0.x), 2.0 * (p2.y - 2.0 * p1.y + p0.y))
+            }
+            OutlineSegment::Cubic(p0, p1, p2, p3) => {
+                let s = 1.0 - t;
+                Point2D::new(6.0 * (s * (p2.x - 2.0 * p1.x + p0.x) +
+                                    t * (p3.x - 2.0 * p2.x + p1.x)),
+                             6.0 * (s * (p2.y - 2.0 * p1.y + p0.y) +
+                                    t * (p3.y - 2.0 * p2.y + p1.y)))
+            }
+        }
+    }
+
+    fn closest_of(&self, point: &Point2D<f32>, candidates: &[f32]) -> f32 {
+        let (mut best_t, mut best_distance_squared) = (0.0, f32::INFINITY);
+        for &t in candidates {
+            let t = clamp_parameter(t);
+            let offset = sub(&self.point(t), point);
+            let distance_squared = dot(&offset, &offset);
+            if distance_squared < best_distance_squared {
+                best_t = t;
+                best_distance_squared = distance_squared
+            }
+        }
+        best_t
+    }
+
+    fn flatten_into(&self, lines: &mut Vec<(Point2D<f32>, Point2D<f32>)>) {
+        let steps = match *self {
+            OutlineSegment::Line(p0, p1) => {
+                lines.push((p0, p1));
+                return
+            }
+            OutlineSegment::Quadratic(..) => QUADRATIC_FLATTENING_STEPS,
+            OutlineSegment::Cubic(..) => CUBIC_FLATTENING_STEPS,
+        };
+        let mut previous = self.start();
+        for step in 1..(steps + 1) {
+            let next = self.point((step as f32) / (steps as f32));
+            lines.push((previous, next));
+            previous = next
+        }
+    }
+}
+
+/// A glyph outline: a set of closed contours, filled with the nonzero winding rule.
+#[derive(Clone, Debug)]
+pub struct Outline {
+    pub contours: Vec<Vec<OutlineSegment>>,
+}
+
+impl Outline {
+    pub fn new() -> Outline {
+        Outline {
+            contours: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contours.iter().all(|contour| contour.is_empty())
+    }
+
+    /// Returns the bounding box of all the points of the outline, including control points. This
+    /// always encloses the outline itself.
+    pub fn control_box(&self) -> Rect<f32> {
+        let (mut min, mut max) = (Point2D::new(f32::INFINITY, f32::INFINITY),
+                                  Point2D::new(f32::NEG_INFINITY, f32::NEG_INFINITY));
+        for segment in self.contours.iter().flat_map(|contour| contour.iter()) {
+            let points = match *segment {
+                OutlineSegment::Line(p0, p1) => vec![p0, p1],
+                OutlineSegment::Quadratic(p0, p1, p2) => vec![p0, p1, p2],
+                OutlineSegment::Cubic(p0, p1, p2, p3) => vec![p0, p1, p2, p3],
+            };
+            for point in points.iter() {
+                min = Point2D::new(f32::min(min.x, point.x), f32::min(min.y, point.y));
+                max = Point2D::new(f32::max(max.x, point.x), f32::max(max.y, point.y));
+            }
+        }
+        Rect::new(min, Size2D::new(max.x - min.x, max.y - min.y))
+    }
+
+    /// Applies `transform` to every point of the outline.
+    pub fn transform<F>(&self, transform: F) -> Outline
+                        where F: Fn(&Point2D<f32>) -> Point2D<f32> {
+        Outline {
+            contours: self.contours.iter().map(|contour| {
+                contour.iter().map(|segment| {
+                    match *segment {
+                        OutlineSegment::Line(p0, p1) => {
+                            OutlineSegment::Line(transform(&p0), transform(&p1))
+                        }
+                        OutlineSegment::Quadratic(p0, p1, p2) => {
+                            OutlineSegment::Quadratic(transform(&p0),
+                                                      transform(&p1),
+                                                      transform(&p2))
+                        }
+                        OutlineSegment::Cubic(p0, p1, p2, p3) => {
+                            OutlineSegment::Cubic(transform(&p0),
+                                                  transform(&p1),
+                                                  transform(&p2),
+                                                  transform(&p3))
+                        }
+                    }
+                }).collect()
+            }).collect(),
+        }
+    }
+
+    /// Approximates the outline with line segments, for winding number computation.
+    pub fn flatten(&self) -> FlattenedOutline {
+        let mut lines = Vec::new();
+        for segment in self.contours.iter().flat_map(|contour| contour.iter()) {
+            segment.flatten_into(&mut lines)
+        }
+        FlattenedOutline {
+            lines: lines,
+        }
+    }
+
+    /// Returns twice the signed area enclosed by the outline, approximating curves with line
+    /// segments. With the Y axis pointing down, this is positive if the outer contours run
+    /// clockwise.
+    pub fn signed_area(&self) -> f32 {
+        let mut area = 0.0;
+        for &(p0, p1) in self.flatten().lines.iter() {
+            area += cross(&p0, &p1)
+        }
+        area
+    }
+
+    /// Assigns colors to the segments of each contour so that the two segments meeting at every
+    /// corner share exactly one channel, as described in:
+    ///
+    /// > Viktor Chlumský, "Shape Decomposition for Multi-channel Distance Fields", Master's
+    /// > thesis, Czech Technical University in Prague (2015).
+    ///
+    /// Returns the segments of each contour paired with their colors. Segments may be split to
+    /// make room for enough colors, so there can be more of them than in `contours`.
+    pub fn color_edges(&self) -> Vec<Vec<(OutlineSegment, EdgeColor)>> {
+        self.contours.iter().map(|contour| color_contour_edges(contour)).collect()
+    }
+
+    /// Returns the distance from `point` to the nearest point on the outline.
+    pub fn distance(&self, point: &Point2D<f32>) -> f32 {
+        let mut distance = f32::INFINITY;
+        for segment in self.contours.iter().flat_map(|contour| contour.iter()) {
+            distance = f32::min(distance, segment.distance(point))
+        }
+        distance
+    }
+}
+
+pub struct FlattenedOutline {
+    lines: Vec<(Point2D<f32>, Point2D<f32>)>,
+}
+
+impl FlattenedOutline {
+    pub fn winding_number(&self, point: &Point2D<f32>) -> i32 {
+        let mut winding_number = 0;
+        for &(p0, p1) in self.lines.iter() {
+            let side = (p1.x - p0.x) * (point.y - p0.y) - (point.x - p0.x) * (p1.y - p0.y);
+            if p0.y <= point.y {
+                if p1.y > point.y && side > 0.0 {
+                    winding_number += 1
+                }
+            } else if p1.y <= point.y && side < 0.0 {
+                winding_number -= 1
+            }
+        }
+        winding_number
+    }
+
+    pub fn contains(&self, point: &Point2D<f32>) -> bool {
+        self.winding_number(point) != 0
+    }
+}
+
+fn color_contour_edges(contour: &[OutlineSegment]) -> Vec<(OutlineSegment, EdgeColor)> {
+    let mut corners = Vec::new();
+    for (index, segment) in contour.iter().enumerate() {
+        let previous = &contour[(index + contour.len() - 1) % contour.len()];
+        let incoming = normalize(&previous.direction(1.0));
+        let outgoing = normalize(&segment.direction(0.0));
+        if dot(&incoming, &outgoing) <= 0.0 ||
+                cross(&incoming, &outgoing).abs() > CORNER_CROSS_THRESHOLD {
+            corners.push(index)
+        }
+    }
+
+    // Smooth contours need no channel separation at all.
+    if corners.is_empty() {
+        return contour.iter().map(|&segment| (segment, WHITE)).collect()
+    }
+
+    // A contour with a single corner ("teardrop") is split into three parts so that both sides of
+    // the corner still have differing colors.
+    if corners.len() == 1 {
+        // With fewer than three segments there aren't enough to go around, so split each of them
+        // into thirds first. The new joins are smooth, so the corner stays the only one.
+        if contour.len() < 3 {
+            let mut split_contour = Vec::with_capacity(contour.len() * 3);
+            for segment in contour.iter() {
+                let (first, rest) = segment.split(1.0 / 3.0);
+                let (second, third) = rest.split(0.5);
+                split_contour.extend([ first, second, third ].iter());
+            }
+            return color_contour_edges(&split_contour)
+        }
+
+        let start = corners[0];
+        let mut colors = vec![WHITE; contour.len()];
+        for offset in 0..contour.len() {
+            let part = 3 * offset / contour.len();
+            colors[(start + offset) % contour.len()] = [ MAGENTA, WHITE, YELLOW ][part];
+        }
+        return contour.iter().cloned().zip(colors.into_iter()).collect()
+    }
+
+    // Otherwise, switch colors at every corner, taking care that the last run of segments before
+    // wrapping around differs from the first.
+    let palette = [ CYAN, MAGENTA, YELLOW ];
+    let mut colors = vec![WHITE; contour.len()];
+    for (run, &start) in corners.iter().enumerate() {
+        let end = if run + 1 < corners.len() {
+            corners[run + 1]
+        } else {
+            corners[0] + contour.len()
+        };
+        let color = if run + 1 == corners.len() && run % 3 == 0 {
+            MAGENTA
+        } else {
+            palette[run % 3]
+        };
+        for index in start..end {
+            colors[index % contour.len()] = color
+        }
+    }
+    contour.iter().cloned().zip(colors.into_iter()).collect()
+}
+
+/// Pushes the real roots of `a t³ + b t² + c t + d = 0` onto `roots`.
+fn solve_cubic(a: f64, b: f64, c: f64, d: f64, roots: &mut Vec<f32>) {
+    const EPSILON: f64 = 1e-12;
+
+    if a.abs() < EPSILON {
+        // Quadratic (or lower).
+        if b.abs() < EPSILON {
+            if c.abs() >= EPSILON {
+                roots.push((-d / c) as f32)
+            }
+            return
+        }
+        let discriminant = c * c - 4.0 * b * d;
+        if discriminant >= 0.0 {
+            let root = f64::sqrt(discriminant);
+            roots.push(((-c + root) / (2.0 * b)) as f32);
+            roots.push(((-c - root) / (2.0 * b)) as f32);
+        }
+        return
+    }
+
+    // Reduce to the depressed cubic t³ + pt + q = 0 via t = x - b/3a.
+    let (b, c, d) = (b / a, c / a, d / a);
+    let offset = b / 3.0;
+    let p = c - b * b / 3.0;
+    let q = 2.0 * b * b * b / 27.0 - b * c / 3.0 + d;
+    let discriminant = q * q / 4.0 + p * p * p / 27.0;
+    if discriminant > 0.0 {
+        let root = f64::sqrt(discriminant);
+        let u = f64::cbrt(-q / 2.0 + root);
+        let v = f64::cbrt(-q / 2.0 - root);
+        roots.push((u + v - offset) as f32);
+    } else if p.abs() < EPSILON {
+        roots.push((-offset) as f32);
+    } else {
+        let m = 2.0 * f64::sqrt(-p / 3.0);
+        let cos_3_theta = f64::max(-1.0, f64::min(1.0, 3.0 * q / (p * m)));
+        let theta = f64::acos(cos_3_theta) / 3.0;
+        for k in 0..3 {
+            let angle = theta - 2.0 * f64::consts::PI * (k as f64) / 3.0;
+            roots.push((m * f64::cos(angle) - offset) as f32)
+        }
+    }
+}
+
+fn clamp_parameter(t: f32) -> f32 {
+    if t < 0.0 {
+        0.0
+    } else if t > 1.0 {
+        1.0
+    } else {
+        t
+    }
+}
+
+fn lerp(a: &Point2D<f32>, b: &Point2D<f32>, t: f32) -> Point2D<f32> {
+    Point2D::new(a.x + (b.x - a.x) * t, a.y + (b.y - a.y) * t)
+}
+
+pub fn sub(a: &Point2D<f32>, b: &Point2D<f32>) -> Point2D<f32> {
+    Point2D::new(a.x - b.x, a.y - b.y)
+}
+
+pub fn dot(a: &Point2D<f32>, b: &Point2D<f32>) -> f32 {
+    a.x * b.x + a.y * b.y
+}
+
+pub fn cross(a: &Point2D<f32>, b: &Point2D<f32>) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+pub fn length(a: &Point2D<f32>) -> f32 {
+    f32::sqrt(dot(a, a))
+}
+
+pub fn normalize(a: &Point2D<f32>) -> Point2D<f32> {
+    let length = length(a);
+    if length == 0.0 {
+        *a
+    } else {
+        Point2D::new(a.x / length, a.y / length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::Point2D;
+    use std::f32;
+
+    use super::{EdgeColor, Outline, OutlineSegment, WHITE, color_contour_edges, length};
+    use super::{solve_cubic, sub};
+
+    fn check_roots((a, b, c, d): (f64, f64, f64, f64), expected_roots: &[f32]) {
+        let mut roots = vec![];
+        solve_cubic(a, b, c, d, &mut roots);
+        for &root in roots.iter() {
+            let t = root as f64;
+            assert!((((a * t + b) * t + c) * t + d).abs() < 1e-4, "{} isn't a root", root)
+        }
+        for &expected_root in expected_roots.iter() {
+            assert!(roots.iter().any(|&root| (root - expected_root).abs() < 1e-3),
+                    "{} is missing from {:?}",
+                    expected_root,
+                    roots)
+        }
+    }
+
+    #[test]
+    fn cubic_roots() {
+        // (t - 1)(t - 2)(t - 3)
+        check_roots((1.0, -6.0, 11.0, -6.0), &[1.0, 2.0, 3.0]);
+        // (t - 0.5)³
+        check_roots((1.0, -1.5, 0.75, -0.125), &[0.5]);
+        // (t - 2)(t² + 1)
+        check_roots((1.0, -2.0, 1.0, -2.0), &[2.0]);
+        // 2(t + 0.5)(t - 0.25)², scaled so that the leading coefficient isn't one
+        check_roots((2.0, 0.0, -0.375, 0.0625), &[-0.5, 0.25]);
+        // (t - 1)(t + 3), with no cubic term
+        check_roots((0.0, 1.0, 2.0, -3.0), &[1.0, -3.0]);
+        // 2t - 1
+        check_roots((0.0, 0.0, 2.0, -1.0), &[0.5]);
+    }
+
+    /// Checks the closest points on the segment to a grid of points around it against the closest
+    /// of many points sampled along it.
+    fn check_against_sampling(segment: &OutlineSegment) {
+        const SAMPLES: u32 = 4096;
+        for y in -4..17 {
+            for x in -4..17 {
+                let point = Point2D::new(x as f32, y as f32);
+                let mut sampled_distance = f32::INFINITY;
+                for sample in 0..(SAMPLES + 1) {
+                    let t = (sample as f32) / (SAMPLES as f32);
+                    sampled_distance = f32::min(sampled_distance,
+                                                length(&sub(&segment.point(t), &point)))
+                }
+                let t = segment.closest_parameter(&point);
+                assert!(t >= 0.0 && t <= 1.0);
+                let distance = segment.distance(&point);
+                assert_eq!(distance, length(&sub(&segment.point(t), &point)));
+                assert!((distance - sampled_distance).abs() < 0.01,
+                        "{:?} is {} from {:?}, but sampling finds {}",
+                        point,
+                        distance,
+                        segment,
+                        sampled_distance)
+            }
+        }
+    }
+
+    #[test]
+    fn closest_points_on_lines() {
+        check_against_sampling(&OutlineSegment::Line(Point2D::new(1.0, 2.0),
+                                                      Point2D::new(11.0, 9.0)));
+        check_against_sampling(&OutlineSegment::Line(Point2D::new(6.0, 6.0),
+                                                      Point2D::new(6.0, 6.0)));
+    }
+
+    #[test]
+    fn closest_points_on_quadratics() {
+        check_against_sampling(&OutlineSegment::Quadratic(Point2D::new(0.0, 12.0),
+                                                           Point2D::new(6.0, -6.0),
+                                                           Point2D::new(12.0, 12.0)));
+        // Collinear control points, doubling back on itself.
+        check_against_sampling(&OutlineSegment::Quadratic(Point2D::new(2.0, 2.0),
+                                                           Point2D::new(12.0, 12.0),
+                                                           Point2D::new(4.0, 4.0)));
+    }
+
+    #[test]
+    fn closest_points_on_cubics() {
+        // An S curve.
+        check_against_sampling(&OutlineSegment::Cubic(Point2D::new(0.0, 0.0),
+                                                       Point2D::new(16.0, 0.0),
+                                                       Point2D::new(-4.0, 12.0),
+                                                       Point2D::new(12.0, 12.0)));
+        // A loop.
+        check_against_sampling(&OutlineSegment::Cubic(Point2D::new(0.0, 12.0),
+                                                       Point2D::new(16.0, -4.0),
+                                                       Point2D::new(-4.0, -4.0),
+                                                       Point2D::new(12.0, 12.0)));
+        // A cusp.
+        check_against_sampling(&OutlineSegment::Cubic(Point2D::new(0.0, 0.0),
+                                                       Point2D::new(12.0, 12.0),
+                                                       Point2D::new(0.0, 12.0),
+                                                       Point2D::new(12.0, 0.0)));
+    }
+
+    /// Returns the contour around the polygon with the given corners.
+    fn polygon(points: &[(f32, f32)]) -> Vec<OutlineSegment> {
+        (0..points.len()).map(|index| {
+            let (start, end) = (points[index], points[(index + 1) % points.len()]);
+            OutlineSegment::Line(Point2D::new(start.0, start.1), Point2D::new(end.0, end.1))
+        }).collect()
+    }
+
+    fn winding_numbers(contours: Vec<Vec<OutlineSegment>>, points: &[(f32, f32)]) -> Vec<i32> {
+        let mut outline = Outline::new();
+        outline.contours = contours;
+        let flattened_outline = outline.flatten();
+        points.iter().map(|&(x, y)| flattened_outline.winding_number(&Point2D::new(x, y))).collect()
+    }
+
+    #[test]
+    fn nested_contours() {
+        let outer = polygon(&[(0.0, 0.0), (12.0, 0.0), (12.0, 12.0), (0.0, 12.0)]);
+        let inner = polygon(&[(4.0, 4.0), (8.0, 4.0), (8.0, 8.0), (4.0, 8.0)]);
+        let reversed_inner = polygon(&[(4.0, 4.0), (4.0, 8.0), (8.0, 8.0), (8.0, 4.0)]);
+        let points = [(2.0, 6.0), (6.0, 6.0), (14.0, 6.0)];
+
+        // Contours winding the same way add up; opposite ones cut holes.
+        let same_way = winding_numbers(vec![outer.clone(), inner], &points);
+        assert_eq!(same_way[0].abs(), 1);
+        assert_eq!(same_way[1], same_way[0] * 2);
+        assert_eq!(same_way[2], 0);
+        assert_eq!(winding_numbers(vec![outer, reversed_inner], &points)[1], 0);
+    }
+
+    #[test]
+    fn self_touching_contours() {
+        // Two squares that meet at a corner, traced as one contour that passes through the corner
+        // twice.
+        let touching_at_a_point = polygon(&[
+            (0.0, 0.0), (6.0, 0.0), (6.0, 6.0), (12.0, 6.0), (12.0, 12.0), (6.0, 12.0), (6.0, 6.0),
+            (0.0, 6.0),
+        ]);
+        let numbers = winding_numbers(vec![touching_at_a_point],
+                                      &[(3.0, 3.0), (9.0, 9.0), (9.0, 3.0), (3.0, 9.0)]);
+        assert_eq!(numbers[0].abs(), 1);
+        assert_eq!(numbers[1], numbers[0]);
+        assert_eq!(&numbers[2..], &[0, 0]);
+
+        // A square with a slit cut into it, whose two sides run along each other.
+        let slit = polygon(&[
+            (0.0, 0.0), (6.0, 0.0), (6.0, 6.0), (6.0, 0.0), (12.0, 0.0), (12.0, 12.0), (0.0, 12.0),
+        ]);
+        let numbers = winding_numbers(vec![slit], &[(3.0, 3.0), (9.0, 3.0), (6.0, 9.0)]);
+        assert_eq!(numbers[0].abs(), 1);
+        assert_eq!(numbers[1], numbers[0]);
+        assert_eq!(numbers[2], numbers[0]);
+
+        // A figure eight, which crosses itself, winds opposite ways around its two loops.
+        let figure_eight = polygon(&[(0.0, 0.0), (12.0, 12.0), (12.0, 0.0), (0.0, 12.0)]);
+        let numbers = winding_numbers(vec![figure_eight], &[(3.0, 6.0), (9.0, 6.0), (6.0, 3.0)]);
+        assert_eq!(numbers[0].abs(), 1);
+        assert_eq!(numbers[1], -numbers[0]);
+        assert_eq!(numbers[2], 0);
+    }
+
+    /// Checks that the two edges at each corner of the colored contour, given by the indices of
+    /// the edges that start at them, have different colors that still have a channel apiece to
+    /// themselves, so that the median of the channels keeps the corner sharp.
+    fn check_corner_colors(colored_contour: &[(OutlineSegment, EdgeColor)], corners: &[usize]) {
+        for &(_, color) in colored_contour.iter() {
+            assert!(color.count_ones() >= 2)
+        }
+        for &corner in corners.iter() {
+            let previous = (corner + colored_contour.len() - 1) % colored_contour.len();
+            let (incoming_color, outgoing_color) =
+                (colored_contour[previous].1, colored_contour[corner].1);
+            assert!(incoming_color != outgoing_color, "edges at corner {} share a color", corner);
+            assert!((incoming_color & outgoing_color).count_ones() <= 1,
+                    "edges at corner {} share more than one channel",
+                    corner)
+        }
+    }
+
+    #[test]
+    fn polygons_switch_colors_at_every_corner() {
+        // Polygons with a number of corners that's a multiple of three, one more than that, and
+        // one less, to exercise the last run of edges before the colors wrap around.
+        for &corner_count in &[3, 4, 5, 6, 7, 8, 9] {
+            let points: Vec<(f32, f32)> = (0..corner_count).map(|index| {
+                let angle = (index as f32) * 2.0 * f32::consts::PI / (corner_count as f32);
+                (f32::cos(angle) * 8.0, f32::sin(angle) * 8.0)
+            }).collect();
+            let colored_contour = color_contour_edges(&polygon(&points));
+            check_corner_colors(&colored_contour, &(0..corner_count).collect::<Vec<_>>())
+        }
+    }
+
+    #[test]
+    fn smooth_contours_are_white() {
+        let circle: Vec<OutlineSegment> = (0..4).map(|quadrant| {
+            let point = |angle: f32, radius: f32| {
+                let angle = angle * f32::consts::PI / 2.0;
+                Point2D::new(f32::cos(angle) * radius, f32::sin(angle) * radius)
+            };
+            let quadrant = quadrant as f32;
+            OutlineSegment::Quadratic(point(quadrant, 8.0),
+                                      point(quadrant + 0.5, 8.0 * f32::consts::SQRT_2),
+                                      point(quadrant + 1.0, 8.0))
+        }).collect();
+        for &(_, color) in color_contour_edges(&circle).iter() {
+            assert_eq!(color, WHITE)
+        }
+    }
+
+    #[test]
+    fn teardrops_are_split_into_three_colors() {
+        // A teardrop drawn as a single curve, with its one corner where the curve meets itself.
+        let teardrop = OutlineSegment::Cubic(Point2D::new(0.0, 0.0),
+                                             Point2D::new(12.0, -12.0),
+                                             Point2D::new(12.0, 12.0),
+                                             Point2D::new(0.0, 0.0));
+        let colored_contour = color_contour_edges(&[teardrop]);
+        assert_eq!(colored_contour.len(), 3);
+        check_corner_colors(&colored_contour, &[0]);
+
+        // The same teardrop in smoothly joined pieces.
+        for &piece_count in &[2, 3, 4, 5] {
+            let mut pieces = vec![];
+            let mut rest = teardrop;
+            for piece in 0..(piece_count - 1) {
+                let (first, second) = rest.split(1.0 / ((piece_count - piece) as f32));
+                pieces.push(first);
+                rest = second
+            }
+            pieces.push(rest);
+            let colored_contour = color_contour_edges(&pieces);
+            check_corner_colors(&colored_contour, &[0]);
+        }
+    }
+}
@@ -0,0 +1,21 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Reading and writing rendered images as PNG files.
+
+use euclid::Size2D;
+use image::{self, ColorType};
+use std::path::Path;
+
+/// Writes RGBA pixels, top row first, to a PNG file.
+pub fn write_png(path: &Path, pixels: &[u8], size: &Size2D<u32>) {
+    image::save_buffer(path, pixels, size.width, size.height, ColorType::RGBA(8)).unwrap()
+}
+
+/// Reads a PNG file, returning its pixels as RGBA, top row first, along with its size.
+pub fn read_png(path: &Path) -> (Vec<u8>, Size2D<u32>) {
+    let image = image::open(path).unwrap().to_rgba();
+    let (width, height) = image.dimensions();
+    (image.into_raw(), Size2D::new(width, height))
+}
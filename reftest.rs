@@ -0,0 +1,410 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Reference tests.
+//!
+//! A reftest renders a test scene and compares the result against a reference: either a second
+//! scene, built differently, that should look the same (or, for `!=` tests, different), or a
+//! stored image. Distance field rendering isn't bit-exact across GL implementations, so tests can
+//! allow a fuzz tolerance of a maximum per-channel difference over a maximum number of pixels.
+//!
+//! Reftests are listed in manifest files, one per line, in the form used by Gecko:
+//!
+//! ```text
+//! [CONDITION...] (==|!=) TEST.json REFERENCE.(json|png)
+//! ```
+//!
+//! where the optional conditions are:
+//!
+//! * `fuzzy(MAX_DIFFERENCE,MAX_DIFFERING_PIXELS)`, to allow differences within a tolerance.
+//!
+//! * `clip-mode(stencil)` or `clip-mode(geometry)`, to render the test scene with the given
+//!   `ClipMode`. The reference is rendered in the renderer's own mode.
+//!
+//! * `update(OLD.json)`, to draw the old scene first and then repaint only the parts that changed
+//!   in the test scene. This checks that partial repaints leave the same result as drawing from
+//!   scratch.
+//!
+//! Paths are relative to the manifest, and `#` starts a comment.
+
+use batch::ClipMode;
+use png;
+use renderer::Renderer;
+use scene::{self, SceneError};
+
+use euclid::Size2D;
+use std::cmp;
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Relation {
+    /// The test passes if the test and reference images match within the fuzz tolerance.
+    Equal,
+    /// The test passes if the test and reference images differ by more than the fuzz tolerance.
+    NotEqual,
+}
+
+pub enum Reference {
+    /// A scene file.
+    Scene(PathBuf),
+    /// A PNG file of the same size as the render target.
+    Image(PathBuf),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Fuzz {
+    /// The largest difference in any channel of any pixel that still counts as a match.
+    pub max_difference: u8,
+    /// The largest number of pixels that may differ at all.
+    pub max_differing_pixels: usize,
+}
+
+impl Fuzz {
+    pub fn exact() -> Fuzz {
+        Fuzz::new(0, 0)
+    }
+
+    pub fn new(max_difference: u8, max_differing_pixels: usize) -> Fuzz {
+        Fuzz {
+            max_difference: max_difference,
+            max_differing_pixels: max_differing_pixels,
+        }
+    }
+
+    pub fn allows(&self, comparison: &ImageComparison) -> bool {
+        comparison.max_difference <= self.max_difference &&
+            comparison.differing_pixel_count <= self.max_differing_pixels
+    }
+}
+
+pub struct Reftest {
+    /// The stem of the test scene's file name, followed by the conditions that change how it's
+    /// rendered and, for `!=` tests, the reference, so that the same scene can be listed more than
+    /// once under distinct names.
+    pub name: String,
+    pub relation: Relation,
+    /// The scene file to test.
+    pub test: PathBuf,
+    /// The scene to draw before the test scene, if the test scene is drawn as an update to it.
+    pub update_from: Option<PathBuf>,
+    /// The clip mode to render the test scene in, if not the renderer's own.
+    pub clip_mode: Option<ClipMode>,
+    pub reference: Reference,
+    pub fuzz: Fuzz,
+}
+
+impl Reftest {
+    /// Renders the test and its reference and compares them. Fonts are looked up in
+    /// `font_directory`, as in `scene::load_scene()`. If the test fails and an output directory is
+    /// given, the test image, the reference image, and an image of the differences are written
+    /// there for inspection.
+    pub fn run(&self,
+               renderer: &mut Renderer,
+               font_directory: Option<&Path>,
+               output_directory: Option<&Path>)
+               -> ReftestResult {
+        let size = renderer.context().render_target_size;
+        let size = Size2D::new(size.width as u32, size.height as u32);
+        let renderer_clip_mode = renderer.context().clip_mode;
+        if let Some(clip_mode) = self.clip_mode {
+            renderer.context().clip_mode = clip_mode
+        }
+        let test_pixels = match self.update_from {
+            None => render_scene(renderer, &self.test, font_directory),
+            Some(ref update_from) => {
+                renderer.forget_retained_display_list();
+                update_scene(renderer, update_from, font_directory)
+                    .and_then(|_| update_scene(renderer, &self.test, font_directory))
+            }
+        };
+        renderer.context().clip_mode = renderer_clip_mode;
+
+        // Scenes that can't be loaded and references of the wrong size are broken tests, whatever
+        // the relation.
+        let test_pixels = match test_pixels {
+            Ok(test_pixels) => test_pixels,
+            Err(error) => {
+                warn!("couldn't load the scenes of {}: {}", self.name, error);
+                return ReftestResult::broken(&size)
+            }
+        };
+        let reference_pixels = match self.reference {
+            Reference::Scene(ref reference) => {
+                match render_scene(renderer, reference, font_directory) {
+                    Ok(reference_pixels) => reference_pixels,
+                    Err(error) => {
+                        warn!("couldn't load {}: {}", reference.display(), error);
+                        return ReftestResult::broken(&size)
+                    }
+                }
+            }
+            Reference::Image(ref path) => {
+                let (pixels, reference_size) = png::read_png(path);
+                if reference_size != size {
+                    warn!("reference image {} is {}x{}, but the render target is {}x{}",
+                          path.display(),
+                          reference_size.width,
+                          reference_size.height,
+                          size.width,
+                          size.height);
+                    return ReftestResult::broken(&size)
+                }
+                pixels
+            }
+        };
+
+        let comparison = compare_images(&test_pixels, &reference_pixels);
+        let passed = match self.relation {
+            Relation::Equal => self.fuzz.allows(&comparison),
+            Relation::NotEqual => !self.fuzz.allows(&comparison),
+        };
+
+        if let (false, Some(output_directory)) = (passed, output_directory) {
+            let write = |suffix: &str, pixels: &[u8]| {
+                let mut path = output_directory.to_path_buf();
+                path.push(&format!("{}-{}.png", self.name, suffix));
+                png::write_png(&path, pixels, &size)
+            };
+            write("test", &test_pixels);
+            write("reference", &reference_pixels);
+            write("difference", &difference_image(&test_pixels, &reference_pixels));
+        }
+
+        ReftestResult {
+            comparison: comparison,
+            passed: passed,
+        }
+    }
+}
+
+pub struct ReftestResult {
+    pub comparison: ImageComparison,
+    pub passed: bool,
+}
+
+impl ReftestResult {
+    /// Returns the result of a test that couldn't be run properly, on a render target of the
+    /// given size: a failure with every pixel different.
+    fn broken(size: &Size2D<u32>) -> ReftestResult {
+        ReftestResult {
+            comparison: ImageComparison {
+                max_difference: 255,
+                differing_pixel_count: (size.width * size.height) as usize,
+            },
+            passed: false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ImageComparison {
+    /// The largest difference in any channel of any pixel.
+    pub max_difference: u8,
+    /// The number of pixels that differ in any channel.
+    pub differing_pixel_count: usize,
+}
+
+impl Display for ImageComparison {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter,
+               "max difference {}, {} differing pixels",
+               self.max_difference,
+               self.differing_pixel_count)
+    }
+}
+
+/// Compares two RGBA images of the same size.
+pub fn compare_images(a: &[u8], b: &[u8]) -> ImageComparison {
+    assert!(a.len() == b.len());
+    let mut comparison = ImageComparison {
+        max_difference: 0,
+        differing_pixel_count: 0,
+    };
+    for (a, b) in a.chunks(4).zip(b.chunks(4)) {
+        let difference = pixel_difference(a, b);
+        comparison.max_difference = cmp::max(comparison.max_difference, difference);
+        if difference > 0 {
+            comparison.differing_pixel_count += 1
+        }
+    }
+    comparison
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Io(io::Error),
+    /// A line that isn't a valid reftest, with its line number, counting from one.
+    Invalid(usize, String),
+}
+
+impl Display for ManifestError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            ManifestError::Io(ref error) => write!(formatter, "{}", error),
+            ManifestError::Invalid(line_number, ref message) => {
+                write!(formatter, "line {}: {}", line_number, message)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for ManifestError {
+    fn from(error: io::Error) -> ManifestError {
+        ManifestError::Io(error)
+    }
+}
+
+/// Reads the reftests listed in the manifest at `path`. Every file a reftest refers to must
+/// exist.
+pub fn read_manifest(path: &Path) -> Result<Vec<Reftest>, ManifestError> {
+    let directory = path.parent().unwrap_or(Path::new("."));
+    let mut reftests = vec![];
+    for (line_index, line) in BufReader::new(try!(File::open(path))).lines().enumerate() {
+        let line = try!(line);
+        match read_manifest_line(&line, directory) {
+            Ok(Some(reftest)) => reftests.push(reftest),
+            Ok(None) => {}
+            Err(message) => return Err(ManifestError::Invalid(line_index + 1, message)),
+        }
+    }
+    Ok(reftests)
+}
+
+/// Reads one line of a manifest, returning `None` if it lists no reftest.
+fn read_manifest_line(line: &str, directory: &Path) -> Result<Option<Reftest>, String> {
+    let line = match line.find('#') {
+        Some(comment_start) => &line[0..comment_start],
+        None => line,
+    };
+    let mut tokens: Vec<&str> = line.split_whitespace().collect();
+    if tokens.is_empty() {
+        return Ok(None)
+    }
+
+    let (mut fuzz, mut update_from, mut clip_mode) = (Fuzz::exact(), None, None);
+    let mut name_suffix = String::new();
+    while !tokens.is_empty() && tokens[0].ends_with(")") {
+        let token = tokens.remove(0);
+        if token.starts_with("fuzzy(") {
+            fuzz = match parse_fuzz(token) {
+                Some(fuzz) => fuzz,
+                None => return Err(format!("malformed fuzz {}", token)),
+            }
+        } else if token == "clip-mode(geometry)" {
+            clip_mode = Some(ClipMode::Geometry);
+            name_suffix.push_str("-clip-mode-geometry")
+        } else if token == "clip-mode(stencil)" {
+            clip_mode = Some(ClipMode::Stencil);
+            name_suffix.push_str("-clip-mode-stencil")
+        } else if token.starts_with("update(") {
+            let old = directory.join(&token["update(".len()..(token.len() - 1)]);
+            try!(check_exists(&old));
+            if let Some(stem) = old.file_stem() {
+                name_suffix.push_str(&format!("-update-{}", stem.to_string_lossy()))
+            }
+            update_from = Some(old)
+        } else {
+            return Err(format!("unknown condition {}", token))
+        }
+    }
+    if tokens.len() != 3 {
+        return Err("expected a relation, a test, and a reference".to_string())
+    }
+    let relation = match tokens[0] {
+        "==" => Relation::Equal,
+        "!=" => Relation::NotEqual,
+        relation => return Err(format!("unknown relation {}", relation)),
+    };
+    let test = directory.join(tokens[1]);
+    try!(check_exists(&test));
+    let reference = directory.join(tokens[2]);
+    try!(check_exists(&reference));
+    let mut name = match test.file_stem() {
+        Some(stem) => format!("{}{}", stem.to_string_lossy(), name_suffix),
+        None => return Err(format!("{} isn't a file name", tokens[1])),
+    };
+    if let (Relation::NotEqual, Some(stem)) = (relation, reference.file_stem()) {
+        name.push_str(&format!("-not-{}", stem.to_string_lossy()))
+    }
+    let reference = match reference.extension().and_then(|extension| extension.to_str()) {
+        Some("png") => Reference::Image(reference),
+        _ => Reference::Scene(reference),
+    };
+    Ok(Some(Reftest {
+        name: name,
+        relation: relation,
+        test: test,
+        update_from: update_from,
+        clip_mode: clip_mode,
+        reference: reference,
+        fuzz: fuzz,
+    }))
+}
+
+fn check_exists(path: &Path) -> Result<(), String> {
+    if path.is_file() {
+        Ok(())
+    } else {
+        Err(format!("{} doesn't exist", path.display()))
+    }
+}
+
+/// Parses `fuzzy(MAX_DIFFERENCE,MAX_DIFFERING_PIXELS)`.
+fn parse_fuzz(token: &str) -> Option<Fuzz> {
+    if !token.starts_with("fuzzy(") || !token.ends_with(")") {
+        return None
+    }
+    let arguments: Vec<&str> = token["fuzzy(".len()..(token.len() - 1)].split(',').collect();
+    if arguments.len() != 2 {
+        return None
+    }
+    match (arguments[0].parse(), arguments[1].parse()) {
+        (Ok(max_difference), Ok(max_differing_pixels)) => {
+            Some(Fuzz::new(max_difference, max_differing_pixels))
+        }
+        _ => None,
+    }
+}
+
+fn render_scene(renderer: &mut Renderer, path: &Path, font_directory: Option<&Path>)
+                -> Result<Vec<u8>, SceneError> {
+    let mut display_list = try!(scene::load_scene(path,
+                                                  &renderer.context().asset_manager,
+                                                  font_directory));
+    Ok(renderer.render(&mut display_list))
+}
+
+fn update_scene(renderer: &mut Renderer, path: &Path, font_directory: Option<&Path>)
+                -> Result<Vec<u8>, SceneError> {
+    let display_list = try!(scene::load_scene(path,
+                                              &renderer.context().asset_manager,
+                                              font_directory));
+    Ok(renderer.render_update(display_list))
+}
+
+/// Returns the largest difference between any channel of two RGBA pixels.
+fn pixel_difference(a: &[u8], b: &[u8]) -> u8 {
+    a.iter().zip(b.iter()).fold(0, |difference, (&a, &b)| {
+        cmp::max(difference, cmp::max(a, b) - cmp::min(a, b))
+    })
+}
+
+/// Returns an opaque image that is black where the images match and red where they don't, scaled
+/// so that even the smallest differences are visible.
+fn difference_image(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(a.len());
+    for (a, b) in a.chunks(4).zip(b.chunks(4)) {
+        let difference = pixel_difference(a, b);
+        let red = if difference == 0 {
+            0
+        } else {
+            cmp::min(cmp::max(difference as u32 * 4, 64), 255) as u8
+        };
+        pixels.extend([ red, 0, 0, 255 ].iter().cloned())
+    }
+    pixels
+}
@@ -0,0 +1,26 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The interface shared by the renderers that draw display lists into memory.
+
+use context::Context;
+use display_list::DisplayList;
+
+pub trait Renderer {
+    /// The context that assets are created in and display lists are batched with.
+    fn context(&mut self) -> &mut Context;
+
+    /// Renders the display list and returns the result as RGBA pixels, top row first.
+    fn render(&mut self, display_list: &mut DisplayList) -> Vec<u8>;
+
+    /// Renders a new version of the display list last given to `render_update()`, repainting
+    /// only the parts of the image that changed, and returns the whole image as RGBA pixels, top
+    /// row first. Everything is repainted if there is no such display list, or if `render()` has
+    /// been called since.
+    fn render_update(&mut self, display_list: DisplayList) -> Vec<u8>;
+
+    /// Forgets the display list last given to `render_update()`, so that the next call to it
+    /// repaints everything.
+    fn forget_retained_display_list(&mut self);
+}
@@ -0,0 +1,510 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Reading and writing display lists as JSON scene files.
+//!
+//! A scene is an object with an `items` array, drawn in order. Every item has a `type`, `bounds`
+//! as `[x, y, width, height]` in pixels, an optional `clip` rectangle in the same form (by
+//! default nothing is clipped out), and, except for images and gradients, a `color` as
+//! `[r, g, b, a]`. Items may have a numeric `id`, which identifies the item across versions of the
+//! scene; by default it is the item's index. IDs, default ones included, must be unique within
+//! the scene. Items may also be clipped to rounded rectangles, listed in `complex-clips` as objects
+//! with a `rect` and the `radii` of the top left, top right, bottom right, and bottom left corners
+//! in pixels. The other fields depend on the type:
+//!
+//! * `"solid-color"` items have no others.
+//!
+//! * `"text"` items have a `font` path, relative to the font directory, a one-character
+//!   `character` string, and optionally `"multichannel": true` to use a multichannel distance
+//!   field and a `blur` sigma in pixels to draw the glyph blurred.
+//!
+//! * `"border"` items have a `width` and a corner `radius` in pixels.
+//!
+//! * `"image"` items have an `image` path, relative to the scene, to a PNG, JPEG, or GIF file,
+//!   and a `source` rectangle in image pixels, the part of the image stretched over the item's
+//!   bounds.
+//!
+//! * `"linear-gradient"` items have an `angle` in degrees clockwise from upward, as in CSS, and
+//!   `stops`, listed in order as objects with an `offset` from 0 to 1 along the gradient line and
+//!   a `color`.
+//!
+//! * `"radial-gradient"` items have a `center` as `[x, y]`, relative to the origin of the item's
+//!   bounds, the `radii` of the ellipse that is the gradient's ending shape as `[x, y]`, `stops`
+//!   as for linear gradients, and optionally `"extend": "repeat"` to repeat the stops beyond the
+//!   last instead of extending its color.
+
+use assets::{ArcAsset, ArcMode, Asset, AssetDescription, AssetManager, BlurredGlyph};
+use assets::{DistanceFieldMode, Glyph, ImageAsset};
+use display_list::{Au, BaseDisplayItem, BorderDisplayItem, BorderRadii, ClippingRegion, Color};
+use display_list::{ComplexClippingRegion, DisplayItem, DisplayList, ExtendMode, GradientStop};
+use display_list::{ImageDisplayItem, ImageKey, ItemId, LinearGradientDisplayItem};
+use display_list::{RadialGradientDisplayItem, SolidColorDisplayItem, TextDisplayItem};
+
+use euclid::{Point2D, Rect, Size2D};
+use rustc_serialize::json::{self, Json};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Why a scene couldn't be loaded.
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Json(json::ParserError),
+    /// The scene is valid JSON, but not a valid scene.
+    Invalid(String),
+}
+
+impl Display for SceneError {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            SceneError::Io(ref error) => write!(formatter, "{}", error),
+            SceneError::Json(ref error) => write!(formatter, "{}", error),
+            SceneError::Invalid(ref message) => write!(formatter, "{}", message),
+        }
+    }
+}
+
+impl From<io::Error> for SceneError {
+    fn from(error: io::Error) -> SceneError {
+        SceneError::Io(error)
+    }
+}
+
+impl From<json::ParserError> for SceneError {
+    fn from(error: json::ParserError) -> SceneError {
+        SceneError::Json(error)
+    }
+}
+
+/// Loads the scene file at `path`. Font paths are resolved relative to `font_directory`, or to
+/// the directory containing the scene if there is none. Image paths are always resolved relative
+/// to the directory containing the scene.
+pub fn load_scene(path: &Path, asset_manager: &AssetManager, font_directory: Option<&Path>)
+                  -> Result<DisplayList, SceneError> {
+    let mut file = try!(File::open(path));
+    let scene_directory = path.parent().unwrap_or(Path::new("."));
+    let font_directory = match font_directory {
+        Some(font_directory) => font_directory,
+        None => scene_directory,
+    };
+    read_scene(&mut file, asset_manager, font_directory, scene_directory)
+}
+
+pub fn read_scene(reader: &mut Read,
+                  asset_manager: &AssetManager,
+                  font_directory: &Path,
+                  image_directory: &Path)
+                  -> Result<DisplayList, SceneError> {
+    let scene = try!(Json::from_reader(reader));
+    let items = match scene.find("items").and_then(|items| items.as_array()) {
+        Some(items) => items,
+        None => return invalid("The scene has no \"items\" array!".to_string()),
+    };
+
+    let mut display_list = DisplayList {
+        items: vec![],
+    };
+    let mut ids = HashSet::new();
+    for (index, item) in items.iter().enumerate() {
+        let item = try!(read_item(item, index, asset_manager, font_directory, image_directory));
+        if !ids.insert(item.id()) {
+            return invalid(format!("Item {} has the ID {}, which an earlier item already has!",
+                                   index,
+                                   item.id().0))
+        }
+        display_list.items.push(item)
+    }
+    Ok(display_list)
+}
+
+pub fn save_scene(path: &Path, display_list: &DisplayList) {
+    let mut file = File::create(path).unwrap();
+    write_scene(&mut file, display_list)
+}
+
+/// Writes out a display list in the form `read_scene()` reads. Font and image paths are written as
+/// the scene they were read from gave them.
+pub fn write_scene(writer: &mut Write, display_list: &DisplayList) {
+    let items = display_list.items.iter().map(write_item).collect();
+    let mut scene = BTreeMap::new();
+    scene.insert("items".to_string(), Json::Array(items));
+    writeln!(writer, "{}", json::as_pretty_json(&Json::Object(scene))).unwrap();
+}
+
+fn read_item(item: &Json,
+             index: usize,
+             asset_manager: &AssetManager,
+             font_directory: &Path,
+             image_directory: &Path)
+             -> Result<DisplayItem, SceneError> {
+    let id = match item.find("id") {
+        None => index as u64,
+        Some(id) => {
+            match id.as_u64() {
+                Some(id) => id,
+                None => return invalid(format!("Expected an ID, found {}!", id)),
+            }
+        }
+    };
+    let base = BaseDisplayItem {
+        id: ItemId(id),
+        bounds: try!(read_rect(try!(field(item, "bounds")))),
+        clip: ClippingRegion {
+            main: match item.find("clip") {
+                None => ClippingRegion::max().main,
+                Some(clip) => try!(read_rect(clip)),
+            },
+            complex: match item.find("complex-clips") {
+                None => vec![],
+                Some(complex_clips) => try!(read_complex_clips(complex_clips)),
+            },
+        },
+    };
+    let color = || field(item, "color").and_then(read_color);
+
+    let display_item = match try!(read_string(try!(field(item, "type")))) {
+        "solid-color" => {
+            DisplayItem::SolidColor(Box::new(SolidColorDisplayItem {
+                base: base,
+                color: try!(color()),
+            }))
+        }
+        "text" => {
+            let font_name = try!(read_string(try!(field(item, "font"))));
+            let font_path = font_directory.join(font_name).to_string_lossy().into_owned();
+            let mut characters = try!(read_string(try!(field(item, "character")))).chars();
+            let character = match (characters.next(), characters.next()) {
+                (Some(character), None) => character,
+                _ => {
+                    return invalid("A text item's \"character\" must be a single character!"
+                                       .to_string())
+                }
+            };
+            let multichannel = match item.find("multichannel") {
+                None => false,
+                Some(multichannel) => {
+                    match multichannel.as_boolean() {
+                        Some(multichannel) => multichannel,
+                        None => return invalid("\"multichannel\" must be a boolean!".to_string()),
+                    }
+                }
+            };
+            let mut glyph = if multichannel {
+                Glyph::new_multichannel(font_path, character)
+            } else {
+                Glyph::new(font_path, character)
+            };
+            glyph.font_name = font_name.to_string();
+
+            let glyph_asset = asset_manager.create_asset(AssetDescription::Glyph(glyph), None);
+            let blurred_glyph_asset = match item.find("blur") {
+                None => None,
+                Some(sigma) => {
+                    let blurred_glyph = BlurredGlyph::new(try!(read_number(sigma)) as f32);
+                    Some(asset_manager.create_asset(AssetDescription::BlurredGlyph(blurred_glyph),
+                                                    Some(glyph_asset.clone())))
+                }
+            };
+            DisplayItem::Text(Box::new(TextDisplayItem {
+                base: base,
+                color: try!(color()),
+                glyph_asset: glyph_asset,
+                blurred_glyph_asset: blurred_glyph_asset,
+            }))
+        }
+        "border" => {
+            DisplayItem::Border(Box::new(BorderDisplayItem {
+                base: base,
+                width: Au::from_f64_px(try!(read_number(try!(field(item, "width"))))),
+                color: try!(color()),
+                radius: Au::from_f64_px(try!(read_number(try!(field(item, "radius"))))),
+                arc_asset: asset_manager.create_asset(AssetDescription::Arc(ArcAsset {
+                    mode: ArcMode::FilledArc,
+                }), None),
+                inverted_arc_asset: asset_manager.create_asset(AssetDescription::Arc(ArcAsset {
+                    mode: ArcMode::InvertedFilledArc,
+                }), None),
+            }))
+        }
+        "image" => {
+            let image_name = try!(read_string(try!(field(item, "image"))));
+            let image_path = image_directory.join(image_name);
+            let mut data = vec![];
+            if let Err(error) = File::open(&image_path).and_then(|mut file| {
+                file.read_to_end(&mut data)
+            }) {
+                return invalid(format!("Couldn't read the image \"{}\": {}",
+                                       image_path.display(),
+                                       error))
+            }
+
+            // Images are identified by their paths.
+            let mut hasher = DefaultHasher::new();
+            image_path.hash(&mut hasher);
+            let image_key = ImageKey(hasher.finish());
+
+            let source = try!(read_numbers(try!(field(item, "source")), 4));
+            DisplayItem::Image(Box::new(ImageDisplayItem {
+                base: base,
+                image_key: image_key,
+                source_rect: Rect::new(Point2D::new(source[0] as u32, source[1] as u32),
+                                       Size2D::new(source[2] as u32, source[3] as u32)),
+                image_asset: asset_manager.create_asset(AssetDescription::Image(ImageAsset {
+                    key: image_key,
+                    name: image_name.to_string(),
+                    data: Arc::new(data),
+                }), None),
+            }))
+        }
+        "linear-gradient" => {
+            DisplayItem::LinearGradient(Box::new(LinearGradientDisplayItem {
+                base: base,
+                angle: try!(read_number(try!(field(item, "angle")))) as f32,
+                stops: try!(read_gradient_stops(try!(field(item, "stops")))),
+            }))
+        }
+        "radial-gradient" => {
+            let center = try!(read_numbers(try!(field(item, "center")), 2));
+            let radii = try!(read_numbers(try!(field(item, "radii")), 2));
+            let extend_mode = match item.find("extend") {
+                None => ExtendMode::Clamp,
+                Some(extend_mode) => {
+                    match try!(read_string(extend_mode)) {
+                        "clamp" => ExtendMode::Clamp,
+                        "repeat" => ExtendMode::Repeat,
+                        extend_mode => {
+                            return invalid(format!("Unknown extend mode \"{}\"!", extend_mode))
+                        }
+                    }
+                }
+            };
+            DisplayItem::RadialGradient(Box::new(RadialGradientDisplayItem {
+                base: base,
+                center: Point2D::new(Au::from_f64_px(center[0]), Au::from_f64_px(center[1])),
+                radii: Size2D::new(Au::from_f64_px(radii[0]), Au::from_f64_px(radii[1])),
+                stops: try!(read_gradient_stops(try!(field(item, "stops")))),
+                extend_mode: extend_mode,
+            }))
+        }
+        item_type => return invalid(format!("Unknown display item type \"{}\"!", item_type)),
+    };
+    Ok(display_item)
+}
+
+fn write_item(item: &DisplayItem) -> Json {
+    let mut object = BTreeMap::new();
+    object.insert("id".to_string(), Json::U64(item.id().0));
+    object.insert("bounds".to_string(), write_rect(&item.base().bounds));
+    object.insert("clip".to_string(), write_rect(&item.base().clip.main));
+    if !item.base().clip.complex.is_empty() {
+        let complex_clips = item.base().clip.complex.iter().map(write_complex_clip).collect();
+        object.insert("complex-clips".to_string(), Json::Array(complex_clips));
+    }
+    match *item {
+        DisplayItem::SolidColor(ref solid_color_display_item) => {
+            object.insert("type".to_string(), Json::String("solid-color".to_string()));
+            object.insert("color".to_string(), write_color(&solid_color_display_item.color));
+        }
+        DisplayItem::Text(ref text_display_item) => {
+            object.insert("type".to_string(), Json::String("text".to_string()));
+            object.insert("color".to_string(), write_color(&text_display_item.color));
+            let glyph_asset = text_display_item.glyph_asset.borrow();
+            let glyph = match *glyph_asset.description() {
+                AssetDescription::Glyph(ref glyph) => glyph,
+                _ => panic!("A text item's glyph asset isn't a glyph!"),
+            };
+            object.insert("font".to_string(), Json::String(glyph.font_name.clone()));
+            object.insert("character".to_string(), Json::String(glyph.character.to_string()));
+            if glyph.mode == DistanceFieldMode::Multichannel {
+                object.insert("multichannel".to_string(), Json::Boolean(true));
+            }
+            if let Some(ref blurred_glyph_asset) = text_display_item.blurred_glyph_asset {
+                object.insert("blur".to_string(), Json::F64(blur_sigma(blurred_glyph_asset)));
+            }
+        }
+        DisplayItem::Border(ref border_display_item) => {
+            object.insert("type".to_string(), Json::String("border".to_string()));
+            object.insert("color".to_string(), write_color(&border_display_item.color));
+            object.insert("width".to_string(),
+                          Json::F64(border_display_item.width.to_f64_px()));
+            object.insert("radius".to_string(),
+                          Json::F64(border_display_item.radius.to_f64_px()));
+        }
+        DisplayItem::Image(ref image_display_item) => {
+            object.insert("type".to_string(), Json::String("image".to_string()));
+            let image_asset = image_display_item.image_asset.borrow();
+            let image = match *image_asset.description() {
+                AssetDescription::Image(ref image) => image,
+                _ => panic!("An image item's image asset isn't an image!"),
+            };
+            object.insert("image".to_string(), Json::String(image.name.clone()));
+            let source_rect = &image_display_item.source_rect;
+            object.insert("source".to_string(), Json::Array(vec![
+                Json::U64(source_rect.origin.x as u64),
+                Json::U64(source_rect.origin.y as u64),
+                Json::U64(source_rect.size.width as u64),
+                Json::U64(source_rect.size.height as u64),
+            ]));
+        }
+        DisplayItem::LinearGradient(ref linear_gradient_display_item) => {
+            object.insert("type".to_string(), Json::String("linear-gradient".to_string()));
+            object.insert("angle".to_string(),
+                          Json::F64(linear_gradient_display_item.angle as f64));
+            let stops = &linear_gradient_display_item.stops;
+            object.insert("stops".to_string(),
+                          Json::Array(stops.iter().map(write_gradient_stop).collect()));
+        }
+        DisplayItem::RadialGradient(ref radial_gradient_display_item) => {
+            let item = &**radial_gradient_display_item;
+            object.insert("type".to_string(), Json::String("radial-gradient".to_string()));
+            object.insert("center".to_string(), Json::Array(vec![
+                Json::F64(item.center.x.to_f64_px()),
+                Json::F64(item.center.y.to_f64_px()),
+            ]));
+            object.insert("radii".to_string(), Json::Array(vec![
+                Json::F64(item.radii.width.to_f64_px()),
+                Json::F64(item.radii.height.to_f64_px()),
+            ]));
+            object.insert("stops".to_string(),
+                          Json::Array(item.stops.iter().map(write_gradient_stop).collect()));
+            if item.extend_mode == ExtendMode::Repeat {
+                object.insert("extend".to_string(), Json::String("repeat".to_string()));
+            }
+        }
+    }
+    Json::Object(object)
+}
+
+fn blur_sigma(blurred_glyph_asset: &Rc<RefCell<Asset>>) -> f64 {
+    match *blurred_glyph_asset.borrow().description() {
+        AssetDescription::BlurredGlyph(ref blurred_glyph) => blurred_glyph.sigma as f64,
+        _ => panic!("A text item's blurred glyph asset isn't a blurred glyph!"),
+    }
+}
+
+fn invalid<T>(message: String) -> Result<T, SceneError> {
+    Err(SceneError::Invalid(message))
+}
+
+fn field<'a>(item: &'a Json, key: &str) -> Result<&'a Json, SceneError> {
+    match item.find(key) {
+        Some(value) => Ok(value),
+        None => invalid(format!("A display item is missing \"{}\"!", key)),
+    }
+}
+
+fn read_number(json: &Json) -> Result<f64, SceneError> {
+    match json.as_f64() {
+        Some(number) => Ok(number),
+        None => invalid(format!("Expected a number, found {}!", json)),
+    }
+}
+
+fn read_string(json: &Json) -> Result<&str, SceneError> {
+    match json.as_string() {
+        Some(string) => Ok(string),
+        None => invalid(format!("Expected a string, found {}!", json)),
+    }
+}
+
+fn read_numbers(json: &Json, count: usize) -> Result<Vec<f64>, SceneError> {
+    match json.as_array() {
+        Some(array) if array.len() == count => array.iter().map(read_number).collect(),
+        _ => invalid(format!("Expected an array of {} numbers, found {}!", count, json)),
+    }
+}
+
+fn read_rect(json: &Json) -> Result<Rect<Au>, SceneError> {
+    let values = try!(read_numbers(json, 4));
+    Ok(Rect::new(Point2D::new(Au::from_f64_px(values[0]), Au::from_f64_px(values[1])),
+                 Size2D::new(Au::from_f64_px(values[2]), Au::from_f64_px(values[3]))))
+}
+
+fn read_complex_clips(json: &Json) -> Result<Vec<ComplexClippingRegion>, SceneError> {
+    match json.as_array() {
+        Some(complex_clips) => complex_clips.iter().map(read_complex_clip).collect(),
+        None => invalid(format!("Expected an array of complex clips, found {}!", json)),
+    }
+}
+
+fn read_complex_clip(json: &Json) -> Result<ComplexClippingRegion, SceneError> {
+    let radii = try!(read_numbers(try!(field(json, "radii")), 4));
+    Ok(ComplexClippingRegion {
+        rect: try!(read_rect(try!(field(json, "rect")))),
+        radii: BorderRadii {
+            top_left: Au::from_f64_px(radii[0]),
+            top_right: Au::from_f64_px(radii[1]),
+            bottom_right: Au::from_f64_px(radii[2]),
+            bottom_left: Au::from_f64_px(radii[3]),
+        },
+    })
+}
+
+fn read_gradient_stops(json: &Json) -> Result<Vec<GradientStop>, SceneError> {
+    match json.as_array() {
+        Some(stops) if !stops.is_empty() => stops.iter().map(read_gradient_stop).collect(),
+        _ => invalid(format!("Expected a nonempty array of gradient stops, found {}!", json)),
+    }
+}
+
+fn read_gradient_stop(json: &Json) -> Result<GradientStop, SceneError> {
+    Ok(GradientStop {
+        offset: try!(read_number(try!(field(json, "offset")))) as f32,
+        color: try!(read_color(try!(field(json, "color")))),
+    })
+}
+
+fn read_color(json: &Json) -> Result<Color, SceneError> {
+    let values = try!(read_numbers(json, 4));
+    if values.iter().any(|&value| value < 0.0 || value > 255.0) {
+        return invalid(format!("Expected color channels from 0 to 255, found {}!", json))
+    }
+    Ok(Color::new(values[0] as u8, values[1] as u8, values[2] as u8, values[3] as u8))
+}
+
+fn write_rect(rect: &Rect<Au>) -> Json {
+    Json::Array(vec![
+        Json::F64(rect.origin.x.to_f64_px()),
+        Json::F64(rect.origin.y.to_f64_px()),
+        Json::F64(rect.size.width.to_f64_px()),
+        Json::F64(rect.size.height.to_f64_px()),
+    ])
+}
+
+fn write_complex_clip(complex_clip: &ComplexClippingRegion) -> Json {
+    let radii = &complex_clip.radii;
+    let mut object = BTreeMap::new();
+    object.insert("rect".to_string(), write_rect(&complex_clip.rect));
+    object.insert("radii".to_string(), Json::Array(vec![
+        Json::F64(radii.top_left.to_f64_px()),
+        Json::F64(radii.top_right.to_f64_px()),
+        Json::F64(radii.bottom_right.to_f64_px()),
+        Json::F64(radii.bottom_left.to_f64_px()),
+    ]));
+    Json::Object(object)
+}
+
+fn write_gradient_stop(stop: &GradientStop) -> Json {
+    let mut object = BTreeMap::new();
+    object.insert("offset".to_string(), Json::F64(stop.offset as f64));
+    object.insert("color".to_string(), write_color(&stop.color));
+    Json::Object(object)
+}
+
+fn write_color(color: &Color) -> Json {
+    Json::Array(vec![
+        Json::U64(color.r as u64),
+        Json::U64(color.g as u64),
+        Json::U64(color.b as u64),
+        Json::U64(color.a as u64),
+    ])
+}
@@ -0,0 +1,500 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A reference rasterizer that draws batches on the CPU.
+//!
+//! This follows the GL state that `DrawContext` sets up and the fragment shader in `draw.rs` as
+//! closely as is practical, sampling the in-memory copies of the atlas pages. It exists to give
+//! regression tests a ground truth on machines without a GPU, and to catch shader and batching
+//! bugs by comparison with GPU output. It makes no attempt to be fast.
+
+use assets::AssetManager;
+use atlas::{Atlas, PixelFormat};
+use batch::{self, Batch, ClipMode, RetainedBatches, TEXTURE_MODE_COVERAGE};
+use batch::{TEXTURE_MODE_DISTANCE_FIELD, TEXTURE_MODE_IMAGE, TEXTURE_MODE_MULTICHANNEL};
+use context::Context;
+use debug::DebugDump;
+use display_list::DisplayList;
+use draw::{CLEAR_DEPTH, CLEAR_STENCIL};
+use job_server::JobServer;
+use renderer::Renderer;
+
+use euclid::{Point2D, Point3D, Rect, Size2D};
+use num_cpus;
+use std::cell::RefCell;
+use std::f32;
+use std::rc::Rc;
+
+/// The reference value and mask of the stencil test, as set in `DrawContext::init_gl_state()`.
+const STENCIL_REFERENCE: u8 = 1;
+const STENCIL_MASK: u8 = 1;
+
+pub struct SoftwareRasterizer {
+    size: Size2D<i32>,
+    /// Premultiplied RGBA pixels, top row first.
+    pixels: Vec<u8>,
+    stencil: Vec<u8>,
+    /// The rectangle that drawing and clearing are restricted to, in pixels from the top left, as
+    /// set with `DrawContext::set_scissor_rect()`.
+    scissor_rect: Option<Rect<i32>>,
+}
+
+/// A vertex after the viewport transform, with its attributes.
+#[derive(Copy, Clone)]
+struct WindowVertex {
+    /// The position in window coordinates: pixels from the bottom left, and depth from 0 to 1.
+    position: Point3D<f32>,
+    color: [f32; 4],
+    buffer_gamma: Point2D<f32>,
+    texture_coord: Point2D<f32>,
+    texture_mode: f32,
+    clip_coord: Point2D<f32>,
+    clip_rect: [f32; 4],
+    clip_radii: [f32; 4],
+}
+
+/// An atlas page to sample from.
+struct Texture<'a> {
+    pixels: &'a [u8],
+    format: PixelFormat,
+    size: Size2D<u32>,
+}
+
+impl SoftwareRasterizer {
+    pub fn new(size: &Size2D<i32>) -> SoftwareRasterizer {
+        let pixel_count = (size.width * size.height) as usize;
+        SoftwareRasterizer {
+            size: *size,
+            pixels: vec![0; pixel_count * 4],
+            stencil: vec![CLEAR_STENCIL; pixel_count],
+            scissor_rect: None,
+        }
+    }
+
+    /// Clears color and stencil inside the scissor rectangle, as `DrawContext::clear()` does.
+    /// Depth is never written, so it needs no storage.
+    pub fn clear(&mut self) {
+        for row in 0..self.size.height {
+            for x in 0..self.size.width {
+                if !self.scissor_test(x, row) {
+                    continue
+                }
+                let index = (row * self.size.width + x) as usize;
+                for channel in 0..4 {
+                    self.pixels[index * 4 + channel] = 0
+                }
+                self.stencil[index] = CLEAR_STENCIL
+            }
+        }
+    }
+
+    pub fn set_scissor_rect(&mut self, rect: Option<&Rect<i32>>) {
+        self.scissor_rect = rect.cloned()
+    }
+
+    /// Clears and redraws only the damaged rectangles, as `DrawContext::draw_damaged_rects()`
+    /// does.
+    pub fn draw_damaged_rects(&mut self,
+                              batches: &[Batch],
+                              damaged_rects: &[Rect<i32>],
+                              atlas: &Atlas) {
+        let batch_bounds: Vec<_> = batches.iter().map(|batch| batch.device_bounds()).collect();
+        for damaged_rect in damaged_rects.iter() {
+            self.set_scissor_rect(Some(damaged_rect));
+            self.clear();
+            for (batch, bounds) in batches.iter().zip(batch_bounds.iter()) {
+                if bounds.map_or(false, |bounds| bounds.intersects(damaged_rect)) {
+                    self.draw_batch(batch, atlas)
+                }
+            }
+        }
+        self.set_scissor_rect(None)
+    }
+
+    pub fn draw_batch(&mut self, batch: &Batch, atlas: &Atlas) {
+        // Batches without textured items can use any page.
+        let page = batch.page.unwrap_or(0);
+        let texture = Texture {
+            pixels: atlas.page_pixels(page),
+            format: atlas.page_format(page),
+            size: atlas.page_size(page),
+        };
+
+        for triangle in batch.elements.chunks(3) {
+            let vertices = [
+                self.transform_vertex(batch, triangle[0] as usize),
+                self.transform_vertex(batch, triangle[1] as usize),
+                self.transform_vertex(batch, triangle[2] as usize),
+            ];
+            self.draw_triangle(&vertices, &texture)
+        }
+    }
+
+    /// Returns the rendered image as RGBA pixels, top row first.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels[..]
+    }
+
+    /// Returns true if the pixel in the given column and row, counting rows from the top, is
+    /// inside the scissor rectangle.
+    fn scissor_test(&self, x: i32, row: i32) -> bool {
+        match self.scissor_rect {
+            None => true,
+            Some(ref rect) => {
+                x >= rect.origin.x && x < rect.max_x() && row >= rect.origin.y && row < rect.max_y()
+            }
+        }
+    }
+
+    fn transform_vertex(&self, batch: &Batch, index: usize) -> WindowVertex {
+        let position = &batch.vertices[index];
+        let color = &batch.colors[index];
+        WindowVertex {
+            position: Point3D::new((position.x + 1.0) * 0.5 * (self.size.width as f32),
+                                   (position.y + 1.0) * 0.5 * (self.size.height as f32),
+                                   (position.z + 1.0) * 0.5),
+            color: [
+                (color.r as f32) / 255.0,
+                (color.g as f32) / 255.0,
+                (color.b as f32) / 255.0,
+                (color.a as f32) / 255.0,
+            ],
+            buffer_gamma: batch.buffer_gamma[index],
+            texture_coord: batch.texture_coords[index],
+            texture_mode: batch.texture_modes[index],
+            clip_coord: batch.clip_coords[index],
+            clip_rect: batch.clip_rects[index],
+            clip_radii: batch.clip_radii[index],
+        }
+    }
+
+    fn draw_triangle(&mut self, vertices: &[WindowVertex; 3], texture: &Texture) {
+        let area = edge_function(&vertices[0].position,
+                                 &vertices[1].position,
+                                 &vertices[2].position);
+        if area == 0.0 {
+            return
+        }
+
+        // As in GL, triangles that wind counterclockwise in window coordinates face the front.
+        // Put the vertices of back-facing ones in counterclockwise order too, so that the same
+        // edge tests work for both.
+        let front_facing = area > 0.0;
+        let (v0, v1, v2) = if front_facing {
+            (&vertices[0], &vertices[1], &vertices[2])
+        } else {
+            (&vertices[0], &vertices[2], &vertices[1])
+        };
+        let area = f32::abs(area);
+
+        let min_x = f32::min(v0.position.x, f32::min(v1.position.x, v2.position.x));
+        let min_y = f32::min(v0.position.y, f32::min(v1.position.y, v2.position.y));
+        let max_x = f32::max(v0.position.x, f32::max(v1.position.x, v2.position.x));
+        let max_y = f32::max(v0.position.y, f32::max(v1.position.y, v2.position.y));
+        let (min_x, min_y) = (clamp(f32::floor(min_x) as i32, 0, self.size.width),
+                              clamp(f32::floor(min_y) as i32, 0, self.size.height));
+        let (max_x, max_y) = (clamp(f32::ceil(max_x) as i32, 0, self.size.width),
+                              clamp(f32::ceil(max_y) as i32, 0, self.size.height));
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let point = Point3D::new((x as f32) + 0.5, (y as f32) + 0.5, 0.0);
+                let w0 = edge_function(&v1.position, &v2.position, &point);
+                let w1 = edge_function(&v2.position, &v0.position, &point);
+                let w2 = edge_function(&v0.position, &v1.position, &point);
+                if !covers(w0, &v1.position, &v2.position) ||
+                        !covers(w1, &v2.position, &v0.position) ||
+                        !covers(w2, &v0.position, &v1.position) {
+                    continue
+                }
+
+                let weights = [ w0 / area, w1 / area, w2 / area ];
+                let fragment = interpolate(&[ v0, v1, v2 ], &weights);
+                self.process_fragment(x, y, &fragment, front_facing, texture)
+            }
+        }
+    }
+
+    /// Runs the scissor, stencil, and depth tests for a fragment and then shades and blends it,
+    /// with the state set in `DrawContext::init_gl_state()`.
+    fn process_fragment(&mut self,
+                        x: i32,
+                        y: i32,
+                        fragment: &WindowVertex,
+                        front_facing: bool,
+                        texture: &Texture) {
+        let row = self.size.height - 1 - y;
+        if !self.scissor_test(x, row) {
+            return
+        }
+        let index = (row * self.size.width + x) as usize;
+
+        // Front faces pass the stencil test where the stencil is set; back faces always pass.
+        let stencil_value = self.stencil[index];
+        if front_facing && (STENCIL_REFERENCE & STENCIL_MASK) != (stencil_value & STENCIL_MASK) {
+            return
+        }
+
+        // Back faces zero the stencil where they fail the depth test and set it where they pass.
+        if fragment.position.z >= CLEAR_DEPTH as f32 {
+            if !front_facing {
+                self.stencil[index] = stencil_value & !STENCIL_MASK
+            }
+            return
+        }
+        if !front_facing {
+            self.stencil[index] = (stencil_value & !STENCIL_MASK) |
+                (STENCIL_REFERENCE & STENCIL_MASK)
+        }
+
+        let color = shade_fragment(fragment, texture);
+        for channel in 0..4 {
+            let destination = (self.pixels[index * 4 + channel] as f32) / 255.0;
+            let result = color[channel] + destination * (1.0 - color[3]);
+            self.pixels[index * 4 + channel] = f32::round(clamp_unit(result) * 255.0) as u8
+        }
+    }
+}
+
+/// Renders display lists entirely on the CPU.
+pub struct SoftwareRenderer {
+    /// The context that assets are created in and display lists are batched with.
+    pub context: Context,
+    rasterizer: SoftwareRasterizer,
+    /// The batches of the display list last given to `render_update()`, if the image still shows
+    /// it.
+    retained_batches: Option<RetainedBatches>,
+}
+
+impl SoftwareRenderer {
+    pub fn new(size: &Size2D<i32>) -> SoftwareRenderer {
+        let atlas = Rc::new(RefCell::new(Atlas::new_in_memory()));
+        let job_server = Rc::new(RefCell::new(JobServer::new(num_cpus::get() as u32)));
+        SoftwareRenderer {
+            context: Context {
+                asset_manager: AssetManager::new(job_server, atlas),
+                render_target_size: *size,
+                device_pixel_ratio: 1.0,
+                clip_mode: ClipMode::Geometry,
+                debug_dump: DebugDump::disabled(),
+            },
+            rasterizer: SoftwareRasterizer::new(size),
+            retained_batches: None,
+        }
+    }
+}
+
+impl Renderer for SoftwareRenderer {
+    fn context(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
+    fn render(&mut self, display_list: &mut DisplayList) -> Vec<u8> {
+        self.retained_batches = None;
+        let batches = batch::batch_display_list(&mut self.context, display_list);
+
+        let atlas = self.context.asset_manager.atlas.borrow();
+        self.rasterizer.clear();
+        for batch in batches.iter() {
+            self.rasterizer.draw_batch(batch, &*atlas)
+        }
+        self.rasterizer.pixels().to_vec()
+    }
+
+    fn render_update(&mut self, display_list: DisplayList) -> Vec<u8> {
+        let damaged_rects = batch::update_retained_batches(&mut self.context,
+                                                           &mut self.retained_batches,
+                                                           display_list);
+        let atlas = self.context.asset_manager.atlas.borrow();
+        self.rasterizer.draw_damaged_rects(self.retained_batches.as_ref().unwrap().batches(),
+                                           &damaged_rects,
+                                           &*atlas);
+        self.rasterizer.pixels().to_vec()
+    }
+
+    fn forget_retained_display_list(&mut self) {
+        self.retained_batches = None
+    }
+}
+
+/// The fragment shader in `draw.rs`.
+fn shade_fragment(fragment: &WindowVertex, texture: &Texture) -> [f32; 4] {
+    let texture_color = texture.sample(&fragment.texture_coord);
+
+    let mut coverage = 1.0;
+    if fragment.texture_mode > (TEXTURE_MODE_IMAGE - 0.5) {
+        // Images are drawn in their own colors below.
+    } else if fragment.texture_mode > (TEXTURE_MODE_COVERAGE - 0.5) {
+        coverage = texture_color[3]
+    } else if fragment.texture_mode > (TEXTURE_MODE_DISTANCE_FIELD - 0.5) {
+        let mut distance = texture_color[3];
+        if fragment.texture_mode > (TEXTURE_MODE_MULTICHANNEL - 0.5) {
+            distance = median(texture_color[0], texture_color[1], texture_color[2])
+        }
+        coverage = smoothstep(fragment.buffer_gamma.x - fragment.buffer_gamma.y,
+                              fragment.buffer_gamma.x + fragment.buffer_gamma.y,
+                              distance)
+    }
+
+    coverage *= rounded_clip_coverage(&fragment.clip_coord,
+                                      &fragment.clip_rect,
+                                      &fragment.clip_radii);
+
+    let alpha = fragment.color[3] * coverage;
+    if fragment.texture_mode > (TEXTURE_MODE_IMAGE - 0.5) {
+        // Images are premultiplied already.
+        return [
+            texture_color[0] * alpha,
+            texture_color[1] * alpha,
+            texture_color[2] * alpha,
+            texture_color[3] * alpha,
+        ]
+    }
+    [
+        fragment.color[0] * alpha,
+        fragment.color[1] * alpha,
+        fragment.color[2] * alpha,
+        alpha,
+    ]
+}
+
+impl<'a> Texture<'a> {
+    /// Samples the texture with bilinear filtering and edge clamping, like the atlas textures.
+    fn sample(&self, texture_coord: &Point2D<f32>) -> [f32; 4] {
+        let x = texture_coord.x * (self.size.width as f32) - 0.5;
+        let y = texture_coord.y * (self.size.height as f32) - 0.5;
+        let (x0, y0) = (f32::floor(x), f32::floor(y));
+        let (fraction_x, fraction_y) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let top_left = self.texel(x0, y0);
+        let top_right = self.texel(x0 + 1, y0);
+        let bottom_left = self.texel(x0, y0 + 1);
+        let bottom_right = self.texel(x0 + 1, y0 + 1);
+        let mut result = [0.0; 4];
+        for channel in 0..4 {
+            let top = lerp(top_left[channel], top_right[channel], fraction_x);
+            let bottom = lerp(bottom_left[channel], bottom_right[channel], fraction_x);
+            result[channel] = lerp(top, bottom, fraction_y)
+        }
+        result
+    }
+
+    fn texel(&self, x: i32, y: i32) -> [f32; 4] {
+        let (width, height) = (self.size.width as i32, self.size.height as i32);
+        let (x, y) = (clamp(x, 0, width - 1), clamp(y, 0, height - 1));
+        let index = (y * width + x) as usize;
+        match self.format {
+            PixelFormat::Alpha => [ 0.0, 0.0, 0.0, (self.pixels[index] as f32) / 255.0 ],
+            PixelFormat::Rgba => {
+                [
+                    (self.pixels[index * 4 + 0] as f32) / 255.0,
+                    (self.pixels[index * 4 + 1] as f32) / 255.0,
+                    (self.pixels[index * 4 + 2] as f32) / 255.0,
+                    (self.pixels[index * 4 + 3] as f32) / 255.0,
+                ]
+            }
+        }
+    }
+}
+
+/// `roundedClipCoverage()` in the fragment shader.
+fn rounded_clip_coverage(position: &Point2D<f32>, rect: &[f32; 4], radii: &[f32; 4]) -> f32 {
+    let (radius, center) = if radii[0] > 0.0 && position.x < rect[0] + radii[0] &&
+            position.y < rect[1] + radii[0] {
+        (radii[0], Point2D::new(rect[0] + radii[0], rect[1] + radii[0]))
+    } else if radii[1] > 0.0 && position.x > rect[2] - radii[1] &&
+            position.y < rect[1] + radii[1] {
+        (radii[1], Point2D::new(rect[2] - radii[1], rect[1] + radii[1]))
+    } else if radii[2] > 0.0 && position.x > rect[2] - radii[2] &&
+            position.y > rect[3] - radii[2] {
+        (radii[2], Point2D::new(rect[2] - radii[2], rect[3] - radii[2]))
+    } else if radii[3] > 0.0 && position.x < rect[0] + radii[3] &&
+            position.y > rect[3] - radii[3] {
+        (radii[3], Point2D::new(rect[0] + radii[3], rect[3] - radii[3]))
+    } else {
+        return 1.0
+    };
+    let distance = f32::sqrt((position.x - center.x) * (position.x - center.x) +
+                             (position.y - center.y) * (position.y - center.y));
+    clamp_unit(radius - distance + 0.5)
+}
+
+/// Twice the signed area of the triangle (a, b, point); positive if it winds counterclockwise.
+fn edge_function(a: &Point3D<f32>, b: &Point3D<f32>, point: &Point3D<f32>) -> f32 {
+    (b.x - a.x) * (point.y - a.y) - (b.y - a.y) * (point.x - a.x)
+}
+
+/// Returns true if a point with the given edge function value for the counterclockwise edge
+/// from `a` to `b` is inside. Points exactly on an edge belong to it only if it's a top or left
+/// edge, so that triangles sharing an edge don't both draw the pixels on it.
+fn covers(edge_value: f32, a: &Point3D<f32>, b: &Point3D<f32>) -> bool {
+    if edge_value != 0.0 {
+        return edge_value > 0.0
+    }
+    let top_edge = a.y == b.y && b.x < a.x;
+    let left_edge = b.y < a.y;
+    top_edge || left_edge
+}
+
+fn interpolate(vertices: &[&WindowVertex; 3], weights: &[f32; 3]) -> WindowVertex {
+    let mut result = WindowVertex {
+        position: Point3D::new(0.0, 0.0, 0.0),
+        color: [0.0; 4],
+        buffer_gamma: Point2D::new(0.0, 0.0),
+        texture_coord: Point2D::new(0.0, 0.0),
+        texture_mode: 0.0,
+        clip_coord: Point2D::new(0.0, 0.0),
+        clip_rect: [0.0; 4],
+        clip_radii: [0.0; 4],
+    };
+    for (vertex, &weight) in vertices.iter().zip(weights.iter()) {
+        result.position.z += vertex.position.z * weight;
+        for channel in 0..4 {
+            result.color[channel] += vertex.color[channel] * weight
+        }
+        result.buffer_gamma.x += vertex.buffer_gamma.x * weight;
+        result.buffer_gamma.y += vertex.buffer_gamma.y * weight;
+        result.texture_coord.x += vertex.texture_coord.x * weight;
+        result.texture_coord.y += vertex.texture_coord.y * weight;
+        result.texture_mode += vertex.texture_mode * weight;
+        result.clip_coord.x += vertex.clip_coord.x * weight;
+        result.clip_coord.y += vertex.clip_coord.y * weight;
+        for index in 0..4 {
+            result.clip_rect[index] += vertex.clip_rect[index] * weight;
+            result.clip_radii[index] += vertex.clip_radii[index] * weight
+        }
+    }
+    result
+}
+
+fn median(a: f32, b: f32, c: f32) -> f32 {
+    f32::max(f32::min(a, b), f32::min(f32::max(a, b), c))
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x < edge0 { 0.0 } else { 1.0 }
+    }
+    let t = clamp_unit((x - edge0) / (edge1 - edge0));
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn clamp_unit(value: f32) -> f32 {
+    f32::max(0.0, f32::min(value, 1.0))
+}
+
+fn clamp(value: i32, min: i32, max: i32) -> i32 {
+    if value < min {
+        min
+    } else if value > max {
+        max
+    } else {
+        value
+    }
+}